@@ -1,18 +1,36 @@
 use std::env;
 use std::fs;
-use std::path::PathBuf;
-use std::process::Command;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
 
 fn main() {
     println!("cargo:rerun-if-changed=build.rs");
     println!("cargo:rerun-if-changed=ripgrep-src");
+    println!("cargo:rerun-if-env-changed=SUP_RIPGREP_SRC");
 
     let out_dir = env::var("OUT_DIR").unwrap();
     let target = env::var("TARGET").unwrap();
     let host = env::var("HOST").unwrap();
 
+    // Check the source is there before anything else, rather than failing
+    // partway through the build
+    let ripgrep_dir = ripgrep_source_dir();
+
+    // Recorded so verify_platform can check the binary against the host
+    println!("cargo:rustc-env=SUP_RG_TARGET={}", target);
+    // Recorded so get_ripgrep_commit can pin the exact source built
+    println!(
+        "cargo:rustc-env=SUP_RG_COMMIT={}",
+        ripgrep_commit(&ripgrep_dir)
+    );
+    // Recorded so ripgrep_version reports the version actually built
+    println!(
+        "cargo:rustc-env=RIPGREP_VERSION={}",
+        ripgrep_version(&ripgrep_dir)
+    );
+
     // Build ripgrep from submodule
-    let binary_path = build_ripgrep_from_submodule(&out_dir, &target, &host);
+    let binary_path = build_ripgrep_from_submodule(&ripgrep_dir, &out_dir, &target, &host);
 
     // Copy binary to the sup package directory for distribution
     let binary_name = if target.contains("windows") {
@@ -31,19 +49,95 @@ fn main() {
     println!("Ripgrep binary copied to: {}", package_binary.display());
 }
 
-fn build_ripgrep_from_submodule(out_dir: &str, target: &str, host: &str) -> PathBuf {
-    // Use the submodule
-    let ripgrep_dir = PathBuf::from("ripgrep-src");
+/// The ripgrep source to build the binary from: `SUP_RIPGREP_SRC` if set,
+/// otherwise the submodule. Panics with what to do if it isn't a checkout.
+fn ripgrep_source_dir() -> PathBuf {
+    let (ripgrep_dir, from_env) = match env::var_os("SUP_RIPGREP_SRC") {
+        Some(dir) => (PathBuf::from(dir), true),
+        None => (PathBuf::from("ripgrep-src"), false),
+    };
+    if ripgrep_dir.join("Cargo.toml").is_file() {
+        return ripgrep_dir;
+    }
 
-    if !ripgrep_dir.exists() {
-        panic!("ripgrep-src submodule not found! Run: git submodule update --init --recursive");
+    if from_env {
+        panic!(
+            "SUP_RIPGREP_SRC={} is not a ripgrep checkout (no Cargo.toml)",
+            ripgrep_dir.display()
+        );
+    }
+    // An uninitialized submodule is an empty directory
+    if tool_available("git") {
+        panic!(
+            "ripgrep-src submodule not found! Run: git submodule update --init --recursive \
+             (or set SUP_RIPGREP_SRC to a ripgrep checkout)"
+        );
     }
+    panic!(
+        "ripgrep-src submodule not found and git not found; install git and run \
+         git submodule update --init --recursive, or set SUP_RIPGREP_SRC to a ripgrep checkout"
+    );
+}
 
+/// Whether `tool` can be run at all.
+fn tool_available(tool: &str) -> bool {
+    Command::new(tool)
+        .arg("--version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok()
+}
+
+/// The commit the ripgrep source is checked out at, or "unknown" if git
+/// can't tell (such as when building from a source archive).
+fn ripgrep_commit(ripgrep_dir: &Path) -> String {
+    Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .current_dir(ripgrep_dir)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .filter(|commit| !commit.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// The `version` of the `[package]` in the ripgrep source's Cargo.toml, or
+/// "unknown" if it has none.
+fn ripgrep_version(ripgrep_dir: &Path) -> String {
+    let manifest = fs::read_to_string(ripgrep_dir.join("Cargo.toml"))
+        .expect("Failed to read ripgrep's Cargo.toml");
+    let mut in_package = false;
+    for line in manifest.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_package = line == "[package]";
+        } else if in_package {
+            if let Some((key, value)) = line.split_once('=') {
+                if key.trim() == "version" {
+                    return value.trim().trim_matches('"').to_string();
+                }
+            }
+        }
+    }
+    "unknown".to_string()
+}
+
+/// Smallest plausible size of a ripgrep binary; real ones are megabytes.
+const MIN_BINARY_SIZE: u64 = 64 * 1024;
+
+fn build_ripgrep_from_submodule(
+    ripgrep_dir: &Path,
+    out_dir: &str,
+    target: &str,
+    host: &str,
+) -> PathBuf {
     println!("Building ripgrep from submodule for target: {}", target);
 
-    // Build ripgrep
-    let mut cargo_cmd = Command::new("cargo");
-    cargo_cmd.current_dir(&ripgrep_dir);
+    // Build ripgrep with the same cargo that's running this script
+    let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let mut cargo_cmd = Command::new(&cargo);
+    cargo_cmd.current_dir(ripgrep_dir);
     cargo_cmd.args(&["build", "--release", "--bin", "rg"]);
 
     // Cross-compile if target != host
@@ -51,7 +145,12 @@ fn build_ripgrep_from_submodule(out_dir: &str, target: &str, host: &str) -> Path
         cargo_cmd.args(&["--target", target]);
     }
 
-    let status = cargo_cmd.status().expect("Failed to build ripgrep");
+    let status = cargo_cmd.status().unwrap_or_else(|e| {
+        panic!(
+            "Failed to run {} to build ripgrep: {}; install a Rust toolchain or set CARGO",
+            cargo, e
+        )
+    });
     if !status.success() {
         panic!("Failed to build ripgrep from source");
     }
@@ -73,6 +172,19 @@ fn build_ripgrep_from_submodule(out_dir: &str, target: &str, host: &str) -> Path
         ripgrep_dir.join("target").join("release").join(binary_name)
     };
 
+    // A broken toolchain can leave an empty or truncated file behind, which
+    // would only fail once a user tries to run it
+    let size = fs::metadata(&built_binary)
+        .map(|metadata| metadata.len())
+        .unwrap_or(0);
+    if size < MIN_BINARY_SIZE {
+        panic!(
+            "ripgrep binary at {} is only {} bytes; the ripgrep build didn't produce a valid binary",
+            built_binary.display(),
+            size
+        );
+    }
+
     let dest_binary = PathBuf::from(out_dir).join(binary_name);
 
     fs::copy(&built_binary, &dest_binary).expect("Failed to copy ripgrep binary");
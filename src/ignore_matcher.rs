@@ -0,0 +1,92 @@
+use ignore::gitignore::Gitignore;
+use ignore::{Match, WalkBuilder};
+use pyo3::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::PatternError;
+
+/// The `.gitignore` rules under a directory, read and compiled once so that
+/// many searches over the same tree can share them instead of each walk
+/// reading every ignore file again. Pass it to `RipGrep.search` as
+/// `ignore`. Unlike a plain walk, the rules apply whether or not `root` is
+/// inside a git repository. A `.gitignore` that can't be read or parsed
+/// raises `PatternError`.
+#[pyclass]
+pub(crate) struct IgnoreMatcher {
+    #[pyo3(get)]
+    root: PathBuf,
+    pub(crate) rules: Arc<IgnoreRules>,
+}
+
+/// One compiled matcher per `.gitignore` file, shallowest first.
+pub(crate) struct IgnoreRules {
+    gitignores: Vec<Gitignore>,
+}
+
+#[pymethods]
+impl IgnoreMatcher {
+    #[new]
+    fn new(root: &str) -> PyResult<Self> {
+        let root = std::path::absolute(root).map_err(|e| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Invalid root: {}", e))
+        })?;
+
+        let mut gitignores = Vec::new();
+        // Ignored directories are skipped, so rules inside them never count
+        let walker = WalkBuilder::new(&root)
+            .hidden(false)
+            .filter_entry(|entry| entry.file_name() != ".git")
+            .build();
+        for entry in walker.filter_map(Result::ok) {
+            if entry.file_name() == ".gitignore" {
+                let (gitignore, error) = Gitignore::new(entry.path());
+                if let Some(e) = error {
+                    return Err(PatternError::new_err(format!(
+                        "Invalid .gitignore {}: {}",
+                        entry.path().display(),
+                        e
+                    )));
+                }
+                gitignores.push((entry.depth(), gitignore));
+            }
+        }
+        gitignores.sort_by_key(|(depth, _)| *depth);
+
+        Ok(IgnoreMatcher {
+            root,
+            rules: Arc::new(IgnoreRules {
+                gitignores: gitignores.into_iter().map(|(_, g)| g).collect(),
+            }),
+        })
+    }
+
+    /// Whether the rules ignore `path`, which need not exist.
+    #[pyo3(signature = (path, is_dir=false))]
+    fn is_ignored(&self, path: &str, is_dir: bool) -> PyResult<bool> {
+        let path = std::path::absolute(path).map_err(|e| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Invalid path: {}", e))
+        })?;
+        Ok(self.rules.is_ignored(&path, is_dir))
+    }
+}
+
+impl IgnoreRules {
+    /// Whether `path`, which must be absolute, is ignored, either itself or
+    /// by way of a directory it's in. Like git, the deepest `.gitignore`
+    /// with an opinion wins.
+    pub(crate) fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let mut ignored = false;
+        for gitignore in &self.gitignores {
+            if !path.starts_with(gitignore.path()) {
+                continue;
+            }
+            match gitignore.matched_path_or_any_parents(path, is_dir) {
+                Match::Ignore(_) => ignored = true,
+                Match::Whitelist(_) => ignored = false,
+                Match::None => {}
+            }
+        }
+        ignored
+    }
+}
@@ -1,128 +1,4611 @@
-use grep_regex::RegexMatcher;
-use grep_searcher::sinks::UTF8;
-use grep_searcher::Searcher;
-use ignore::WalkBuilder;
+mod ignore_matcher;
+mod limiter;
+mod options;
+
+use flate2::read::GzDecoder;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use grep_matcher::{LineTerminator, Matcher};
+use grep_regex::{RegexMatcher, RegexMatcherBuilder};
+use grep_searcher::{
+    BinaryDetection, Encoding, MmapChoice, Searcher, SearcherBuilder, Sink, SinkContext, SinkMatch,
+};
+use ignore::overrides::{Override, OverrideBuilder};
+use ignore::types::{Types, TypesBuilder};
+use ignore::{DirEntry, WalkBuilder, WalkState};
+use ignore_matcher::{IgnoreMatcher, IgnoreRules};
+use options::{
+    BinaryMode, LimitBehavior, Normalization, OnError, PatternPriority, ReadStrategy,
+    SearchOptions, SortBy,
+};
+use pyo3::buffer::PyBuffer;
+use pyo3::import_exception;
 use pyo3::prelude::*;
-use pyo3::types::PyList;
-use std::path::Path;
-use std::process::Command;
+use pyo3::types::{PyBytes, PyDict, PyList, PyTuple};
+use serde::Serialize;
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{RecvTimeoutError, TrySendError};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use unicode_normalization::{is_nfc, is_nfd, UnicodeNormalization};
+
+// Defined in Python so they can also derive from the built-in exceptions
+// raised before the hierarchy existed
+import_exception!(sup.errors, BinaryError);
+import_exception!(sup.errors, LimitExceeded);
+import_exception!(sup.errors, PathNotFoundError);
+import_exception!(sup.errors, PatternError);
+import_exception!(sup.errors, SearchIOError);
+import_exception!(sup.errors, TimeoutError);
+
+#[pyclass]
+#[derive(Clone)]
+struct RipGrep {
+    #[pyo3(get)]
+    pattern: String,
+    options: SearchOptions,
+    /// Compiled once and shared, so caches warmed by one search (or by
+    /// `warm`) carry over to the next.
+    matcher: Arc<RegexMatcher>,
+    /// The individual patterns of an instance built by `with_patterns`.
+    sub_patterns: Option<Arc<SubPatterns>>,
+    /// Capture-aware copy of the pattern, compiled when `named_output` or
+    /// `captures` is set.
+    named_regex: Option<Arc<regex::Regex>>,
+    /// Compiled `exclude_if_matches` patterns.
+    exclusions: Arc<Vec<RegexMatcher>>,
+    /// Compiled `context_pattern`.
+    heading_matcher: Option<Arc<RegexMatcher>>,
+    /// Compiled `record_separator`, shared by every search like `matcher`.
+    record_separator: Option<Arc<RegexMatcher>>,
+    /// Compiled `types` and `types_not`, shared like `matcher`.
+    types: Arc<Types>,
+    /// Compiled `include_globs` and `exclude_globs` for the root last
+    /// walked, as they match relative to it. Searches under the same root
+    /// reuse them.
+    overrides: Arc<Mutex<Option<(PathBuf, Override)>>>,
+    /// Built by `from_bytes_pattern`, so matched lines may not be UTF-8.
+    bytes_pattern: bool,
+    /// Precompiled gitignore rules used in place of the walker's own, set
+    /// for one search by passing an `IgnoreMatcher`.
+    ignore_rules: Option<Arc<IgnoreRules>>,
+}
+
+struct SubPatterns {
+    matchers: Vec<RegexMatcher>,
+    priority: PatternPriority,
+}
+
+impl SubPatterns {
+    /// Index of the pattern to report for `line`.
+    fn which(&self, line: &str) -> Option<usize> {
+        match self.priority {
+            PatternPriority::First => self
+                .matchers
+                .iter()
+                .position(|m| m.is_match(line.as_bytes()).unwrap_or(false)),
+            PatternPriority::Longest => {
+                let mut best: Option<(usize, usize)> = None;
+                for (index, matcher) in self.matchers.iter().enumerate() {
+                    let mut longest = None;
+                    let _ = matcher.find_iter(line.as_bytes(), |m| {
+                        longest = longest.max(Some(m.end() - m.start()));
+                        true
+                    });
+                    if let Some(len) = longest {
+                        // Strictly longer, so ties keep the earlier pattern
+                        if best.map_or(true, |(_, best_len)| len > best_len) {
+                            best = Some((index, len));
+                        }
+                    }
+                }
+                best.map(|(index, _)| index)
+            }
+        }
+    }
+}
+
+#[pymethods]
+impl RipGrep {
+    #[new]
+    #[pyo3(signature = (pattern, **options))]
+    fn new(pattern: String, options: Option<&Bound<'_, PyDict>>) -> PyResult<Self> {
+        let options = SearchOptions::from_kwargs(options)?;
+        let pattern = literal_pattern(&options, normalize_text(options.normalize, &pattern));
+        // Compiling the matcher validates the regex pattern immediately
+        let matcher = matcher_builder(&options)
+            .build(&pattern)
+            .map_err(invalid_regex)?;
+        RipGrep::build(pattern, options, matcher, None)
+    }
+
+    /// Build an instance matching any of `patterns`, like several `-e`
+    /// flags. Results gain a `which_pattern` key: the index of the pattern
+    /// reported for the line, chosen by `priority` (`"first"` for the first
+    /// matching pattern in declaration order, `"longest"` for the one with
+    /// the longest match).
+    #[staticmethod]
+    #[pyo3(signature = (patterns, priority="first", **options))]
+    fn with_patterns(
+        patterns: Vec<String>,
+        priority: &str,
+        options: Option<&Bound<'_, PyDict>>,
+    ) -> PyResult<Self> {
+        let options = SearchOptions::from_kwargs(options)?;
+        let priority = PatternPriority::parse(priority)?;
+        let patterns: Vec<String> = patterns
+            .iter()
+            .map(|pattern| literal_pattern(&options, normalize_text(options.normalize, pattern)))
+            .collect();
+
+        if patterns.is_empty() {
+            return Err(PatternError::new_err(
+                "with_patterns needs at least one pattern",
+            ));
+        }
+
+        let builder = matcher_builder(&options);
+        // Compiled one by one first, so an error can say which pattern it's in
+        let matchers = patterns
+            .iter()
+            .enumerate()
+            .map(|(index, pattern)| {
+                builder.build(pattern).map_err(|e| {
+                    PatternError::new_err(format!(
+                        "Invalid regex in pattern {} ({:?}): {}",
+                        index, pattern, e
+                    ))
+                })
+            })
+            .collect::<PyResult<Vec<_>>>()?;
+        let matcher = builder.build_many(&patterns).map_err(invalid_regex)?;
+
+        let pattern = patterns
+            .iter()
+            .map(|p| format!("(?:{})", p))
+            .collect::<Vec<_>>()
+            .join("|");
+        let sub_patterns = SubPatterns { matchers, priority };
+        RipGrep::build(pattern, options, matcher, Some(sub_patterns))
+    }
+
+    /// Build an instance from a raw byte pattern, which need not be UTF-8.
+    /// Bytes outside ASCII match themselves literally (Unicode mode is off),
+    /// so patterns can target binary data. Matched lines that aren't valid
+    /// UTF-8 are reported with invalid sequences replaced by U+FFFD. Files
+    /// with a NUL byte are still given up on unless `binary` says otherwise.
+    #[staticmethod]
+    #[pyo3(signature = (pattern, **options))]
+    fn from_bytes_pattern(pattern: Vec<u8>, options: Option<&Bound<'_, PyDict>>) -> PyResult<Self> {
+        let options = SearchOptions::from_kwargs(options)?;
+
+        // Spell non-ASCII bytes as `\xNN` escapes, which match single bytes
+        // with Unicode disabled; ASCII stays as-is so regex syntax works
+        let mut escaped = String::with_capacity(pattern.len());
+        for byte in pattern {
+            if byte.is_ascii() {
+                escaped.push(byte as char);
+            } else {
+                escaped.push_str(&format!("\\x{:02X}", byte));
+            }
+        }
+
+        let matcher = matcher_builder(&options)
+            .unicode(false)
+            .build(&escaped)
+            .map_err(invalid_regex)?;
+        let mut rg = RipGrep::build(escaped, options, matcher, None)?;
+        rg.bytes_pattern = true;
+        Ok(rg)
+    }
+
+    /// Search the file or directory at `path`. `ignore` swaps the
+    /// `.gitignore` files on disk for an `IgnoreMatcher`'s rules, and
+    /// `filename_glob` searches only the files it matches, standing in for
+    /// `include_globs` for this call.
+    ///
+    /// Under `on_error="collect"` a dict is returned instead, with the
+    /// `matches` plus `errors`: a `{"file", "error"}` dict for each file
+    /// that couldn't be searched.
+    ///
+    /// While a directory is walked, `progress` is called every
+    /// `progress_every` files with the number of files searched and of
+    /// matches found so far. Parallel walks call it at most every 50ms, so
+    /// it may see more files at a time. An exception raised by `progress`
+    /// stops the search and propagates.
+    ///
+    /// Given `start_line` and/or `end_line`, only matches on those lines
+    /// (inclusive, 1-based) of each file are kept, as in `search_file_lines`.
+    /// A range that's empty or past the end of a file finds nothing.
+    #[pyo3(signature = (
+        path,
+        ignore=None,
+        filename_glob=None,
+        progress=None,
+        progress_every=100,
+        start_line=None,
+        end_line=None
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn search(
+        &self,
+        path: &str,
+        ignore: Option<PyRef<'_, IgnoreMatcher>>,
+        filename_glob: Option<String>,
+        progress: Option<&Bound<'_, PyAny>>,
+        progress_every: usize,
+        start_line: Option<u64>,
+        end_line: Option<u64>,
+        py: Python,
+    ) -> PyResult<PyObject> {
+        if progress_every == 0 {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                "progress_every must be at least 1",
+            ));
+        }
+        let progress = progress.map(|callback| Progress {
+            callback,
+            every: progress_every,
+            reported: Cell::new(0),
+        });
+        let progress = progress.as_ref();
+        let line_range = (start_line.is_some() || end_line.is_some())
+            .then(|| start_line.unwrap_or(1).max(1)..=end_line.unwrap_or(u64::MAX));
+
+        let (matches, ctx) = if ignore.is_some() || filename_glob.is_some() {
+            let mut rg = self.clone();
+            rg.ignore_rules = ignore.map(|ignore| ignore.rules.clone());
+            if let Some(glob) = filename_glob {
+                rg.options.include_globs = vec![glob];
+                // Not shared with this instance, whose globs differ
+                rg.overrides = Arc::default();
+                rg.overrides_for(Path::new("."))?;
+            }
+            rg.collect_reporting(py, Path::new(path), progress, line_range)?
+        } else {
+            self.collect_reporting(py, Path::new(path), progress, line_range)?
+        };
+
+        let matches = matches_to_list(py, &matches)?;
+        self.with_errors(py, "matches", matches, ctx.errors)
+    }
+
+    /// Search `path` like `search`, returning a dict of the same `matches`
+    /// and `stats`: `total_matches`, `files_with_matches` and
+    /// `files_searched`, which counts every file opened for searching,
+    /// whether or not it matched.
+    fn search_with_stats<'py>(&self, path: &str, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let (matches, ctx) = self.collect_reporting(py, Path::new(path), None, None)?;
+        let files_with_matches = matches
+            .iter()
+            .filter(|m| m.is_dir != Some(true))
+            .map(|m| &m.file)
+            .collect::<HashSet<_>>()
+            .len();
+
+        let stats = PyDict::new_bound(py);
+        stats.set_item("total_matches", matches.len())?;
+        stats.set_item("files_with_matches", files_with_matches)?;
+        stats.set_item("files_searched", ctx.files_searched.load(Ordering::Relaxed))?;
+        let dict = PyDict::new_bound(py);
+        dict.set_item("matches", matches_to_list(py, &matches)?)?;
+        dict.set_item("stats", stats)?;
+        Ok(dict)
+    }
+
+    /// Search every path listed in the file at `list_path`, one per line,
+    /// and return the matches of all of them together, in list order. Each
+    /// path may be a file or a directory and is taken relative to the
+    /// working directory, like the path given to `search`. Blank lines and
+    /// lines starting with `#` are skipped.
+    fn search_from_list(&self, list_path: &str, py: Python) -> PyResult<Py<PyList>> {
+        let list = fs::read_to_string(list_path).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => path_error(Path::new(list_path)),
+            _ => search_error(e),
+        })?;
+
+        let paths = list
+            .lines()
+            .map(str::trim)
+            .filter(|path| !path.is_empty() && !path.starts_with('#'));
+        let matches = self.collect_all(py, paths)?;
+        Ok(matches_to_list(py, &matches)?.into())
+    }
+
+    /// Search each of `paths`, files or directories alike, and return the
+    /// matches of all of them together, in order. Cheaper than calling
+    /// `search` once per path, as the results are turned into dicts once.
+    fn search_many(&self, paths: Vec<String>, py: Python) -> PyResult<Py<PyList>> {
+        let matches = self.collect_all(py, paths.iter().map(String::as_str))?;
+        Ok(matches_to_list(py, &matches)?.into())
+    }
+
+    /// Search several files (or directories) in one call, each with its own
+    /// options. Every spec is a dict with a `path` plus any options to
+    /// override for that path, such as `encoding`; the matches of all specs
+    /// are returned together, in spec order. Options that shape the pattern
+    /// itself, `fixed_strings` and `normalize`, can't be overridden.
+    fn search_files_with_options(
+        &self,
+        specs: Vec<Bound<'_, PyDict>>,
+        py: Python,
+    ) -> PyResult<Py<PyList>> {
+        let mut matches = Vec::new();
+        for spec in specs {
+            let Some(path) = spec.get_item("path")? else {
+                return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                    "Each spec needs a path",
+                ));
+            };
+            let path: String = path.extract()?;
+            let overrides = spec.copy()?;
+            overrides.del_item("path")?;
+
+            let rg = self.with_overrides(&overrides)?;
+            matches.extend(rg.collect(py, Path::new(&path))?);
+        }
+        Ok(matches_to_list(py, &matches)?.into())
+    }
+
+    /// The resolved configuration: the pattern plus every option, with
+    /// defaults filled in for anything not passed to the constructor.
+    fn config<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let dict = self.options.to_dict(py)?;
+        dict.set_item("pattern", &self.pattern)?;
+        if let Some(sub) = &self.sub_patterns {
+            dict.set_item("priority", sub.priority.as_str())?;
+        }
+        Ok(dict)
+    }
+
+    /// Run the matcher once against a tiny buffer so that its lazily built
+    /// internals (such as the DFA cache) exist before the first real search,
+    /// moving that one-time cost off the critical path.
+    fn warm(&self) {
+        let _ = self.matcher.is_match(b"warm up the matcher\n");
+    }
+
+    /// Search `root` on a background thread, returning an iterator that
+    /// yields match dicts as they're found. The search runs without the GIL
+    /// and stops early once the iterator is dropped.
+    fn search_generator(&self, root: &str) -> SearchGenerator {
+        self.spawn_search(PathBuf::from(root), false)
+    }
+
+    /// Alias for `search_generator`.
+    fn search_iter(&self, path: &str) -> SearchGenerator {
+        self.search_generator(path)
+    }
+
+    /// Like `search_generator`, but also searches inside `.tar`, `.tar.gz`,
+    /// `.tgz` and `.gz` files found under `root`. Matches inside an archive
+    /// are reported with `file` set to `ARCHIVE!MEMBER`, or just the archive
+    /// path for a plain gzip file.
+    fn search_all(&self, root: &str) -> SearchGenerator {
+        self.spawn_search(PathBuf::from(root), true)
+    }
+
+    /// Search `path`, calling `callback` with each match dict as soon as its
+    /// file has been searched instead of building one list of every match.
+    /// A falsy return value stops the search; an exception raised by
+    /// `callback` stops it and propagates. Returns the number of matches
+    /// passed to `callback`.
+    fn search_stream(
+        &self,
+        path: &str,
+        callback: &Bound<'_, PyAny>,
+        py: Python,
+    ) -> PyResult<usize> {
+        let path = Path::new(path);
+        let _permit = py.allow_threads(limiter::acquire);
+        let mut ctx = self.context(path.is_file())?;
+
+        let mut delivered = 0;
+        let mut deliver = |file_matches: Vec<SearchMatch>| -> PyResult<bool> {
+            for m in file_matches {
+                delivered += 1;
+                if !callback.call1((m.to_dict(py)?,))?.is_truthy()? {
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        };
+
+        if path.is_file() {
+            let mut matches = Vec::new();
+            self.search_entry(&mut ctx, path, &mut matches)?;
+            deliver(matches)?;
+        } else if path.is_dir() {
+            self.walk_directory(&mut ctx, path, &mut deliver)?;
+        } else {
+            return Err(path_error(path));
+        }
+        Ok(delivered)
+    }
+
+    /// Search `root` and return the matches grouped by file, as a dict of
+    /// file path to that file's match dicts, files in walk order.
+    ///
+    /// Each file's matches are already in line order, since every file is
+    /// searched start to finish in one go; `group_sorted` sorts each list by
+    /// line number anyway, for callers that want it guaranteed explicitly.
+    #[pyo3(signature = (root, group_sorted=false))]
+    fn search_by_file(&self, root: &str, group_sorted: bool, py: Python) -> PyResult<Py<PyDict>> {
+        let matches = self.collect(py, Path::new(root))?;
+
+        let dict = PyDict::new_bound(py);
+        for (file, mut file_matches) in group_by_file(matches) {
+            if group_sorted {
+                // Stable, so directory matches (no line number) keep their place
+                file_matches.sort_by_key(|m| m.line_number);
+            }
+            dict.set_item(file, matches_to_list(py, &file_matches)?)?;
+        }
+        Ok(dict.into())
+    }
+
+    /// Search `path` and return the matches grouped by file, as a list of
+    /// `{"file", "matches"}` dicts in walk order, one per file with matches.
+    /// The match dicts are those of `search` without the repeated `file`,
+    /// in line order.
+    fn search_grouped(&self, path: &str, py: Python) -> PyResult<Py<PyList>> {
+        let matches = self.collect(py, Path::new(path))?;
+
+        let groups = PyList::empty_bound(py);
+        for (file, mut file_matches) in group_by_file(matches) {
+            // Stable, so directory matches (no line number) keep their place
+            file_matches.sort_by_key(|m| m.line_number);
+            let list = matches_to_list(py, &file_matches)?;
+            for m in list.iter() {
+                m.downcast::<PyDict>()?.del_item("file")?;
+            }
+            let group = PyDict::new_bound(py);
+            group.set_item("file", file)?;
+            group.set_item("matches", list)?;
+            groups.append(group)?;
+        }
+        Ok(groups.into())
+    }
+
+    /// Like `search`, but guarantees every record has the same set of keys,
+    /// filling absent values with `None`, so `pandas.DataFrame(records)`
+    /// never produces ragged columns.
+    fn search_records(&self, path: &str, py: Python) -> PyResult<Py<PyList>> {
+        let matches = self.collect(py, Path::new(path))?;
+        let records = matches_to_list(py, &matches)?;
+
+        let mut keys: Vec<Bound<'_, PyAny>> = Vec::new();
+        for record in records.iter() {
+            for key in record.downcast::<PyDict>()?.keys() {
+                if !keys.iter().any(|k| k.eq(&key).unwrap_or(false)) {
+                    keys.push(key);
+                }
+            }
+        }
+
+        for record in records.iter() {
+            let record = record.downcast::<PyDict>()?;
+            for key in &keys {
+                if !record.contains(key)? {
+                    record.set_item(key, py.None())?;
+                }
+            }
+        }
+
+        Ok(records.into())
+    }
+
+    /// Search an in-memory buffer instead of a path: `bytes`, `bytearray`,
+    /// `memoryview`, `mmap` or any other object exposing a contiguous byte
+    /// buffer. The memory is searched in place without copying. `file` is
+    /// `None` in the results.
+    fn search_bytes(&self, data: &Bound<'_, PyAny>, py: Python) -> PyResult<Py<PyList>> {
+        let buffer = PyBuffer::<u8>::get_bound(data)?;
+        if !buffer.is_c_contiguous() {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                "search_bytes requires a contiguous buffer",
+            ));
+        }
+
+        // SAFETY: the buffer is contiguous and `buffer` keeps the exporter
+        // alive; we hold the GIL, so no Python code can resize it meanwhile.
+        let bytes = unsafe {
+            std::slice::from_raw_parts(buffer.buf_ptr() as *const u8, buffer.len_bytes())
+        };
+
+        let matches = self.search_slice_impl(bytes)?;
+        Ok(matches_to_list(py, &matches)?.into())
+    }
+
+    /// Search an in-memory string instead of a path, such as contents
+    /// fetched over the network. `file` is `None` in the results.
+    fn search_text(&self, content: &str, py: Python) -> PyResult<Py<PyList>> {
+        let matches = self.search_slice_impl(content.as_bytes())?;
+        Ok(matches_to_list(py, &matches)?.into())
+    }
+
+    /// Search a stream rather than a path: `reader`, any Python file-like
+    /// object whose `read` returns `bytes` or `str`, or the process's stdin
+    /// when it's `None`. The stream is read in buffered chunks, never
+    /// memory mapped, so pipes and sockets work. `file` is `"<stdin>"` in
+    /// the results.
+    #[pyo3(signature = (reader=None))]
+    fn search_reader(&self, reader: Option<&Bound<'_, PyAny>>, py: Python) -> PyResult<Py<PyList>> {
+        let _permit = py.allow_threads(limiter::acquire);
+        // These need the whole text in memory, as for a buffer
+        let whole_text =
+            self.rewrites_text() || self.heading_matcher.is_some() || self.options.paragraph;
+        let matches = if whole_text {
+            let mut content = Vec::new();
+            let read = match reader {
+                Some(reader) => PyReader::new(reader).read_to_end(&mut content),
+                None => py.allow_threads(|| io::stdin().lock().read_to_end(&mut content)),
+            };
+            read.map_err(search_error)?;
+            let mut matches = self.search_slice_impl(&content)?;
+            for m in &mut matches {
+                m.file = Some(STDIN_NAME.to_string());
+                if m.id.is_some() {
+                    m.id = Some(m.stable_id());
+                }
+            }
+            matches
+        } else {
+            // Not a single file, so the searcher never tries to map it
+            let mut ctx = self.context(false)?;
+            match reader {
+                Some(reader) => ctx.run(self, Some(STDIN_NAME), |searcher, matcher, sink| {
+                    searcher.search_reader(matcher, PyReader::new(reader), sink)
+                }),
+                None => py.allow_threads(|| {
+                    ctx.run(self, Some(STDIN_NAME), |searcher, matcher, sink| {
+                        searcher.search_reader(matcher, io::stdin().lock(), sink)
+                    })
+                }),
+            }
+            .map_err(search_error)?
+        };
+        Ok(matches_to_list(py, &matches)?.into())
+    }
+
+    /// Search the files at `paths` as one document split across them, in
+    /// the given order, such as a log rotated into chunks. Each match
+    /// reports the file it came from and its line number within that file.
+    /// A file that doesn't end in a newline still ends its last line, so
+    /// outside `multiline` no match spans two files.
+    fn search_concat(&self, paths: Vec<String>, py: Python) -> PyResult<Py<PyList>> {
+        if !self.options.line_number.0 {
+            // Matches are traced back to their file by line number
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                "search_concat needs line_number",
+            ));
+        }
+        let mut document = Vec::new();
+        // Where each file's first line falls in the document
+        let mut starts = Vec::with_capacity(paths.len());
+        let mut next_line = 1;
+        for path in &paths {
+            let contents = fs::read(path).map_err(|e| match e.kind() {
+                io::ErrorKind::NotFound => path_error(Path::new(path)),
+                _ => search_error(e),
+            })?;
+            starts.push((next_line, path.as_str()));
+            next_line += contents.iter().filter(|&&b| b == b'\n').count() as u64;
+            document.extend_from_slice(&contents);
+            if !contents.is_empty() && !contents.ends_with(b"\n") {
+                document.push(b'\n');
+                next_line += 1;
+            }
+        }
+
+        // An empty file starts where the next one does, so take the last
+        let locate = |line_number: u64| {
+            let i = starts.partition_point(|&(start, _)| start <= line_number) - 1;
+            let (start, file) = starts[i];
+            (file, line_number - start + 1)
+        };
+        let mut matches = self.search_slice_impl(&document)?;
+        for m in &mut matches {
+            if let Some(line_number) = m.line_number {
+                let (file, local) = locate(line_number);
+                m.file = Some(file.to_string());
+                m.line_number = Some(local);
+            }
+            if m.id.is_some() {
+                m.id = Some(m.stable_id());
+            }
+            for (line_number, _) in m
+                .context_before
+                .iter_mut()
+                .chain(m.context_after.iter_mut())
+                .flatten()
+            {
+                *line_number = locate(*line_number).1;
+            }
+        }
+        Ok(matches_to_list(py, &matches)?.into())
+    }
+
+    /// Search only lines `start_line` through `end_line` (inclusive, 1-based)
+    /// of the file at `path`. Bounds are clamped to the file and reported
+    /// line numbers are those of the file.
+    fn search_file_lines(
+        &self,
+        path: &str,
+        start_line: u64,
+        end_line: u64,
+        py: Python,
+    ) -> PyResult<Py<PyList>> {
+        let mut ctx = self.context(true)?;
+        // The range is kept by line number, whether or not they're reported
+        ctx.searcher = self.searcher_builder(true).line_number(true).build();
+        ctx.line_range = Some(start_line.max(1)..=end_line);
+
+        let mut matches = Vec::new();
+        self.search_file_impl(&mut ctx, Path::new(path), &mut matches)?;
+        Ok(matches_to_list(py, &matches)?.into())
+    }
+
+    /// Search the file at `path` only from byte `from_offset` to the end,
+    /// for polling a growing log. If `from_offset` falls inside a line, the
+    /// search starts at the next one; past the end, the file is taken to
+    /// have been truncated or rotated and is searched from the start. Only
+    /// complete lines are searched: returns the matches, each with the
+    /// absolute `byte_offset` of its line, and the offset just after the
+    /// last newline, to pass as `from_offset` next time so that a line
+    /// still being written is searched once it's finished. Earlier lines
+    /// are never read, so `line_number` counts from the first line searched.
+    fn search_tail(&self, path: &str, from_offset: u64, py: Python) -> PyResult<(Py<PyList>, u64)> {
+        let path = Path::new(path);
+        if !path.is_file() {
+            return Err(path_error(path));
+        }
+        let mut file = fs::File::open(path).map_err(search_error)?;
+        let len = file.metadata().map_err(search_error)?.len();
+        let start = if from_offset > len { 0 } else { from_offset };
+
+        // Read from one byte early to see whether `start` begins a line
+        let read_from = start.saturating_sub(1);
+        file.seek(SeekFrom::Start(read_from))
+            .map_err(search_error)?;
+        let mut tail = Vec::new();
+        file.read_to_end(&mut tail).map_err(search_error)?;
+
+        let skip = if start == 0 {
+            0
+        } else {
+            tail.iter()
+                .position(|&b| b == b'\n')
+                .map_or(tail.len(), |newline| newline + 1)
+        };
+        // A trailing partial line is left for the next call
+        let complete = tail[skip..]
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map_or(0, |newline| newline + 1);
+        let region = &tail[skip..skip + complete];
+        let end = read_from + (skip + complete) as u64;
+
+        let mut ctx = self.context(true)?;
+        ctx.base_offset = Some(read_from + skip as u64);
+        let display = self.display_path(path);
+        let matches = ctx
+            .run(self, Some(&*display), |searcher, matcher, sink| {
+                searcher.search_slice(matcher, region, sink)
+            })
+            .map_err(search_error)?;
+
+        Ok((matches_to_list(py, &matches)?.into(), end))
+    }
+
+    /// Rewrite the file at `path`, replacing each match with the string
+    /// returned by `callback`. The callback receives a tuple of the match's
+    /// captures (group 0 is the whole match, unmatched groups are `None`).
+    /// The file is replaced atomically and the number of replacements made
+    /// is returned; a file without matches is left untouched.
+    fn replace_with_callback(
+        &self,
+        path: &str,
+        callback: &Bound<'_, PyAny>,
+        py: Python,
+    ) -> PyResult<usize> {
+        let regex = self.capture_regex()?;
+        let path = Path::new(path);
+        let contents = py
+            .allow_threads(|| fs::read_to_string(path))
+            .map_err(|e| SearchIOError::new_err(format!("Read error: {}", e)))?;
+
+        let mut output = String::with_capacity(contents.len());
+        let mut changes = 0;
+        // Match line by line, like the searcher, so patterns never span lines
+        for line in contents.split_inclusive('\n') {
+            let body = line.trim_end_matches(['\n', '\r']);
+            let mut last = 0;
+            for caps in regex.captures_iter(body) {
+                let whole = caps.get(0).unwrap();
+                let groups: Vec<Option<&str>> =
+                    caps.iter().map(|g| g.map(|m| m.as_str())).collect();
+                let replacement: String = callback
+                    .call1((PyTuple::new_bound(py, groups),))?
+                    .extract()?;
+                output.push_str(&line[last..whole.start()]);
+                output.push_str(&replacement);
+                last = whole.end();
+                changes += 1;
+            }
+            output.push_str(&line[last..]);
+        }
+
+        if changes > 0 {
+            py.allow_threads(|| write_atomically(path, &output))
+                .map_err(|e| SearchIOError::new_err(format!("Write error: {}", e)))?;
+        }
+        Ok(changes)
+    }
+
+    /// Preview replacing each match under `root` with `replacement`, which
+    /// may refer to capture groups as `$1` or `${name}`, without touching
+    /// any file. Returns a dict of path to a unified diff of that file's
+    /// changed lines, ready for `patch`; files without matches, or that
+    /// aren't UTF-8, are left out.
+    fn diff_preview(
+        &self,
+        root: &str,
+        replacement: &str,
+        py: Python,
+    ) -> PyResult<BTreeMap<String, String>> {
+        let regex = self.capture_regex()?;
+        let root = Path::new(root);
+        if !root.exists() {
+            return Err(path_error(root));
+        }
+        let _permit = py.allow_threads(limiter::acquire);
+        let files = self.walk_files(root)?;
+
+        py.allow_threads(|| {
+            let mut diffs = BTreeMap::new();
+            for file in files {
+                let Ok(contents) = fs::read_to_string(&file) else {
+                    continue;
+                };
+                // Line by line, like the searcher and replace_with_callback
+                let old: Vec<&str> = contents.split_inclusive('\n').collect();
+                let new: Vec<String> = old
+                    .iter()
+                    .map(|line| {
+                        let body = line.trim_end_matches(['\n', '\r']);
+                        let replaced = regex.replace_all(body, replacement);
+                        format!("{}{}", replaced, &line[body.len()..])
+                    })
+                    .collect();
+                let display = self.display_path(&file);
+                if let Some(diff) = unified_diff(&display, &old, &new) {
+                    diffs.insert(display, diff);
+                }
+            }
+            Ok(diffs)
+        })
+    }
+
+    /// Preview replacing every match under `path` with `replacement`, which
+    /// may refer to capture groups as `$1` or `${name}`, without touching
+    /// any file. Returns the match dicts of `search`, each with the line as
+    /// it would read after the substitution added as `replaced`.
+    fn replace(&self, path: &str, replacement: &str, py: Python) -> PyResult<Py<PyList>> {
+        let regex = self.capture_regex()?;
+        let matches = self.collect(py, Path::new(path))?;
+
+        let results = PyList::empty_bound(py);
+        for m in &matches {
+            let dict = m.to_dict(py)?;
+            dict.set_item("replaced", regex.replace_all(&m.line, replacement).as_ref())?;
+            results.append(dict)?;
+        }
+        Ok(results.into())
+    }
+
+    /// Replace every match under `path` with `replacement`, as previewed by
+    /// `replace`, writing each changed file back atomically with its
+    /// permissions kept. Only lines the search reports are rewritten, so
+    /// options like `invert_match`, `max_count` or binary detection apply
+    /// as they do to `search`. Files without matches, or that aren't UTF-8,
+    /// are left untouched. Returns the number of files changed and of
+    /// replacements made.
+    fn replace_in_files(
+        &self,
+        path: &str,
+        replacement: &str,
+        py: Python,
+    ) -> PyResult<(usize, usize)> {
+        if !self.options.line_number.0 {
+            // Reported lines are found in the file by their number
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                "replace_in_files needs line_number",
+            ));
+        }
+        let regex = self.capture_regex()?;
+        let root = Path::new(path);
+        if !root.exists() {
+            return Err(path_error(root));
+        }
+        let _permit = py.allow_threads(limiter::acquire);
+        let files = self.walk_files(root)?;
+        let mut ctx = self.context(root.is_file())?;
+
+        py.allow_threads(|| {
+            let (mut files_changed, mut replacements) = (0, 0);
+            for file in files {
+                let replaced = match self.replace_reported(&mut ctx, &file, &regex, replacement) {
+                    Ok(replaced) => replaced,
+                    Err(e) => {
+                        self.file_error(&mut ctx, &file, e)?;
+                        continue;
+                    }
+                };
+                let Some((output, changes)) = replaced.filter(|&(_, changes)| changes > 0) else {
+                    continue;
+                };
+                write_atomically(&file, &output)
+                    .map_err(|e| SearchIOError::new_err(format!("Write error: {}", e)))?;
+                files_changed += 1;
+                replacements += changes;
+            }
+            Ok((files_changed, replacements))
+        })
+    }
+
+    /// Search `root` and open the matches in an editor, running
+    /// `editor_cmd +LINE FILE` for each match in turn (or only the first
+    /// with `first_only`) and waiting for the editor to exit each time.
+    ///
+    /// `editor_cmd` is a program name or a list of the program and its
+    /// leading arguments. Returns the number of matches opened.
+    #[pyo3(signature = (root, editor_cmd, first_only=false))]
+    fn open_matches(
+        &self,
+        root: &str,
+        editor_cmd: &Bound<'_, PyAny>,
+        first_only: bool,
+        py: Python,
+    ) -> PyResult<usize> {
+        let editor_cmd: Vec<String> = match editor_cmd.extract::<String>() {
+            Ok(program) => vec![program],
+            Err(_) => editor_cmd.extract()?,
+        };
+        let Some((program, leading_args)) = editor_cmd.split_first() else {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                "editor_cmd must not be empty",
+            ));
+        };
+
+        let mut matches = self.collect(py, Path::new(root))?;
+        if first_only {
+            matches.truncate(1);
+        }
+
+        for m in &matches {
+            let mut command = Command::new(program);
+            command.args(leading_args);
+            if let Some(line_number) = m.line_number {
+                command.arg(format!("+{}", line_number));
+            }
+            command.args(&m.file);
+
+            py.allow_threads(|| command.status())
+                .map_err(|e| SearchIOError::new_err(format!("Failed to run editor: {}", e)))?;
+        }
+        Ok(matches.len())
+    }
+
+    /// Split the file at `path` into buckets of `bucket_lines` lines and
+    /// return how many matching lines fall in each, covering the whole file,
+    /// for plotting where matches cluster.
+    fn density(&self, path: &str, bucket_lines: usize, py: Python) -> PyResult<Vec<usize>> {
+        if bucket_lines == 0 {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                "bucket_lines must be at least 1",
+            ));
+        }
+
+        let _permit = py.allow_threads(limiter::acquire);
+        // Passthru reports non-matching lines too, so trailing buckets
+        // without matches are still counted
+        let mut searcher = self
+            .searcher_builder(true)
+            .passthru(true)
+            .line_number(true)
+            .build();
+        let mut sink = DensitySink {
+            rg: self,
+            bucket_lines: bucket_lines as u64,
+            buckets: Vec::new(),
+        };
+        searcher
+            .search_path(&*self.matcher, path, &mut sink)
+            .map_err(search_error)?;
+        Ok(sink.buckets)
+    }
+
+    /// Count how many times each distinct matching line occurs under `root`,
+    /// like piping the matches through `sort | uniq -c`.
+    ///
+    /// `aggregate` selects what to group by; only `"line"` is supported.
+    /// With `normalize_whitespace`, runs of whitespace are collapsed to a
+    /// single space and the line is trimmed before counting.
+    #[pyo3(signature = (root, aggregate="line", normalize_whitespace=false))]
+    fn search_aggregate(
+        &self,
+        root: &str,
+        aggregate: &str,
+        normalize_whitespace: bool,
+        py: Python,
+    ) -> PyResult<Py<PyDict>> {
+        if aggregate != "line" {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "Unsupported aggregate: {}",
+                aggregate
+            )));
+        }
+
+        let matches = self.collect(py, Path::new(root))?;
+
+        let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+        for m in matches {
+            let key = if normalize_whitespace {
+                m.line.split_whitespace().collect::<Vec<_>>().join(" ")
+            } else {
+                m.line
+            };
+            *counts.entry(key).or_default() += 1;
+        }
+
+        let dict = PyDict::new_bound(py);
+        for (line, count) in counts {
+            dict.set_item(line, count)?;
+        }
+        Ok(dict.into())
+    }
+
+    /// Search `root` file by file and report, for each file searched, the
+    /// number of matches, the time taken in seconds and whether it was read
+    /// through a memory map (`"mmap"`) or buffered reads (`"buffered"`).
+    /// `skipped` is true for files given up on, with `skip_reason` saying
+    /// why: `"max_lines"` or `"long_line"` (see `skip_long_lines`). Under
+    /// `report_lines_scanned`, `lines_scanned` counts the lines read before
+    /// the file was finished or given up on (`None` for record searches).
+    ///
+    /// A file that can't be searched is dealt with as `on_error` says; under
+    /// `"collect"` a dict is returned, with the `files` profiled plus the
+    /// `errors`, as for `search`.
+    fn profile(&self, root: &str, py: Python) -> PyResult<PyObject> {
+        let root = Path::new(root);
+        let single_file = root.is_file();
+        let mut ctx = self.context(single_file)?;
+        let read_method = if self.uses_mmap(single_file) {
+            "mmap"
+        } else {
+            "buffered"
+        };
+
+        let files = self.walk_files(root)?;
+
+        let _permit = py.allow_threads(limiter::acquire);
+        let results = PyList::empty_bound(py);
+        for file in files {
+            let mut matches = Vec::new();
+            let start = Instant::now();
+            if let Err(e) = self.search_file_impl(&mut ctx, &file, &mut matches) {
+                self.file_error(&mut ctx, &file, e)?;
+                continue;
+            }
+            let elapsed = start.elapsed().as_secs_f64();
+
+            let dict = PyDict::new_bound(py);
+            dict.set_item("file", file.to_string_lossy())?;
+            dict.set_item("matches", matches.len())?;
+            dict.set_item("elapsed", elapsed)?;
+            dict.set_item("read_method", read_method)?;
+            dict.set_item("skipped", ctx.skipped.is_some())?;
+            dict.set_item("skip_reason", ctx.skipped.map(|reason| reason.as_str()))?;
+            if self.options.report_lines_scanned {
+                dict.set_item("lines_scanned", ctx.lines_scanned)?;
+            }
+            results.append(dict)?;
+        }
+
+        self.with_errors(py, "files", results, ctx.errors)
+    }
+
+    /// Count the matching lines of each file under `root`, like `rg -c`.
+    /// Returns `{"file", "count"}` dicts in walk order, leaving out files
+    /// without matches. Matched lines are counted as the searcher finds them,
+    /// never decoded into strings, so this is much cheaper than taking the
+    /// `len` of each file's `search` results.
+    fn count(&self, root: &str, py: Python) -> PyResult<Py<PyList>> {
+        let root = Path::new(root);
+        if !root.exists() {
+            return Err(path_error(root));
+        }
+        let files = self.walk_files(root)?;
+
+        let _permit = py.allow_threads(limiter::acquire);
+        let mut ctx = self.context(root.is_file())?;
+        let results = PyList::empty_bound(py);
+        for file in files {
+            let count = match self.count_file(&mut ctx, &file) {
+                Ok(count) => count,
+                Err(e) => {
+                    self.file_error(&mut ctx, &file, e)?;
+                    continue;
+                }
+            };
+            if count > 0 {
+                let dict = PyDict::new_bound(py);
+                dict.set_item("file", self.display_path(&file))?;
+                dict.set_item("count", count)?;
+                results.append(dict)?;
+            }
+            if let Err(err) = self.check_max_count(&ctx) {
+                // The counts so far, the last one cut at `max_count`
+                err.value_bound(py).setattr("partial_results", &results)?;
+                return Err(err);
+            }
+        }
+
+        Ok(results.into())
+    }
+
+    /// Paths of the files under `root` with at least one match, like
+    /// `rg -l`, in walk order. Each file is only read up to its first
+    /// match, and no line is decoded, so this is much cheaper than
+    /// collecting the files of `search`'s results.
+    fn files_with_matches(&self, root: &str, py: Python) -> PyResult<Py<PyList>> {
+        let root = Path::new(root);
+        if !root.exists() {
+            return Err(path_error(root));
+        }
+        let files = self.walk_files(root)?;
+
+        // Counting stops at the first match of each file
+        let mut rg = self.clone();
+        rg.options.max_count = Some(1);
+        rg.options.limit_behavior = LimitBehavior::Truncate;
+        let _permit = py.allow_threads(limiter::acquire);
+        let mut ctx = rg.context(root.is_file())?;
+        let mut seen = HashSet::new();
+        let results = PyList::empty_bound(py);
+        for file in files {
+            match rg.count_file(&mut ctx, &file) {
+                Ok(0) => {}
+                Ok(_) => {
+                    let display = rg.display_path(&file);
+                    if seen.insert(display.clone()) {
+                        results.append(display)?;
+                    }
+                }
+                Err(e) => rg.file_error(&mut ctx, &file, e)?,
+            }
+        }
+
+        Ok(results.into())
+    }
+
+    /// How many files under `root` a search would read and their total size
+    /// in bytes, as `{file_count, total_bytes}`, for sizing a progress bar.
+    /// Only the walk runs, with the same ignore, glob and type rules, so no
+    /// file is opened.
+    fn estimate<'py>(&self, root: &str, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let root = Path::new(root);
+        if !root.exists() {
+            return Err(path_error(root));
+        }
+        let files = self.walk_files(root)?;
+
+        let total_bytes: u64 = files
+            .iter()
+            .filter_map(|file| fs::metadata(file).ok())
+            .map(|metadata| metadata.len())
+            .sum();
+        let dict = PyDict::new_bound(py);
+        dict.set_item("file_count", files.len())?;
+        dict.set_item("total_bytes", total_bytes)?;
+        Ok(dict)
+    }
+
+    /// For each directory under `root` with matches, how many of the files
+    /// directly inside it match, as a dict of directory path to count.
+    fn dir_match_summary(&self, root: &str, py: Python) -> PyResult<BTreeMap<String, usize>> {
+        let root = Path::new(root);
+        let _permit = py.allow_threads(limiter::acquire);
+        let mut ctx = self.context(root.is_file())?;
+
+        let mut summary = BTreeMap::new();
+        let mut tally = |file_matches: Vec<SearchMatch>| -> PyResult<bool> {
+            // Directories matched by `match_dirs` aren't files with matches
+            let file = file_matches
+                .iter()
+                .find(|m| m.is_dir != Some(true))
+                .and_then(|m| m.file.as_deref());
+            if let Some(file) = file {
+                let dir = Path::new(file).parent().unwrap_or(Path::new(""));
+                *summary
+                    .entry(dir.to_string_lossy().to_string())
+                    .or_insert(0) += 1;
+            }
+            Ok(true)
+        };
+
+        if root.is_file() {
+            let mut matches = Vec::new();
+            self.search_file_impl(&mut ctx, root, &mut matches)?;
+            tally(matches)?;
+        } else if root.is_dir() {
+            self.walk_directory(&mut ctx, root, &mut tally)?;
+        } else {
+            return Err(path_error(root));
+        }
+        Ok(summary)
+    }
+
+    /// Search `root` and return the matches as flat buffers rather than one
+    /// dict per match, for handing large result sets to columnar tools.
+    ///
+    /// The returned dict holds, for `n` matches:
+    ///
+    /// - `line_numbers`: `n` native-endian int64 values (`array("q")`
+    ///   layout), `-1` where a match has no line number.
+    /// - `file_ids`: `n` int64 indices into `files`.
+    /// - `files`: the distinct file paths, in order of first match.
+    /// - `offsets`: `n + 1` int64 byte offsets into `text`; match `i` is
+    ///   `text[offsets[i]:offsets[i + 1]]`.
+    /// - `text`: every matching line concatenated, UTF-8 encoded.
+    fn search_arrow<'py>(&self, root: &str, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let matches = self.collect(py, Path::new(root))?;
+
+        let mut line_numbers = Vec::with_capacity(matches.len() * 8);
+        let mut file_ids = Vec::with_capacity(matches.len() * 8);
+        let mut offsets = Vec::with_capacity((matches.len() + 1) * 8);
+        let mut files: Vec<String> = Vec::new();
+        let mut file_index: HashMap<String, i64> = HashMap::new();
+        let mut text = String::new();
+
+        offsets.extend_from_slice(&0i64.to_ne_bytes());
+        for m in &matches {
+            let line_number = m.line_number.map_or(-1, |n| n as i64);
+            line_numbers.extend_from_slice(&line_number.to_ne_bytes());
+
+            let file = m.file.clone().unwrap_or_default();
+            let id = *file_index.entry(file.clone()).or_insert_with(|| {
+                files.push(file);
+                files.len() as i64 - 1
+            });
+            file_ids.extend_from_slice(&id.to_ne_bytes());
+
+            text.push_str(&m.line);
+            offsets.extend_from_slice(&(text.len() as i64).to_ne_bytes());
+        }
+
+        let dict = PyDict::new_bound(py);
+        dict.set_item("line_numbers", PyBytes::new_bound(py, &line_numbers))?;
+        dict.set_item("file_ids", PyBytes::new_bound(py, &file_ids))?;
+        dict.set_item("files", files)?;
+        dict.set_item("offsets", PyBytes::new_bound(py, &offsets))?;
+        dict.set_item("text", PyBytes::new_bound(py, text.as_bytes()))?;
+        Ok(dict)
+    }
+
+    /// Search `root` and return the matches serialized with bincode in one
+    /// `bytes` buffer, for shipping large result sets to another process
+    /// without building a dict per match. `sup.decode_matches` turns the
+    /// buffer back into match dicts.
+    ///
+    /// The buffer is a `Vec` of `(file, line_number, line, matches)`
+    /// records in bincode's default encoding: little-endian fixed-width
+    /// integers, `u64` length prefixes and a `u8` tag before each `Option`.
+    /// `matches` holds `(start, end)` pairs of `u64`.
+    fn search_encoded<'py>(&self, root: &str, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+        let matches = self.collect(py, Path::new(root))?;
+        let records: Vec<EncodedMatch<'_>> = matches
+            .iter()
+            .map(|m| EncodedMatch {
+                file: m.file.as_deref(),
+                line_number: m.line_number,
+                line: &m.line,
+                matches: &m.spans,
+            })
+            .collect();
+
+        let encoded = bincode::serialize(&records).map_err(|e| {
+            PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(format!(
+                "Failed to encode matches: {}",
+                e
+            ))
+        })?;
+        Ok(PyBytes::new_bound(py, &encoded))
+    }
+
+    /// Search `root` and return the matches as a JSON array of match
+    /// objects, serialized without building any Python dicts. The objects
+    /// have the same keys as `search`'s dicts, except that under
+    /// `raw_bytes` `line` is the decoded text, since JSON has no bytes.
+    fn search_json(&self, root: &str, py: Python) -> PyResult<String> {
+        let matches = self.collect(py, Path::new(root))?;
+        let records: Vec<serde_json::Value> = matches.iter().map(SearchMatch::to_json).collect();
+        serde_json::to_string(&records).map_err(|e| {
+            PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(format!(
+                "Failed to encode matches: {}",
+                e
+            ))
+        })
+    }
+
+    /// Compare the matches under `root` with `previous_results` from an
+    /// earlier run, keyed by file and line text so matches that merely moved
+    /// count as unchanged.
+    ///
+    /// `previous_results` holds match dicts, or hashes from a previous
+    /// call's `hashes`. Returns a dict with `added` and `unchanged` (current
+    /// match dicts), `removed` (entries of `previous_results`) and `hashes`
+    /// (one per current match, to store for the next comparison).
+    fn diff<'py>(
+        &self,
+        root: &str,
+        previous_results: Vec<Bound<'py, PyAny>>,
+        py: Python<'py>,
+    ) -> PyResult<Bound<'py, PyDict>> {
+        let matches = self.collect(py, Path::new(root))?;
+
+        // Multiset of previous hashes, so duplicate lines pair up one by one
+        let mut previous: HashMap<String, Vec<usize>> = HashMap::new();
+        for (index, item) in previous_results.iter().enumerate() {
+            let hash = match item.extract::<String>() {
+                Ok(hash) => hash,
+                Err(_) => {
+                    let item = item.downcast::<PyDict>()?;
+                    let field = |key: &str| -> PyResult<String> {
+                        Ok(item
+                            .get_item(key)?
+                            .and_then(|v| v.extract::<String>().ok())
+                            .unwrap_or_default())
+                    };
+                    match_hash(&field("file")?, &field("line")?)
+                }
+            };
+            previous.entry(hash).or_default().push(index);
+        }
+
+        let added = PyList::empty_bound(py);
+        let unchanged = PyList::empty_bound(py);
+        let mut hashes = Vec::with_capacity(matches.len());
+        for m in &matches {
+            let hash = match_hash(m.file.as_deref().unwrap_or_default(), &m.line);
+            let seen = previous.get_mut(&hash).and_then(|indices| indices.pop());
+            if seen.is_some() {
+                unchanged.append(m.to_dict(py)?)?;
+            } else {
+                added.append(m.to_dict(py)?)?;
+            }
+            hashes.push(hash);
+        }
+
+        let mut removed: Vec<usize> = previous.into_values().flatten().collect();
+        removed.sort_unstable();
+
+        let dict = PyDict::new_bound(py);
+        dict.set_item("added", added)?;
+        dict.set_item(
+            "removed",
+            removed
+                .into_iter()
+                .map(|index| previous_results[index].clone())
+                .collect::<Vec<_>>(),
+        )?;
+        dict.set_item("unchanged", unchanged)?;
+        dict.set_item("hashes", hashes)?;
+        Ok(dict)
+    }
+
+    /// Return an order-independent digest of every match under `root`,
+    /// together with the number of matches.
+    ///
+    /// Identical trees always produce identical digests, regardless of the
+    /// order in which files were walked. Each match is hashed by its file,
+    /// line number and line with FNV-1a, like `diff`, so stored digests
+    /// stay valid across builds.
+    fn search_digest(&self, root: &str, py: Python) -> PyResult<(String, usize)> {
+        let matches = self.collect(py, Path::new(root))?;
+
+        let mut hashes: Vec<u64> = matches
+            .iter()
+            .map(|m| {
+                let line_number = m.line_number.unwrap_or(0).to_le_bytes();
+                fnv1a([
+                    m.file.as_deref().unwrap_or_default().as_bytes(),
+                    &line_number,
+                    m.line.as_bytes(),
+                ])
+            })
+            .collect();
+        hashes.sort_unstable();
+
+        let bytes: Vec<u8> = hashes.iter().flat_map(|hash| hash.to_le_bytes()).collect();
+        Ok((format!("{:016x}", fnv1a([&bytes[..]])), matches.len()))
+    }
+}
+
+/// A single matching line, collected before being handed to Python.
+///
+/// Optional fields are driven by the search options rather than by the
+/// individual match, so every dict from one search has the same keys.
+#[derive(Default)]
+struct SearchMatch {
+    /// `None` for in-memory buffers.
+    file: Option<String>,
+    /// `None` for directory name matches.
+    line_number: Option<u64>,
+    line: String,
+    /// Set when `max_match_len` is enabled.
+    match_truncated: Option<bool>,
+    /// Set when `snippet_radius` is enabled; `None` inside if nothing matched.
+    snippet: Option<Option<String>>,
+    /// Set when `match_dirs` is enabled.
+    is_dir: Option<bool>,
+    /// Set for `with_patterns` instances; `None` inside if none matched.
+    which_pattern: Option<Option<usize>>,
+    /// Set when `named_output` is enabled: each requested capture's value.
+    named: Option<Vec<(String, Option<String>)>>,
+    /// Set when `blame` is enabled: the line's last commit hash and author,
+    /// or `None` outside a git repository.
+    blame: Option<Option<(String, String)>>,
+    /// Set when `include_mode` is enabled; `None` inside if stat failed.
+    mode: Option<Option<FileMode>>,
+    /// Set when `context_pattern` is enabled; `None` inside if no earlier
+    /// line matched it.
+    context_heading: Option<Option<String>>,
+    /// Set when `line_ending` is enabled: `"lf"`, `"crlf"` or `"none"`.
+    line_ending: Option<&'static str>,
+    /// Set when `paragraph` is enabled.
+    paragraph: Option<Option<String>>,
+    /// Byte offsets of every occurrence of the pattern in the line, as
+    /// `(start, end)`, measured before any `max_match_len` truncation.
+    spans: Vec<(usize, usize)>,
+    /// Set by `search_tail`: where the line starts in the file.
+    byte_offset: Option<u64>,
+    /// Set when `column` is enabled; `None` inside if nothing matched.
+    column: Option<Option<usize>>,
+    /// Set when `absolute_offset` is enabled: where the line starts in the
+    /// file.
+    offset: Option<u64>,
+    /// Set when `file_span` is enabled: each match's byte range in the file.
+    file_spans: Option<Vec<(u64, u64)>>,
+    /// Set when `before_context` or `after_context` is enabled: the
+    /// surrounding lines as `(line_number, line)` pairs.
+    context_before: Option<Vec<(u64, String)>>,
+    context_after: Option<Vec<(u64, String)>>,
+    /// Set when `split` is enabled: the line cut at every match.
+    segments: Option<Vec<String>>,
+    /// Set when `with_ids` is enabled.
+    id: Option<String>,
+    /// Set when `hex_preview` is enabled; `None` inside if nothing matched.
+    hex_preview: Option<Option<String>>,
+    /// Set when `raw_bytes` is enabled: the line as read, reported in place
+    /// of `line`.
+    raw_line: Option<Vec<u8>>,
+    /// Set when `captures` is enabled: groups 1 and up of the first match,
+    /// `None` for a group that didn't take part. Empty if nothing matched.
+    captures: Option<Vec<Option<String>>>,
+    /// Set when `label` is.
+    label: Option<String>,
+}
+
+/// The fields of a `SearchMatch` that `search_encoded` serializes.
+#[derive(Serialize)]
+struct EncodedMatch<'a> {
+    file: Option<&'a str>,
+    line_number: Option<u64>,
+    line: &'a str,
+    matches: &'a [(usize, usize)],
+}
+
+/// A file's permissions as reported by `include_mode`.
+#[derive(Clone, Copy)]
+enum FileMode {
+    /// Permission bits, reported in octal like Python's `oct`.
+    #[cfg(unix)]
+    Bits(u32),
+    /// Windows has no mode bits, only a read-only attribute.
+    #[cfg(not(unix))]
+    ReadOnly(bool),
+}
+
+impl SearchMatch {
+    /// The `with_ids` id: a hash of the file, line number, column of the
+    /// first match and line text.
+    fn stable_id(&self) -> String {
+        let line_number = self.line_number.unwrap_or(0).to_le_bytes();
+        let column = self.spans.first().map_or(0, |&(start, _)| start as u64);
+        let column = column.to_le_bytes();
+        let parts: [&[u8]; 4] = [
+            self.file.as_deref().unwrap_or_default().as_bytes(),
+            &line_number,
+            &column,
+            self.line.as_bytes(),
+        ];
+        let hash = fnv1a(parts);
+        format!("{:016x}", hash)
+    }
+
+    fn to_dict<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let dict = PyDict::new_bound(py);
+        dict.set_item("file", &self.file)?;
+        dict.set_item("line_number", self.line_number)?;
+        if let Some(id) = &self.id {
+            dict.set_item("id", id)?;
+        }
+        if let Some(label) = &self.label {
+            dict.set_item("label", label)?;
+        }
+        // Named output is a flat table row of just the requested captures
+        if let Some(named) = &self.named {
+            for (name, value) in named {
+                dict.set_item(name, value)?;
+            }
+            return Ok(dict);
+        }
+        match &self.raw_line {
+            Some(raw) => dict.set_item("line", PyBytes::new_bound(py, raw))?,
+            None => dict.set_item("line", &self.line)?,
+        }
+        let spans = PyList::empty_bound(py);
+        for &(start, end) in &self.spans {
+            let span = PyDict::new_bound(py);
+            span.set_item("start", start)?;
+            span.set_item("end", end)?;
+            spans.append(span)?;
+        }
+        dict.set_item("matches", spans)?;
+        if let Some(truncated) = self.match_truncated {
+            dict.set_item("match_truncated", truncated)?;
+        }
+        if let Some(snippet) = &self.snippet {
+            dict.set_item("snippet", snippet)?;
+        }
+        if let Some(is_dir) = self.is_dir {
+            dict.set_item("is_dir", is_dir)?;
+        }
+        if let Some(which_pattern) = self.which_pattern {
+            dict.set_item("which_pattern", which_pattern)?;
+        }
+        if let Some(line_ending) = self.line_ending {
+            dict.set_item("line_ending", line_ending)?;
+        }
+        if let Some(paragraph) = &self.paragraph {
+            dict.set_item("paragraph", paragraph)?;
+        }
+        if let Some(offset) = self.byte_offset {
+            dict.set_item("byte_offset", offset)?;
+        }
+        if let Some(column) = self.column {
+            dict.set_item("column", column)?;
+        }
+        if let Some(offset) = self.offset {
+            dict.set_item("offset", offset)?;
+        }
+        if let Some(file_spans) = &self.file_spans {
+            dict.set_item("file_spans", file_spans)?;
+        }
+        if let Some(segments) = &self.segments {
+            dict.set_item("segments", segments)?;
+        }
+        if let Some(captures) = &self.captures {
+            dict.set_item("captures", captures)?;
+        }
+        if let Some(preview) = &self.hex_preview {
+            dict.set_item("hex_preview", preview)?;
+        }
+        if let Some(before) = &self.context_before {
+            dict.set_item("context_before", before)?;
+        }
+        if let Some(after) = &self.context_after {
+            dict.set_item("context_after", after)?;
+        }
+        if let Some(heading) = &self.context_heading {
+            dict.set_item("context_heading", heading)?;
+        }
+        if let Some(mode) = self.mode {
+            match mode {
+                #[cfg(unix)]
+                Some(FileMode::Bits(bits)) => dict.set_item("mode", format!("0o{:o}", bits))?,
+                #[cfg(not(unix))]
+                Some(FileMode::ReadOnly(readonly)) => dict.set_item("readonly", readonly)?,
+                None if cfg!(unix) => dict.set_item("mode", py.None())?,
+                None => dict.set_item("readonly", py.None())?,
+            }
+        }
+        if let Some(blame) = &self.blame {
+            let (commit, author) = blame.clone().unzip();
+            dict.set_item("commit", commit)?;
+            dict.set_item("author", author)?;
+        }
+        Ok(dict)
+    }
+
+    /// `to_dict`, as a JSON object.
+    fn to_json(&self) -> serde_json::Value {
+        use serde_json::{json, Map, Value};
+
+        let mut object = Map::new();
+        let mut set = |key: &str, value: Value| {
+            object.insert(key.to_string(), value);
+        };
+        set("file", json!(self.file));
+        set("line_number", json!(self.line_number));
+        if let Some(id) = &self.id {
+            set("id", json!(id));
+        }
+        if let Some(label) = &self.label {
+            set("label", json!(label));
+        }
+        if let Some(named) = &self.named {
+            for (name, value) in named {
+                set(name, json!(value));
+            }
+            return Value::Object(object);
+        }
+        set("line", json!(self.line));
+        let spans: Vec<Value> = self
+            .spans
+            .iter()
+            .map(|&(start, end)| json!({"start": start, "end": end}))
+            .collect();
+        set("matches", Value::Array(spans));
+        if let Some(truncated) = self.match_truncated {
+            set("match_truncated", json!(truncated));
+        }
+        if let Some(snippet) = &self.snippet {
+            set("snippet", json!(snippet));
+        }
+        if let Some(is_dir) = self.is_dir {
+            set("is_dir", json!(is_dir));
+        }
+        if let Some(which_pattern) = self.which_pattern {
+            set("which_pattern", json!(which_pattern));
+        }
+        if let Some(line_ending) = self.line_ending {
+            set("line_ending", json!(line_ending));
+        }
+        if let Some(paragraph) = &self.paragraph {
+            set("paragraph", json!(paragraph));
+        }
+        if let Some(offset) = self.byte_offset {
+            set("byte_offset", json!(offset));
+        }
+        if let Some(column) = self.column {
+            set("column", json!(column));
+        }
+        if let Some(offset) = self.offset {
+            set("offset", json!(offset));
+        }
+        if let Some(file_spans) = &self.file_spans {
+            set("file_spans", json!(file_spans));
+        }
+        if let Some(segments) = &self.segments {
+            set("segments", json!(segments));
+        }
+        if let Some(captures) = &self.captures {
+            set("captures", json!(captures));
+        }
+        if let Some(preview) = &self.hex_preview {
+            set("hex_preview", json!(preview));
+        }
+        if let Some(before) = &self.context_before {
+            set("context_before", json!(before));
+        }
+        if let Some(after) = &self.context_after {
+            set("context_after", json!(after));
+        }
+        if let Some(heading) = &self.context_heading {
+            set("context_heading", json!(heading));
+        }
+        if let Some(mode) = self.mode {
+            match mode {
+                #[cfg(unix)]
+                Some(FileMode::Bits(bits)) => set("mode", json!(format!("0o{:o}", bits))),
+                #[cfg(not(unix))]
+                Some(FileMode::ReadOnly(readonly)) => set("readonly", json!(readonly)),
+                None if cfg!(unix) => set("mode", Value::Null),
+                None => set("readonly", Value::Null),
+            }
+        }
+        if let Some(blame) = &self.blame {
+            let (commit, author) = blame.clone().unzip();
+            set("commit", json!(commit));
+            set("author", json!(author));
+        }
+        Value::Object(object)
+    }
+}
+
+fn matches_to_list<'py>(py: Python<'py>, matches: &[SearchMatch]) -> PyResult<Bound<'py, PyList>> {
+    let results = PyList::empty_bound(py);
+    for m in matches {
+        results.append(m.to_dict(py)?)?;
+    }
+    Ok(results)
+}
+
+/// `matches` split by file, files in order of their first match.
+fn group_by_file(matches: Vec<SearchMatch>) -> Vec<(Option<String>, Vec<SearchMatch>)> {
+    let mut files: Vec<(Option<String>, Vec<SearchMatch>)> = Vec::new();
+    let mut index: HashMap<Option<String>, usize> = HashMap::new();
+    for m in matches {
+        let i = *index.entry(m.file.clone()).or_insert_with(|| {
+            files.push((m.file.clone(), Vec::new()));
+            files.len() - 1
+        });
+        files[i].1.push(m);
+    }
+    files
+}
+
+fn matcher_builder(options: &SearchOptions) -> RegexMatcherBuilder {
+    let mut builder = RegexMatcherBuilder::new();
+    builder
+        .case_insensitive(options.case_insensitive)
+        .case_smart(options.smart_case)
+        // Wraps the pattern after any `fixed_strings` escaping
+        .word(options.word)
+        .unicode(options.unicode.0)
+        .multi_line(options.multiline)
+        .dot_matches_new_line(options.dot_matches_new_line.unwrap_or(options.multiline))
+        .crlf(options.crlf);
+    builder
+}
+
+/// `pattern` escaped to match literally when `fixed_strings` is set.
+fn literal_pattern(options: &SearchOptions, pattern: Cow<'_, str>) -> String {
+    if options.fixed_strings {
+        regex::escape(&pattern)
+    } else {
+        pattern.into_owned()
+    }
+}
+
+/// The error for a search that found more than `limit` matches under
+/// `limit_behavior="error"`.
+fn limit_exceeded(limit: usize) -> PyErr {
+    LimitExceeded::new_err(format!("Result limit of {} matches exceeded", limit))
+}
+
+fn invalid_regex(e: grep_regex::Error) -> PyErr {
+    PatternError::new_err(format!("Invalid regex: {}", e))
+}
+
+/// The error for a `path` that can't be searched as asked, saying why:
+/// `PathNotFoundError` if nothing is there, or else `SearchIOError` for a
+/// broken symlink, a directory where a file was needed, or something that's
+/// neither, such as a device or socket.
+fn path_error(path: &Path) -> PyErr {
+    let display = path.display();
+    match fs::symlink_metadata(path) {
+        Err(_) => PathNotFoundError::new_err(format!("No such file or directory: {}", display)),
+        Ok(meta) if meta.file_type().is_symlink() && !path.exists() => {
+            SearchIOError::new_err(format!("Broken symlink: {}", display))
+        }
+        Ok(_) if path.is_dir() => SearchIOError::new_err(format!("Is a directory: {}", display)),
+        Ok(_) => SearchIOError::new_err(format!("Not a file or directory: {}", display)),
+    }
+}
+
+/// Map a failed search to `BinaryError` if it hit a line that isn't UTF-8,
+/// or `SearchIOError` otherwise.
+fn search_error(e: io::Error) -> PyErr {
+    if e.kind() == io::ErrorKind::InvalidData {
+        BinaryError::new_err(format!("Search error: {}", e))
+    } else {
+        SearchIOError::new_err(format!("Search error: {}", e))
+    }
+}
+
+impl RipGrep {
+    fn build(
+        pattern: String,
+        options: SearchOptions,
+        matcher: RegexMatcher,
+        sub_patterns: Option<SubPatterns>,
+    ) -> PyResult<Self> {
+        let mut rg = RipGrep {
+            pattern,
+            options,
+            matcher: Arc::new(matcher),
+            sub_patterns: sub_patterns.map(Arc::new),
+            named_regex: None,
+            exclusions: Arc::default(),
+            heading_matcher: None,
+            record_separator: None,
+            types: Arc::new(Types::empty()),
+            overrides: Arc::default(),
+            bytes_pattern: false,
+            ignore_rules: None,
+        };
+        // Validate the remaining patterns and globs immediately too
+        rg.record_separator = rg.build_record_separator()?.map(Arc::new);
+        build_globset(&rg.options.follow_links_matching)?;
+        rg.overrides_for(Path::new("."))?;
+        rg.types = Arc::new(rg.build_types()?);
+        rg.build_encoding()?;
+
+        if !rg.options.line_number.0 {
+            let needs_lines = [
+                ("before_context", rg.options.before_context > 0),
+                ("after_context", rg.options.after_context > 0),
+                ("blame", rg.options.blame),
+                ("paragraph", rg.options.paragraph),
+                ("context_pattern", rg.options.context_pattern.is_some()),
+                ("normalize", rg.options.normalize.is_some()),
+                ("collapse_whitespace", rg.options.collapse_whitespace),
+            ];
+            if let Some((name, _)) = needs_lines.iter().find(|(_, set)| *set) {
+                return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                    "line_number=False can't be combined with {}, which needs line numbers",
+                    name
+                )));
+            }
+        }
+
+        if !rg.options.named_output.is_empty() || rg.options.captures {
+            let regex = rg.capture_regex()?;
+            for name in &rg.options.named_output {
+                if !regex.capture_names().flatten().any(|n| n == name) {
+                    return Err(PatternError::new_err(format!(
+                        "Unknown capture group in named_output: {}",
+                        name
+                    )));
+                }
+            }
+            rg.named_regex = Some(Arc::new(regex));
+        }
+
+        let builder = matcher_builder(&rg.options);
+        let exclusions = rg
+            .options
+            .exclude_if_matches
+            .iter()
+            .map(|pattern| {
+                builder
+                    .build(pattern)
+                    .map_err(|e| PatternError::new_err(format!("Invalid exclusion pattern: {}", e)))
+            })
+            .collect::<PyResult<Vec<_>>>()?;
+        rg.exclusions = Arc::new(exclusions);
+
+        if let Some(pattern) = &rg.options.context_pattern {
+            let heading_matcher = builder
+                .build(pattern)
+                .map_err(|e| PatternError::new_err(format!("Invalid context pattern: {}", e)))?;
+            rg.heading_matcher = Some(Arc::new(heading_matcher));
+        }
+        Ok(rg)
+    }
+
+    /// Fill in `context_heading` for `matches` found in `content`: the
+    /// nearest line before each match that matches `context_pattern`.
+    fn attach_headings(&self, content: &[u8], matches: &mut [SearchMatch]) {
+        let Some(heading_matcher) = &self.heading_matcher else {
+            return;
+        };
+
+        let headings: Vec<(u64, &[u8])> = content
+            .split(|&b| b == b'\n')
+            .zip(1..)
+            .filter(|(line, _)| heading_matcher.is_match(line).unwrap_or(false))
+            .map(|(line, line_number)| (line_number, line))
+            .collect();
+
+        for m in matches {
+            let heading = m.line_number.and_then(|line_number| {
+                let before = headings.partition_point(|&(n, _)| n < line_number);
+                before.checked_sub(1).map(|i| {
+                    let line = String::from_utf8_lossy(headings[i].1);
+                    line.trim_end().to_string()
+                })
+            });
+            m.context_heading = Some(heading);
+        }
+    }
+
+    /// Fill in `paragraph` for `matches` found in `content`: the run of
+    /// non-blank lines around each match, joined with newlines.
+    fn attach_paragraphs(&self, content: &[u8], matches: &mut [SearchMatch]) {
+        if !self.options.paragraph {
+            return;
+        }
+
+        let content = String::from_utf8_lossy(content);
+        let lines: Vec<&str> = content.lines().collect();
+        let is_blank = |i: usize| lines[i].trim().is_empty();
+
+        for m in matches {
+            let paragraph = m.line_number.and_then(|line_number| {
+                let index = usize::try_from(line_number).ok()?.checked_sub(1)?;
+                if index >= lines.len() {
+                    return None;
+                }
+                let mut start = index;
+                while start > 0 && !is_blank(start - 1) {
+                    start -= 1;
+                }
+                let mut end = index + 1;
+                while end < lines.len() && !is_blank(end) {
+                    end += 1;
+                }
+                Some(lines[start..end].join("\n"))
+            });
+            m.paragraph = Some(paragraph);
+        }
+    }
+
+    /// Whether `line` matches one of the `exclude_if_matches` patterns and
+    /// should be dropped despite matching the pattern.
+    fn is_excluded(&self, line: &str) -> bool {
+        self.exclusions
+            .iter()
+            .any(|exclusion| exclusion.is_match(line.as_bytes()).unwrap_or(false))
+    }
+
+    /// The pattern compiled for capture extraction, with the same flags as
+    /// the search matcher. Lines are `str`, so with `unicode` off a pattern
+    /// that could then match invalid UTF-8, like `.`, stays Unicode-aware.
+    fn capture_regex(&self) -> PyResult<regex::Regex> {
+        let pattern = if self.options.word {
+            // What grep-regex's `word` wraps the pattern in
+            format!(r"\b{{start-half}}(?:{})\b{{end-half}}", self.pattern)
+        } else {
+            self.pattern.clone()
+        };
+        let mut builder = regex::RegexBuilder::new(&pattern);
+        builder
+            .case_insensitive(
+                self.options.case_insensitive
+                    || (self.options.smart_case && !has_uppercase_literal(&self.pattern)),
+            )
+            .multi_line(self.options.multiline)
+            .dot_matches_new_line(
+                self.options
+                    .dot_matches_new_line
+                    .unwrap_or(self.options.multiline),
+            )
+            .crlf(self.options.crlf);
+        builder
+            .clone()
+            .unicode(self.options.unicode.0)
+            .build()
+            .or_else(|_| builder.unicode(true).build())
+            .map_err(|e| PatternError::new_err(format!("Invalid regex: {}", e)))
+    }
+
+    /// The contents of the file at `path` with `replacement` substituted in
+    /// the lines the search reports, and how many replacements that made, or
+    /// `None` if it isn't UTF-8. A `multiline` match is replaced across all
+    /// the lines it covers.
+    fn replace_reported(
+        &self,
+        ctx: &mut SearchContext,
+        path: &Path,
+        regex: &regex::Regex,
+        replacement: &str,
+    ) -> PyResult<Option<(String, usize)>> {
+        let Ok(contents) = fs::read_to_string(path) else {
+            return Ok(None);
+        };
+        let mut matches = Vec::new();
+        self.search_file_impl(ctx, path, &mut matches)?;
+        // First and last line of each reported match
+        let reported: HashMap<u64, u64> = matches
+            .iter()
+            .filter_map(|m| {
+                let start = m.line_number?;
+                Some((start, start + m.line.matches('\n').count() as u64))
+            })
+            .collect();
+
+        let lines: Vec<&str> = contents.split_inclusive('\n').collect();
+        let mut output = String::with_capacity(contents.len());
+        let mut changes = 0;
+        let mut index = 0;
+        while index < lines.len() {
+            let Some(&last) = reported.get(&(index as u64 + 1)) else {
+                output.push_str(lines[index]);
+                index += 1;
+                continue;
+            };
+            let end = usize::try_from(last).map_or(lines.len(), |last| last.min(lines.len()));
+            let block = lines[index..end].concat();
+            let body = block.trim_end_matches(['\n', '\r']);
+            changes += regex.find_iter(body).count();
+            output.push_str(&regex.replace_all(body, replacement));
+            output.push_str(&block[body.len()..]);
+            index = end;
+        }
+        Ok(Some((output, changes)))
+    }
+
+    /// `include_globs` and `exclude_globs` as walker overrides, relative to
+    /// `root`. Following ripgrep, a file matching an include glob is
+    /// searched even if an ignore file would skip it.
+    fn build_overrides(&self, root: &Path) -> PyResult<Override> {
+        let invalid_glob = |e: ignore::Error| PatternError::new_err(format!("Invalid glob: {}", e));
+
+        let mut builder = OverrideBuilder::new(root);
+        for glob in &self.options.include_globs {
+            builder.add(glob).map_err(invalid_glob)?;
+        }
+        for glob in &self.options.exclude_globs {
+            builder.add(&format!("!{}", glob)).map_err(invalid_glob)?;
+        }
+        builder.build().map_err(invalid_glob)
+    }
+
+    /// `build_overrides` for `root`, reused from the last search if it
+    /// walked the same root.
+    fn overrides_for(&self, root: &Path) -> PyResult<Override> {
+        let mut cached = self.overrides.lock().unwrap();
+        if let Some((cached_root, overrides)) = &*cached {
+            if cached_root == root {
+                return Ok(overrides.clone());
+            }
+        }
+        let overrides = self.build_overrides(root)?;
+        *cached = Some((root.to_path_buf(), overrides.clone()));
+        Ok(overrides)
+    }
+
+    /// `types` and `types_not` selected from ripgrep's default type
+    /// definitions.
+    fn build_types(&self) -> PyResult<Types> {
+        let mut builder = TypesBuilder::new();
+        builder.add_defaults();
+        for name in &self.options.types {
+            builder.select(name);
+        }
+        for name in &self.options.types_not {
+            builder.negate(name);
+        }
+        builder.build().map_err(|e| {
+            PatternError::new_err(format!(
+                "Invalid file types (types={:?}, types_not={:?}): {}",
+                self.options.types, self.options.types_not, e
+            ))
+        })
+    }
+
+    fn build_encoding(&self) -> PyResult<Option<Encoding>> {
+        let Some(label) = &self.options.encoding else {
+            return Ok(None);
+        };
+        // Like `rg --encoding auto`: UTF-8, apart from a BOM's say-so
+        if label == "auto" {
+            return Ok(None);
+        }
+
+        Encoding::new(label).map(Some).map_err(|e| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Invalid encoding: {}", e))
+        })
+    }
+
+    /// A copy of this instance with `overrides` applied on top of its
+    /// options, recompiling the matcher so flags like `case_insensitive`
+    /// take effect.
+    fn with_overrides(&self, overrides: &Bound<'_, PyDict>) -> PyResult<Self> {
+        // The pattern was escaped or normalized once, when it was built
+        for key in ["fixed_strings", "normalize"] {
+            if overrides.contains(key)? {
+                return Err(PyErr::new::<pyo3::exceptions::PyTypeError, _>(format!(
+                    "{} shapes the pattern, so it can't be overridden per path",
+                    key
+                )));
+            }
+        }
+        let mut options = self.options.clone();
+        options.update(Some(overrides))?;
+        let mut builder = matcher_builder(&options);
+        if self.bytes_pattern {
+            builder.unicode(false);
+        }
+        let matcher = builder.build(&self.pattern).map_err(invalid_regex)?;
+
+        let mut rg = RipGrep::build(self.pattern.clone(), options, matcher, None)?;
+        rg.sub_patterns = self.sub_patterns.clone();
+        rg.bytes_pattern = self.bytes_pattern;
+        Ok(rg)
+    }
+
+    fn build_record_separator(&self) -> PyResult<Option<RegexMatcher>> {
+        let Some(separator) = &self.options.record_separator else {
+            return Ok(None);
+        };
+
+        // Multi-line so that `^` anchors at the start of every line
+        RegexMatcherBuilder::new()
+            .multi_line(true)
+            .build(separator)
+            .map(Some)
+            .map_err(|e| PatternError::new_err(format!("Invalid record separator: {}", e)))
+    }
+
+    /// Search a buffer already in memory, as `search_bytes` and
+    /// `search_text` do.
+    fn search_slice_impl(&self, bytes: &[u8]) -> PyResult<Vec<SearchMatch>> {
+        let rewritten = self.rewrite_bytes(bytes);
+        let mut ctx = self.context(true)?;
+        let mut matches = ctx
+            .run(self, None, |searcher, matcher, sink| {
+                searcher.search_slice(matcher, &rewritten, sink)
+            })
+            .map_err(search_error)?;
+        self.restore_lines(bytes, &mut matches);
+        self.attach_headings(bytes, &mut matches);
+        self.attach_paragraphs(bytes, &mut matches);
+        if let Err(err) = self.check_max_count(&ctx) {
+            Python::with_gil(|py| {
+                err.value_bound(py)
+                    .setattr("partial_results", matches_to_list(py, &matches)?)
+            })?;
+            return Err(err);
+        }
+        Ok(matches)
+    }
+
+    /// `line` without its terminator: exactly `\r\n` or `\n` under `crlf`,
+    /// otherwise all trailing whitespace.
+    fn trim_line<'a>(&self, line: &'a str) -> &'a str {
+        if !self.options.crlf {
+            return line.trim_end();
+        }
+        let line = line.strip_suffix('\n').unwrap_or(line);
+        line.strip_suffix('\r').unwrap_or(line)
+    }
+
+    /// `trim_line` for a line's raw bytes.
+    fn trim_line_bytes<'a>(&self, line: &'a [u8]) -> &'a [u8] {
+        if !self.options.crlf {
+            return trim_end_bytes(line);
+        }
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        line.strip_suffix(b"\r").unwrap_or(line)
+    }
+
+    /// Turn a matched line (or record) into a `SearchMatch`, applying the
+    /// per-line output options.
+    fn make_match(
+        &self,
+        matcher: &RegexMatcher,
+        file: Option<&str>,
+        line_number: u64,
+        line: &str,
+    ) -> SearchMatch {
+        let line_ending = self.options.line_ending.then(|| {
+            if line.ends_with("\r\n") {
+                "crlf"
+            } else if line.ends_with('\n') {
+                "lf"
+            } else {
+                "none"
+            }
+        });
+        let line = self.trim_line(line);
+        let spans = if self.options.overlapping {
+            overlapping_spans(matcher, line.as_bytes())
+        } else {
+            match_spans(matcher, line)
+        };
+        let segments = self.options.split.then(|| {
+            // Overlapping matches can't cut the line into pieces
+            if self.options.overlapping {
+                split_on_spans(line, &match_spans(matcher, line))
+            } else {
+                split_on_spans(line, &spans)
+            }
+        });
+        let hex_preview = self
+            .options
+            .hex_preview
+            .then(|| hex_preview(matcher, line.as_bytes()));
+        let raw_line = self.options.raw_bytes.then(|| line.as_bytes().to_vec());
+        let column = self.options.column.then(|| {
+            // Characters up to the match, so multibyte text before it counts once
+            spans
+                .first()
+                .map(|&(start, _)| line.char_indices().take_while(|(i, _)| *i < start).count() + 1)
+        });
+        let which_pattern = self.sub_patterns.as_ref().map(|sub| sub.which(line));
+        let caps = self
+            .named_regex
+            .as_ref()
+            .and_then(|regex| regex.captures(line));
+        let named = (!self.options.named_output.is_empty()).then(|| {
+            self.options
+                .named_output
+                .iter()
+                .map(|name| {
+                    let value = caps.as_ref().and_then(|c| c.name(name));
+                    (name.clone(), value.map(|m| m.as_str().to_string()))
+                })
+                .collect()
+        });
+        let captures = self.options.captures.then(|| {
+            let groups = caps.iter().flat_map(|c| c.iter().skip(1));
+            groups
+                .take(self.options.max_groups.unwrap_or(usize::MAX))
+                .map(|group| group.map(|m| m.as_str().to_string()))
+                .collect()
+        });
+        let snippet = self
+            .options
+            .snippet_radius
+            .map(|radius| snippet_around_match(matcher, line, radius));
+        let (line, match_truncated) = match self.options.max_match_len {
+            Some(limit) => {
+                let (line, truncated) = truncate_long_match(matcher, line, limit);
+                (line, Some(truncated))
+            }
+            None => (line.to_string(), None),
+        };
+
+        let mut m = SearchMatch {
+            file: file.map(str::to_string),
+            line_number: self.options.line_number.0.then_some(line_number),
+            line,
+            match_truncated,
+            snippet,
+            is_dir: self.options.match_dirs.then_some(false),
+            which_pattern,
+            named,
+            line_ending,
+            column,
+            spans,
+            segments,
+            hex_preview,
+            raw_line,
+            captures,
+            label: self.options.label.clone(),
+            ..Default::default()
+        };
+        if self.options.with_ids {
+            m.id = Some(m.stable_id());
+        }
+        m
+    }
+
+    /// Search `root` on a background thread, streaming matches to the
+    /// returned generator.
+    fn spawn_search(&self, root: PathBuf, archives: bool) -> SearchGenerator {
+        // Bounded, so a slow consumer applies backpressure to the walk
+        let (sender, receiver) = mpsc::sync_channel(256);
+        let rg = self.clone();
+
+        thread::spawn(move || {
+            let result = (|| -> PyResult<()> {
+                let mut permit = Some(limiter::acquire());
+                let mut ctx = rg.context(root.is_file())?;
+                ctx.archives = archives;
+                let mut remaining = rg.options.max_total.unwrap_or(usize::MAX);
+                let raises = rg.options.limit_behavior == LimitBehavior::Error;
+                let mut send = |file_matches: Vec<SearchMatch>| -> PyResult<bool> {
+                    for m in file_matches {
+                        if remaining == 0 {
+                            // The matches within the limit were already yielded
+                            if raises {
+                                return Err(limit_exceeded(rg.options.max_total.unwrap_or(0)));
+                            }
+                            break;
+                        }
+                        remaining -= 1;
+                        // A failed send means the generator was dropped
+                        let sent = match sender.try_send(Ok(m)) {
+                            Ok(()) => true,
+                            Err(TrySendError::Full(m)) => {
+                                // Waiting on the consumer isn't searching, and
+                                // the consumer may itself be waiting for the
+                                // slot, so give it up meanwhile
+                                drop(permit.take());
+                                let sent = sender.send(m).is_ok();
+                                permit.get_or_insert_with(limiter::acquire);
+                                sent
+                            }
+                            Err(TrySendError::Disconnected(_)) => false,
+                        };
+                        if !sent {
+                            return Ok(false);
+                        }
+                    }
+                    // Under "error", walk on to find out whether it's exceeded
+                    Ok(remaining > 0 || raises)
+                };
+
+                if root.is_file() {
+                    let mut matches = Vec::new();
+                    rg.search_entry(&mut ctx, &root, &mut matches)?;
+                    send(matches)?;
+                    rg.check_max_count(&ctx)?;
+                } else if root.is_dir() {
+                    rg.walk_directory(&mut ctx, &root, &mut send)?;
+                } else {
+                    return Err(path_error(&root));
+                }
+                Ok(())
+            })();
+
+            if let Err(err) = result {
+                let _ = sender.send(Err(err));
+            }
+        });
+
+        SearchGenerator {
+            receiver: Mutex::new(receiver),
+        }
+    }
+
+    fn build_searcher(&self, single_file: bool) -> Searcher {
+        self.searcher_builder(single_file).build()
+    }
+
+    fn searcher_builder(&self, single_file: bool) -> SearcherBuilder {
+        let mmap = if self.uses_mmap(single_file) {
+            // SAFETY: a memory map can fault if the file is truncated while
+            // it's being searched; like ripgrep we accept that risk.
+            unsafe { MmapChoice::auto() }
+        } else {
+            MmapChoice::never()
+        };
+
+        let binary = match (self.options.binary_quit_byte, self.options.binary) {
+            (Some(byte), _) => BinaryDetection::quit(byte),
+            (None, BinaryMode::Skip) => BinaryDetection::quit(b'\x00'),
+            (None, BinaryMode::Text) => BinaryDetection::convert(b'\x00'),
+            (None, BinaryMode::Search) => BinaryDetection::none(),
+        };
+
+        let line_terminator = if self.options.crlf {
+            LineTerminator::crlf()
+        } else {
+            LineTerminator::byte(b'\n')
+        };
+
+        let mut builder = SearcherBuilder::new();
+        builder
+            .memory_map(mmap)
+            .binary_detection(binary)
+            .line_terminator(line_terminator)
+            .multi_line(self.options.multiline)
+            .invert_match(self.options.invert_match)
+            .before_context(self.options.before_context)
+            .after_context(self.options.after_context)
+            // Validated when the instance was built
+            .encoding(self.build_encoding().ok().flatten())
+            .line_number(self.counts_lines())
+            // Report every line to the sink so it can count and measure them
+            .passthru(self.sees_every_line());
+        builder
+    }
+
+    /// Whether the searcher tracks line numbers: unless `line_number` is
+    /// off and no limit is kept by line.
+    fn counts_lines(&self) -> bool {
+        self.options.line_number.0
+            || self.options.max_lines.is_some()
+            || self.options.report_lines_scanned
+    }
+
+    fn has_context(&self) -> bool {
+        self.options.before_context > 0 || self.options.after_context > 0
+    }
+
+    /// Whether the sink needs every line, not just matches, to enforce
+    /// `max_lines` or `skip_long_lines` or to count `report_lines_scanned`.
+    fn sees_every_line(&self) -> bool {
+        self.options.max_lines.is_some()
+            || self.options.skip_long_lines.is_some()
+            || self.options.report_lines_scanned
+    }
+
+    /// Whether files are memory mapped for this search. Under
+    /// `read_strategy="auto"` this follows ripgrep: map a file searched on
+    /// its own, read buffered while walking a directory.
+    fn uses_mmap(&self, single_file: bool) -> bool {
+        // grep-searcher never memory maps on macOS
+        if cfg!(target_os = "macos") {
+            return false;
+        }
+
+        match self.options.read_strategy {
+            ReadStrategy::Auto => single_file,
+            ReadStrategy::Mmap => true,
+            ReadStrategy::Buffered => false,
+        }
+    }
+
+    fn context(&self, single_file: bool) -> PyResult<SearchContext> {
+        Ok(SearchContext {
+            matcher: Arc::clone(&self.matcher),
+            record_separator: self.record_separator.clone(),
+            searcher: self.build_searcher(single_file),
+            line_range: None,
+            base_offset: None,
+            skipped: None,
+            lines_scanned: None,
+            over_max_count: false,
+            errors: Vec::new(),
+            files_searched: Arc::default(),
+            archives: false,
+        })
+    }
+
+    fn collect(&self, py: Python<'_>, path: &Path) -> PyResult<Vec<SearchMatch>> {
+        Ok(self.collect_reporting(py, path, None, None)?.0)
+    }
+
+    /// `collect`, also returning the context searched with, for what it
+    /// gathered along the way: the files `on_error="collect"` set aside and
+    /// how many files were searched. `progress` is reported to while a
+    /// directory is walked, and only matches within `line_range` of each
+    /// file are kept.
+    fn collect_reporting(
+        &self,
+        py: Python<'_>,
+        path: &Path,
+        progress: Option<&Progress<'_, '_>>,
+        line_range: Option<RangeInclusive<u64>>,
+    ) -> PyResult<(Vec<SearchMatch>, SearchContext)> {
+        let _permit = py.allow_threads(limiter::acquire);
+        let mut ctx = self.context(path.is_file())?;
+        if line_range.is_some() {
+            // The range is kept by line number, whether or not they're reported
+            ctx.searcher = self
+                .searcher_builder(path.is_file())
+                .line_number(true)
+                .build();
+            ctx.line_range = line_range;
+        }
+        let mut matches = Vec::new();
+
+        let result = if path.is_file() {
+            if self.too_large(path)? {
+                return Ok((matches, ctx));
+            }
+            self.search_file_impl(&mut ctx, path, &mut matches)
+                .and_then(|()| self.apply_limit(&mut matches, self.options.max_per_dir))
+                .and_then(|()| self.apply_limit(&mut matches, self.options.max_total))
+                .and_then(|()| self.check_max_count(&ctx))
+        } else if path.is_dir() {
+            self.search_directory_impl(py, &mut ctx, path, &mut matches, progress)
+        } else {
+            Err(path_error(path))
+        };
+
+        if let Err(err) = result {
+            // What was kept within the limits is handed back with the error
+            if err.is_instance_of::<LimitExceeded>(py) {
+                err.value_bound(py)
+                    .setattr("partial_results", matches_to_list(py, &matches)?)?;
+            }
+            return Err(err);
+        }
+
+        if self.options.stable {
+            // By path, line number, then where on the line the match starts,
+            // which tells apart the several results `overlapping` or
+            // `multiline` can give one line
+            matches.sort_by_cached_key(|m| {
+                let file = m.file.clone().map(PathBuf::from);
+                (file, m.line_number, m.spans.clone())
+            });
+        }
+        // Stable sorts, so each file's matches keep their line order
+        match self.options.sort {
+            Some(SortBy::Path) => {
+                matches.sort_by_cached_key(|m| m.file.clone().map(PathBuf::from));
+            }
+            Some(SortBy::Modified) => matches.sort_by_cached_key(|m| {
+                let modified = m
+                    .file
+                    .as_ref()
+                    .and_then(|file| fs::metadata(file).and_then(|meta| meta.modified()).ok());
+                (modified, m.file.clone().map(PathBuf::from))
+            }),
+            None => {}
+        }
+        Ok((matches, ctx))
+    }
+
+    /// `results` as they are, or under `on_error="collect"` a dict of them as
+    /// `key` plus `errors`: a `{"file", "error"}` dict for each file that
+    /// couldn't be searched.
+    fn with_errors(
+        &self,
+        py: Python<'_>,
+        key: &str,
+        results: Bound<'_, PyList>,
+        errors: Vec<(String, String)>,
+    ) -> PyResult<PyObject> {
+        if self.options.on_error != OnError::Collect {
+            return Ok(results.into_any().unbind());
+        }
+        let failed = PyList::empty_bound(py);
+        for (file, error) in errors {
+            let dict = PyDict::new_bound(py);
+            dict.set_item("file", file)?;
+            dict.set_item("error", error)?;
+            failed.append(dict)?;
+        }
+        let dict = PyDict::new_bound(py);
+        dict.set_item(key, results)?;
+        dict.set_item("errors", failed)?;
+        Ok(dict.into_any().unbind())
+    }
+
+    /// Deal with a file a walk couldn't search, as `on_error` says.
+    fn file_error(&self, ctx: &mut SearchContext, path: &Path, e: PyErr) -> PyResult<()> {
+        match self.options.on_error {
+            OnError::Print => eprintln!("Error searching {}: {}", path.display(), e),
+            OnError::Ignore => {}
+            OnError::Raise => return Err(e),
+            OnError::Collect => ctx.errors.push((self.display_path(path), e.to_string())),
+        }
+        Ok(())
+    }
+
+    /// `collect` each of `paths` in turn, sharing the compiled matcher.
+    fn collect_all<'a>(
+        &self,
+        py: Python<'_>,
+        paths: impl IntoIterator<Item = &'a str>,
+    ) -> PyResult<Vec<SearchMatch>> {
+        let mut matches = Vec::new();
+        for path in paths {
+            matches.extend(self.collect(py, Path::new(path))?);
+        }
+        Ok(matches)
+    }
+
+    /// Search one file found by a walk, looking inside it if it's an
+    /// archive and the search asked for that.
+    fn search_entry(
+        &self,
+        ctx: &mut SearchContext,
+        path: &Path,
+        matches: &mut Vec<SearchMatch>,
+    ) -> PyResult<()> {
+        match ArchiveKind::of(path) {
+            Some(kind) if ctx.archives => self.search_archive(ctx, path, kind, matches),
+            _ => self.search_file_impl(ctx, path, matches),
+        }
+    }
+
+    /// Search the members of a tar archive, or the contents of a gzip file,
+    /// streaming them through the searcher without unpacking to disk.
+    fn search_archive(
+        &self,
+        ctx: &mut SearchContext,
+        path: &Path,
+        kind: ArchiveKind,
+        matches: &mut Vec<SearchMatch>,
+    ) -> PyResult<()> {
+        let archive_error = |e: io::Error| SearchIOError::new_err(format!("Archive error: {}", e));
+        ctx.files_searched.fetch_add(1, Ordering::Relaxed);
+        ctx.over_max_count = false;
+        let file = fs::File::open(path).map_err(archive_error)?;
+        let display = self.display_path(path);
+
+        let reader: Box<dyn io::Read> = match kind {
+            ArchiveKind::Tar => Box::new(file),
+            ArchiveKind::TarGz | ArchiveKind::Gz => Box::new(GzDecoder::new(file)),
+        };
+
+        if kind == ArchiveKind::Gz {
+            let file_matches = ctx
+                .run(self, Some(&*display), |searcher, matcher, sink| {
+                    searcher.search_reader(matcher, reader, sink)
+                })
+                .map_err(archive_error)?;
+            matches.extend(file_matches);
+            return Ok(());
+        }
+
+        let mut archive = tar::Archive::new(reader);
+        for entry in archive.entries().map_err(archive_error)? {
+            let entry = entry.map_err(archive_error)?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let member = entry.path().map_err(archive_error)?;
+            let qualified = format!("{}!{}", display, member.display());
+
+            let file_matches = ctx
+                .run(self, Some(&*qualified), |searcher, matcher, sink| {
+                    searcher.search_reader(matcher, entry, sink)
+                })
+                .map_err(archive_error)?;
+            matches.extend(file_matches);
+        }
+        Ok(())
+    }
+
+    /// Cut `matches` down to `limit`, raising `LimitExceeded` instead of
+    /// dropping anything if `limit_behavior` is `"error"`.
+    fn apply_limit(&self, matches: &mut Vec<SearchMatch>, limit: Option<usize>) -> PyResult<()> {
+        let Some(limit) = limit else {
+            return Ok(());
+        };
+        if matches.len() <= limit {
+            return Ok(());
+        }
+
+        matches.truncate(limit);
+        match self.options.limit_behavior {
+            LimitBehavior::Truncate => Ok(()),
+            LimitBehavior::Error => Err(limit_exceeded(limit)),
+        }
+    }
+
+    /// Raise `LimitExceeded` if the file last searched with `ctx` had more
+    /// than `max_count` matches, which only `limit_behavior="error"` checks.
+    fn check_max_count(&self, ctx: &SearchContext) -> PyResult<()> {
+        if ctx.over_max_count {
+            return Err(limit_exceeded(self.options.max_count.unwrap_or(0)));
+        }
+        Ok(())
+    }
+
+    fn search_file_impl(
+        &self,
+        ctx: &mut SearchContext,
+        path: &Path,
+        matches: &mut Vec<SearchMatch>,
+    ) -> PyResult<()> {
+        ctx.files_searched.fetch_add(1, Ordering::Relaxed);
+        // Nothing about the last file carries over, even if this one is
+        // passed over before it's read
+        ctx.skipped = None;
+        ctx.lines_scanned = None;
+        ctx.over_max_count = false;
+        if self.options.text_only && self.looks_binary(path) {
+            return Ok(());
+        }
+
+        let start = matches.len();
+        if let Some(separator) = &ctx.record_separator {
+            let skipped = self.search_file_records(ctx, separator, path, matches)?;
+            ctx.skipped = skipped;
+            ctx.lines_scanned = None;
+            // Under "error", a record past `max_count` was kept to show it
+            if let Some(max) = self.options.max_count {
+                if matches.len() - start > max {
+                    matches.truncate(start + max);
+                    ctx.over_max_count = true;
+                }
+            }
+        } else {
+            let file = self.display_path(path);
+            let file_matches = if self.rewrites_text() {
+                // Rewriting the text needs all of it in memory
+                let original = fs::read(path).map_err(search_error)?;
+                let content = self.rewrite_bytes(&original);
+                let mut file_matches = ctx
+                    .run(self, Some(&*file), |searcher, matcher, sink| {
+                        searcher.search_slice(matcher, &content, sink)
+                    })
+                    .map_err(search_error)?;
+                self.restore_lines(&original, &mut file_matches);
+                file_matches
+            } else {
+                ctx.run(self, Some(&*file), |searcher, matcher, sink| {
+                    searcher.search_path(matcher, path, sink)
+                })
+                .map_err(search_error)?
+            };
+            matches.extend(file_matches);
+        }
+
+        if self.options.blame {
+            attach_blame(path, &mut matches[start..]);
+        }
+        if (self.heading_matcher.is_some() || self.options.paragraph) && matches.len() > start {
+            // Both need lines around the match, which the sink never saw
+            let content = fs::read(path).map_err(search_error)?;
+            self.attach_headings(&content, &mut matches[start..]);
+            self.attach_paragraphs(&content, &mut matches[start..]);
+        }
+        if self.options.include_mode && matches.len() > start {
+            let mode = file_mode(path);
+            for m in &mut matches[start..] {
+                m.mode = Some(mode);
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether text is rewritten before matching, by `normalize` or
+    /// `collapse_whitespace`.
+    fn rewrites_text(&self) -> bool {
+        self.options.normalize.is_some() || self.options.collapse_whitespace
+    }
+
+    /// `bytes` as matched: normalized, then with whitespace collapsed.
+    fn rewrite_bytes<'b>(&self, bytes: &'b [u8]) -> Cow<'b, [u8]> {
+        let normalized = self.normalize_bytes(bytes);
+        if !self.options.collapse_whitespace {
+            return normalized;
+        }
+
+        let is_blank = |b: u8| matches!(b, b' ' | b'\t' | 0x0b | 0x0c);
+        let mut collapsed = Vec::with_capacity(normalized.len());
+        let mut in_run = false;
+        for &b in normalized.iter() {
+            if is_blank(b) {
+                if !in_run {
+                    collapsed.push(b' ');
+                }
+                in_run = true;
+            } else {
+                collapsed.push(b);
+                in_run = false;
+            }
+        }
+        Cow::Owned(collapsed)
+    }
+
+    /// Put back the original text of lines matched in collapsed form,
+    /// including context lines. Truncated lines stay as found, since the
+    /// original would undo the truncation.
+    fn restore_lines(&self, original: &[u8], matches: &mut [SearchMatch]) {
+        if !self.options.collapse_whitespace {
+            return;
+        }
+
+        let lines: Vec<&[u8]> = original.split(|&b| b == b'\n').collect();
+        let line_at = |line_number: u64| {
+            let index = usize::try_from(line_number).ok()?.checked_sub(1)?;
+            let line = lines.get(index)?;
+            Some(String::from_utf8_lossy(line).trim_end().to_string())
+        };
+        for m in matches {
+            if m.match_truncated != Some(true) {
+                if let Some(line) = m.line_number.and_then(line_at) {
+                    m.line = line;
+                }
+            }
+            let context = m
+                .context_before
+                .iter_mut()
+                .chain(m.context_after.iter_mut());
+            for (line_number, line) in context.flatten() {
+                if let Some(original) = line_at(*line_number) {
+                    *line = original;
+                }
+            }
+        }
+    }
+
+    /// `bytes` normalized per the `normalize` option. Invalid UTF-8 is
+    /// replaced, since it can't be normalized.
+    fn normalize_bytes<'b>(&self, bytes: &'b [u8]) -> Cow<'b, [u8]> {
+        let form = self.options.normalize;
+        if form.is_none() {
+            return Cow::Borrowed(bytes);
+        }
+        let Ok(text) = std::str::from_utf8(bytes) else {
+            let text = String::from_utf8_lossy(bytes);
+            return Cow::Owned(normalize_text(form, &text).into_owned().into_bytes());
+        };
+        match normalize_text(form, text) {
+            Cow::Borrowed(_) => Cow::Borrowed(bytes),
+            Cow::Owned(text) => Cow::Owned(text.into_bytes()),
+        }
+    }
+
+    /// Guess, for `text_only`, whether the file at `path` is binary: true
+    /// if more than `text_threshold` of its first 8 KiB are control bytes
+    /// other than common whitespace.
+    fn looks_binary(&self, path: &Path) -> bool {
+        let mut head = Vec::with_capacity(8192);
+        let Ok(file) = fs::File::open(path) else {
+            return false;
+        };
+        if file.take(8192).read_to_end(&mut head).is_err() || head.is_empty() {
+            return false;
+        }
+
+        let control = head
+            .iter()
+            .filter(|&&b| (b < 0x20 && !matches!(b, b'\t' | b'\n' | b'\r' | 0x0c)) || b == 0x7f)
+            .count();
+        control as f64 / head.len() as f64 > self.options.text_threshold.0
+    }
+
+    /// Search a file split into records starting at each `separator` match
+    /// rather than into lines. Each matching record is reported once, with
+    /// the line number it starts on. Returns why the file was skipped, if it
+    /// was.
+    fn search_file_records(
+        &self,
+        ctx: &SearchContext,
+        separator: &RegexMatcher,
+        path: &Path,
+        matches: &mut Vec<SearchMatch>,
+    ) -> PyResult<Option<SkipReason>> {
+        let mut bytes = fs::read(path).map_err(search_error)?;
+        if let Some(max) = self.options.max_lines {
+            let lines = bytes.iter().filter(|&&b| b == b'\n').count()
+                + usize::from(!bytes.is_empty() && !bytes.ends_with(b"\n"));
+            if lines > max {
+                return Ok(Some(SkipReason::MaxLines));
+            }
+        }
+        let mut skipped = None;
+        if let Some(cap) = self.options.skip_long_lines {
+            let mut offset = 0;
+            for line in bytes.split(|&b| b == b'\n') {
+                if line.strip_suffix(b"\r").unwrap_or(line).len() > cap {
+                    skipped = Some(SkipReason::LongLine);
+                    break;
+                }
+                offset += line.len() + 1;
+            }
+            if skipped.is_some() {
+                if !self.options.keep_partial_long_lines {
+                    return Ok(skipped);
+                }
+                // Only what comes before the long line is searched
+                bytes.truncate(offset);
+            }
+        }
+        let content = String::from_utf8_lossy(&bytes);
+        let content = normalize_text(self.options.normalize, &content);
+        let file = self.display_path(path);
+        let matcher = &ctx.matcher;
+
+        let mut starts = vec![0];
+        let _ = separator.find_iter(content.as_bytes(), |m| {
+            if m.start() > 0 {
+                starts.push(m.start());
+            }
+            true
+        });
+        starts.push(content.len());
+
+        let (first, mut max_count) = (matches.len(), self.options.max_count.unwrap_or(usize::MAX));
+        if self.options.limit_behavior == LimitBehavior::Error {
+            // One record more shows whether `max_count` is exceeded
+            max_count = max_count.saturating_add(1);
+        }
+        for bounds in starts.windows(2) {
+            if matches.len() - first >= max_count {
+                break;
+            }
+            let record = &content[bounds[0]..bounds[1]];
+            if record.is_empty()
+                || matcher.is_match(record.as_bytes()).unwrap_or(false) == self.options.invert_match
+                || self.is_excluded(record)
+            {
+                continue;
+            }
+
+            let line_number = content[..bounds[0]].matches('\n').count() as u64 + 1;
+            if let Some(range) = &ctx.line_range {
+                if !range.contains(&line_number) {
+                    continue;
+                }
+            }
+            let mut m = self.make_match(matcher, Some(&*file), line_number, record);
+            if self.options.absolute_offset {
+                m.offset = Some(bounds[0] as u64);
+            }
+            matches.push(m);
+        }
+
+        Ok(skipped)
+    }
+
+    /// Match a directory's name against the pattern for `match_dirs`.
+    fn match_dir_name(&self, matcher: &RegexMatcher, entry: &DirEntry) -> Option<SearchMatch> {
+        if !entry.file_type().map_or(false, |ft| ft.is_dir()) {
+            return None;
+        }
+
+        let name = entry.file_name().to_string_lossy();
+        if !matcher.is_match(name.as_bytes()).unwrap_or(false) {
+            return None;
+        }
+
+        Some(SearchMatch {
+            file: Some(self.display_path(entry.path())),
+            line_number: None,
+            line: name.to_string(),
+            spans: match_spans(matcher, &name),
+            is_dir: Some(true),
+            which_pattern: self.sub_patterns.as_ref().map(|sub| sub.which(&name)),
+            mode: self.options.include_mode.then(|| file_mode(entry.path())),
+            label: self.options.label.clone(),
+            ..Default::default()
+        })
+    }
+
+    /// The path reported as `file` for matches in `path`.
+    fn display_path(&self, path: &Path) -> String {
+        if self.options.git_relative {
+            // Relative to the repository root, or absolute outside a repo
+            if let Ok(absolute) = fs::canonicalize(path) {
+                let relative = find_git_root(&absolute)
+                    .and_then(|root| absolute.strip_prefix(root).ok().map(Path::to_path_buf));
+                return relative.unwrap_or(absolute).to_string_lossy().to_string();
+            }
+        }
+
+        path.to_string_lossy().to_string()
+    }
+
+    fn build_walker(&self, path: &Path) -> PyResult<WalkBuilder> {
+        let mut builder = WalkBuilder::new(path);
+        builder.hidden(!self.options.hidden);
+        if self.options.no_ignore {
+            builder
+                .git_ignore(false)
+                .git_exclude(false)
+                .git_global(false)
+                .ignore(false);
+        }
+
+        // Nothing past the deepest included depth is searched, so don't
+        // descend that far either
+        let deepest = self.options.include_depths.iter().max().copied();
+        let max_depth = match (self.options.max_depth, deepest) {
+            (Some(max), Some(deepest)) => Some(max.min(deepest)),
+            (max, deepest) => max.or(deepest),
+        };
+        builder.max_depth(max_depth);
+        builder.same_file_system(self.options.same_file_system);
+        builder.max_filesize(self.options.max_filesize);
+        builder.overrides(self.overrides_for(path)?);
+        builder.types(Types::clone(&self.types));
+        if self.options.stable || self.options.sort == Some(SortBy::Path) {
+            // Directory listing order varies between filesystems and runs,
+            // and decides which files a `max_per_dir` cap keeps
+            builder.sort_by_file_name(|a, b| a.cmp(b));
+        }
+
+        // Only one entry filter can be installed, so it covers both symlinks
+        // and precompiled ignore rules
+        let (follow_files, follow_dirs) = (
+            self.options.follow_links || self.options.follow_file_links,
+            self.options.follow_links || self.options.follow_dir_links,
+        );
+        let globs =
+            if self.options.follow_links_matching.is_empty() && !follow_files && !follow_dirs {
+                None
+            } else {
+                // Follow links, but prune every symlink that isn't whitelisted so
+                // it's treated exactly as if links weren't followed at all.
+                builder.follow_links(true);
+                Some(build_globset(&self.options.follow_links_matching)?)
+            };
+        let ignore = match &self.ignore_rules {
+            Some(rules) => {
+                // The precompiled rules replace the .gitignore files on disk
+                builder.git_ignore(false);
+                let cwd = std::env::current_dir().map_err(search_error)?;
+                Some((rules.clone(), cwd))
+            }
+            None => None,
+        };
+        if globs.is_some() || ignore.is_some() {
+            builder.filter_entry(move |entry| {
+                if let Some(globs) = &globs {
+                    if entry.path_is_symlink() {
+                        // Followed, the entry has the type of the link's target
+                        let followed = if entry.file_type().is_some_and(|t| t.is_dir()) {
+                            follow_dirs
+                        } else {
+                            follow_files
+                        };
+                        if !followed && !symlink_matches(globs, entry.path()) {
+                            return false;
+                        }
+                    }
+                }
+                if let Some((rules, cwd)) = &ignore {
+                    let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+                    if rules.is_ignored(&cwd.join(entry.path()), is_dir) {
+                        return false;
+                    }
+                }
+                true
+            });
+        }
+
+        Ok(builder)
+    }
+
+    /// Whether files at `depth` of a walk are searched under `include_depths`.
+    fn depth_included(&self, depth: usize) -> bool {
+        self.options.include_depths.is_empty() || self.options.include_depths.contains(&depth)
+    }
+
+    /// Whether the file at `path` is over `max_filesize` and so skipped, as
+    /// a walk would skip it. Raises instead under `max_filesize_error`.
+    fn too_large(&self, path: &Path) -> PyResult<bool> {
+        let Some(max) = self.options.max_filesize else {
+            return Ok(false);
+        };
+        let size = fs::metadata(path).map_err(search_error)?.len();
+        if size <= max {
+            return Ok(false);
+        }
+        if self.options.max_filesize_error {
+            let err = LimitExceeded::new_err(format!(
+                "{} is {} bytes, over max_filesize of {}",
+                path.display(),
+                size,
+                max
+            ));
+            // Raised before the file is searched, so nothing was collected
+            Python::with_gil(|py| {
+                err.value_bound(py)
+                    .setattr("partial_results", PyList::empty_bound(py))
+            })?;
+            return Err(err);
+        }
+        Ok(true)
+    }
+
+    /// Every file `root` names or contains, in walk order.
+    fn walk_files(&self, root: &Path) -> PyResult<Vec<PathBuf>> {
+        if root.is_file() {
+            if self.too_large(root)? {
+                return Ok(Vec::new());
+            }
+            return Ok(vec![root.to_path_buf()]);
+        }
+        if !root.is_dir() {
+            return Ok(Vec::new());
+        }
+
+        Ok(self
+            .build_walker(root)?
+            .build()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.file_type().map_or(false, |ft| ft.is_file()))
+            .filter(|entry| self.depth_included(entry.depth()))
+            .map(|entry| entry.into_path())
+            .collect())
+    }
+
+    /// Number of matching lines in the file at `path`, for `count`.
+    fn count_file(&self, ctx: &mut SearchContext, path: &Path) -> PyResult<usize> {
+        ctx.over_max_count = false;
+        if self.options.text_only && self.looks_binary(path) {
+            return Ok(0);
+        }
+        if ctx.record_separator.is_some() || self.rewrites_text() {
+            // Both need the text itself, so take the full search path
+            let mut matches = Vec::new();
+            self.search_file_impl(ctx, path, &mut matches)?;
+            return Ok(matches.len());
+        }
+
+        let mut sink = CountSink {
+            rg: self,
+            count: 0,
+            over_max_count: false,
+        };
+        ctx.searcher
+            .search_path(&*ctx.matcher, path, &mut sink)
+            .map_err(search_error)?;
+        ctx.over_max_count = sink.over_max_count;
+        Ok(sink.count)
+    }
+
+    fn search_directory_impl(
+        &self,
+        py: Python<'_>,
+        ctx: &mut SearchContext,
+        path: &Path,
+        matches: &mut Vec<SearchMatch>,
+        progress: Option<&Progress<'_, '_>>,
+    ) -> PyResult<()> {
+        if self.walks_in_parallel() {
+            return self.walk_parallel(py, ctx, path, matches, progress);
+        }
+
+        let files_searched = Arc::clone(&ctx.files_searched);
+        let result = self.walk_directory(ctx, path, |file_matches| {
+            matches.extend(file_matches);
+            if let Some(max) = self.options.max_total {
+                self.apply_limit(matches, Some(max))?;
+                // Under "error", walk on to find out whether it's exceeded
+                if matches.len() >= max && self.options.limit_behavior == LimitBehavior::Truncate {
+                    return Ok(false);
+                }
+            }
+
+            // The GIL is held for the whole walk, so give Python a chance to
+            // deliver Ctrl-C, and report progress, between files
+            py.check_signals().and_then(|()| match progress {
+                Some(progress) => {
+                    progress.tick(files_searched.load(Ordering::Relaxed), matches.len())
+                }
+                None => Ok(()),
+            })?;
+            Ok(true)
+        });
+
+        if let Err(err) = result {
+            // Whatever was collected so far, whether the walk was stopped by
+            // Ctrl-C, a limit or a file that couldn't be searched, is
+            // attached to the exception as `partial_results`
+            err.value_bound(py)
+                .setattr("partial_results", matches_to_list(py, matches)?)?;
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Whether directory searches use the parallel walker: unless `threads`
+    /// is 1, or `max_per_dir` or `max_total` needs files counted in walk
+    /// order.
+    fn walks_in_parallel(&self) -> bool {
+        self.options.threads != Some(1)
+            && self.options.max_per_dir.is_none()
+            && self.options.max_total.is_none()
+    }
+
+    /// Search the files under `path` on worker threads with the GIL
+    /// released. Each file's matches stay together and in line order, and
+    /// files are put in path order at the end, so results don't depend on
+    /// which worker finished first.
+    fn walk_parallel(
+        &self,
+        py: Python<'_>,
+        ctx: &mut SearchContext,
+        path: &Path,
+        matches: &mut Vec<SearchMatch>,
+        progress: Option<&Progress<'_, '_>>,
+    ) -> PyResult<()> {
+        let mut builder = self.build_walker(path)?;
+        builder.threads(self.options.threads.unwrap_or(0));
+        let walker = builder.build_parallel();
+
+        let found: Mutex<Vec<Vec<SearchMatch>>> = Mutex::new(Vec::new());
+        let files_searched = Arc::clone(&ctx.files_searched);
+        // Reported once the walk is over: showing a `PyErr` needs the GIL
+        let errors: Mutex<Vec<(PathBuf, PyErr)>> = Mutex::new(Vec::new());
+        let cancelled = AtomicBool::new(false);
+        // Set once a file goes over `max_count` under "error"
+        let over_max_count = AtomicBool::new(false);
+        let (done, finished) = mpsc::channel::<()>();
+        // Only so the receiver can be borrowed while the GIL is released
+        let finished = Mutex::new(finished);
+
+        let result = thread::scope(|scope| {
+            let template = ctx.clone();
+            let (found, errors, cancelled) = (&found, &errors, &cancelled);
+            let over_max_count = &over_max_count;
+            scope.spawn(move || {
+                walker.run(|| {
+                    let mut ctx = template.clone();
+                    Box::new(move |entry| {
+                        if cancelled.load(Ordering::Relaxed) {
+                            return WalkState::Quit;
+                        }
+                        let entry = match entry {
+                            Ok(entry) => entry,
+                            // Such as a symlink loop, handled like a file
+                            // that can't be searched
+                            Err(e) => {
+                                let at = walk_error_path(&e).unwrap_or(path).to_path_buf();
+                                errors.lock().unwrap().push((at, walk_error(&e)));
+                                if self.options.on_error == OnError::Raise {
+                                    cancelled.store(true, Ordering::Relaxed);
+                                    return WalkState::Quit;
+                                }
+                                return WalkState::Continue;
+                            }
+                        };
+
+                        if self.options.match_dirs && entry.depth() > 0 {
+                            if let Some(dir_match) = self.match_dir_name(&ctx.matcher, &entry) {
+                                found.lock().unwrap().push(vec![dir_match]);
+                                return WalkState::Continue;
+                            }
+                        }
+                        if !entry.file_type().map_or(false, |ft| ft.is_file())
+                            || !self.depth_included(entry.depth())
+                        {
+                            return WalkState::Continue;
+                        }
+
+                        let mut file_matches = Vec::new();
+                        if let Err(e) = self.search_entry(&mut ctx, entry.path(), &mut file_matches)
+                        {
+                            errors.lock().unwrap().push((entry.into_path(), e));
+                            if self.options.on_error == OnError::Raise {
+                                cancelled.store(true, Ordering::Relaxed);
+                                return WalkState::Quit;
+                            }
+                        }
+                        if !file_matches.is_empty() {
+                            found.lock().unwrap().push(file_matches);
+                        }
+                        if ctx.over_max_count {
+                            over_max_count.store(true, Ordering::Relaxed);
+                            cancelled.store(true, Ordering::Relaxed);
+                            return WalkState::Quit;
+                        }
+                        WalkState::Continue
+                    })
+                });
+                let _ = done.send(());
+            });
+
+            // Wait without the GIL, waking now and then so Python can
+            // deliver Ctrl-C, which stops the workers after their current file
+            loop {
+                let waited = py.allow_threads(|| {
+                    finished
+                        .lock()
+                        .unwrap()
+                        .recv_timeout(Duration::from_millis(50))
+                });
+                let walking = matches!(waited, Err(RecvTimeoutError::Timeout));
+                let report = || match progress {
+                    Some(progress) => {
+                        let found = found.lock().unwrap().iter().map(Vec::len).sum();
+                        progress.tick(files_searched.load(Ordering::Relaxed), found)
+                    }
+                    None => Ok(()),
+                };
+                if !walking {
+                    // A walk over before the first wakeup still reports
+                    return report();
+                }
+                if let Err(err) = py.check_signals().and_then(|()| report()) {
+                    cancelled.store(true, Ordering::Relaxed);
+                    return Err(err);
+                }
+            }
+        });
+
+        let mut found = found.into_inner().unwrap();
+        found.sort_by_cached_key(|file_matches| file_matches[0].file.clone().map(PathBuf::from));
+        matches.extend(found.into_iter().flatten());
+        let mut errors = errors.into_inner().unwrap();
+        errors.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let mut result = result;
+        for (path, e) in errors {
+            if let Err(err) = self.file_error(ctx, &path, e) {
+                result = result.and(Err(err));
+                break;
+            }
+        }
+
+        let result = result.and_then(|()| {
+            if over_max_count.into_inner() {
+                return Err(limit_exceeded(self.options.max_count.unwrap_or(0)));
+            }
+            Ok(())
+        });
+        if let Err(err) = result {
+            // Whatever was collected so far, as in the single-threaded walk
+            err.value_bound(py)
+                .setattr("partial_results", matches_to_list(py, matches)?)?;
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Walk `path`, searching every file and handing each file's matches to
+    /// `on_file`, which returns whether to keep walking. Doesn't touch
+    /// Python, so it can run on a thread without the GIL.
+    ///
+    /// Files are searched one at a time, so each file's matches arrive
+    /// together and in line order.
+    fn walk_directory<F>(
+        &self,
+        ctx: &mut SearchContext,
+        path: &Path,
+        mut on_file: F,
+    ) -> PyResult<()>
+    where
+        F: FnMut(Vec<SearchMatch>) -> PyResult<bool>,
+    {
+        let walker = self.build_walker(path)?.build();
+        let mut per_dir: HashMap<PathBuf, usize> = HashMap::new();
+
+        for entry in walker {
+            let entry = match entry {
+                Ok(entry) => entry,
+                // Such as a directory it has no permission to list, or a
+                // symlink loop, which the walker detects itself; handled
+                // like a file that can't be searched
+                Err(e) => {
+                    let at = walk_error_path(&e).unwrap_or(path);
+                    self.file_error(ctx, at, walk_error(&e))?;
+                    continue;
+                }
+            };
+
+            if self.options.match_dirs && entry.depth() > 0 {
+                if let Some(dir_match) = self.match_dir_name(&ctx.matcher, &entry) {
+                    if !on_file(vec![dir_match])? {
+                        break;
+                    }
+                    continue;
+                }
+            }
+
+            if !entry.file_type().map_or(false, |ft| ft.is_file())
+                || !self.depth_included(entry.depth())
+            {
+                continue;
+            }
+
+            let dir_count = match self.options.max_per_dir {
+                Some(limit) => {
+                    let dir = entry.path().parent().unwrap_or(path).to_path_buf();
+                    let count = per_dir.entry(dir).or_default();
+                    // Under "error", a full directory's files are still
+                    // searched to find out whether the limit is exceeded
+                    if *count >= limit && self.options.limit_behavior == LimitBehavior::Truncate {
+                        continue;
+                    }
+                    Some((count, limit))
+                }
+                None => None,
+            };
+
+            let mut file_matches = Vec::new();
+            if let Err(e) = self.search_entry(ctx, entry.path(), &mut file_matches) {
+                self.file_error(ctx, entry.path(), e)?;
+            }
+
+            let mut exceeded = self.check_max_count(ctx);
+            if let Some((count, limit)) = dir_count {
+                let capped = self.apply_limit(&mut file_matches, Some(limit - *count));
+                exceeded = exceeded.and(capped);
+                *count += file_matches.len();
+            }
+
+            // Hand over what fits before reporting that the limit was hit
+            if !on_file(file_matches)? {
+                break;
+            }
+            exceeded?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Iterator over matches produced by a background search.
+#[pyclass]
+struct SearchGenerator {
+    // Only so the receiver can be borrowed while the GIL is released
+    receiver: Mutex<mpsc::Receiver<PyResult<SearchMatch>>>,
+}
+
+#[pymethods]
+impl SearchGenerator {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&self, py: Python<'_>) -> PyResult<Option<Py<PyDict>>> {
+        let received = py.allow_threads(|| self.receiver.lock().unwrap().recv());
+        match received {
+            Ok(Ok(m)) => Ok(Some(m.to_dict(py)?.into())),
+            Ok(Err(err)) => Err(err),
+            // The search thread finished and dropped its sender
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+/// The `file` reported for matches found by `search_reader`.
+const STDIN_NAME: &str = "<stdin>";
+
+/// A Python file-like object read through its `read` method, for
+/// `search_reader`. Text streams are encoded as UTF-8.
+struct PyReader<'a, 'py> {
+    reader: &'a Bound<'py, PyAny>,
+    /// Bytes read but not yet handed out, as a `str` chunk can encode to
+    /// more bytes than were asked for.
+    pending: Vec<u8>,
+    offset: usize,
+}
+
+impl<'a, 'py> PyReader<'a, 'py> {
+    fn new(reader: &'a Bound<'py, PyAny>) -> Self {
+        PyReader {
+            reader,
+            pending: Vec::new(),
+            offset: 0,
+        }
+    }
+}
+
+impl Read for PyReader<'_, '_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.offset == self.pending.len() {
+            let chunk = self.reader.call_method1("read", (buf.len(),))?;
+            self.pending = match chunk.extract::<String>() {
+                Ok(text) => text.into_bytes(),
+                Err(_) => chunk.extract::<&[u8]>()?.to_vec(),
+            };
+            self.offset = 0;
+        }
+        let n = buf.len().min(self.pending.len() - self.offset);
+        buf[..n].copy_from_slice(&self.pending[self.offset..self.offset + n]);
+        self.offset += n;
+        Ok(n)
+    }
+}
+
+/// Sink collecting the matched lines of one file (or buffer).
+struct MatchSink<'a> {
+    rg: &'a RipGrep,
+    matcher: &'a RegexMatcher,
+    file: Option<&'a str>,
+    line_range: Option<RangeInclusive<u64>>,
+    base_offset: Option<u64>,
+    matches: Vec<SearchMatch>,
+    /// Set once the file turns out to break `max_lines` or
+    /// `skip_long_lines`.
+    skipped: Option<SkipReason>,
+    /// Context lines seen since the last match, for the next one.
+    before: Vec<(u64, String)>,
+    /// How many more context lines belong after the last match.
+    after_remaining: usize,
+    /// The last line number that was within the limits.
+    last_line: u64,
+    /// Set on a match past `max_count` under `limit_behavior="error"`.
+    over_max_count: bool,
+}
+
+impl MatchSink<'_> {
+    /// Whether the file has its `max_count` matches.
+    fn at_max_count(&self) -> bool {
+        self.rg
+            .options
+            .max_count
+            .map_or(false, |max| self.matches.len() >= max)
+    }
+
+    /// Whether to read on after a line. Reading stops at `max_count`
+    /// matches, or once the last match's after context is in, except under
+    /// `limit_behavior="error"`, which reads on for one match more.
+    fn reads_on(&self) -> bool {
+        !self.at_max_count()
+            || self.after_remaining > 0
+            || self.rg.options.limit_behavior == LimitBehavior::Error
+    }
+
+    /// Give up on the file once `line_number` is past `max_lines` or `line`
+    /// is longer than `skip_long_lines`, discarding its matches unless
+    /// partial results are kept. Returns whether to keep searching.
+    fn within_limits(&mut self, line_number: u64, line: &[u8]) -> bool {
+        let Some(reason) = SkipReason::check(&self.rg.options, line_number, line) else {
+            self.last_line = line_number;
+            return true;
+        };
+
+        if !reason.keeps_partial(&self.rg.options) {
+            self.matches.clear();
+        }
+        self.skipped = Some(reason);
+        false
+    }
+
+    /// File a context line under the last match if it's still owed lines
+    /// after it, or else hold it for the next match. Either way a line is
+    /// reported once, as ripgrep does where the context of two matches
+    /// overlaps. Context is simply cut short at the start and end of a file.
+    fn context_line(&mut self, line_number: u64, bytes: &[u8]) {
+        let line = self
+            .rg
+            .trim_line(&String::from_utf8_lossy(bytes))
+            .to_string();
+        if self.after_remaining > 0 {
+            self.after_remaining -= 1;
+            let last = self.matches.last_mut();
+            if let Some(after) = last.and_then(|m| m.context_after.as_mut()) {
+                after.push((line_number, line));
+            }
+        } else if self.rg.options.before_context > 0 {
+            self.before.push((line_number, line));
+            if self.before.len() > self.rg.options.before_context {
+                self.before.remove(0);
+            }
+        }
+    }
+}
+
+impl Sink for MatchSink<'_> {
+    type Error = io::Error;
+
+    fn matched(&mut self, _searcher: &Searcher, mat: &SinkMatch<'_>) -> io::Result<bool> {
+        let line_number = mat.line_number().unwrap_or(0);
+        let raises = self.rg.options.limit_behavior == LimitBehavior::Error;
+        if !self.within_limits(line_number, mat.bytes()) || (self.at_max_count() && !raises) {
+            return Ok(false);
+        }
+        // Context never spans a match, whether or not it's reported
+        let before = std::mem::take(&mut self.before);
+        self.after_remaining = 0;
+
+        // When every line is passed through to check the limits, look past
+        // the range end instead of stopping there
+        if self.rg.sees_every_line() {
+            if let Some(range) = &self.line_range {
+                if !range.contains(&line_number) {
+                    return Ok(true);
+                }
+            }
+        }
+
+        if let Some(range) = &self.line_range {
+            // Nothing past the end of the range can match, stop reading
+            if line_number > *range.end() {
+                return Ok(false);
+            }
+            if line_number < *range.start() {
+                return Ok(true);
+            }
+        }
+
+        let line = match std::str::from_utf8(mat.bytes()) {
+            Ok(line) => Cow::Borrowed(line),
+            Err(_) if self.rg.bytes_pattern || self.rg.options.raw_bytes => {
+                String::from_utf8_lossy(mat.bytes())
+            }
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        };
+        if self.rg.is_excluded(&line) {
+            return Ok(true);
+        }
+        if self.at_max_count() {
+            // Only reached under "error": this match is one too many
+            self.over_max_count = true;
+            return Ok(false);
+        }
+        let mut m = self
+            .rg
+            .make_match(self.matcher, self.file, line_number, &line);
+        m.byte_offset = self
+            .base_offset
+            .map(|base| base + mat.absolute_byte_offset());
+        if self.rg.options.absolute_offset {
+            m.offset = Some(self.base_offset.unwrap_or(0) + mat.absolute_byte_offset());
+        }
+        if self.rg.options.hex_preview {
+            // From the original bytes, which the line may have lost
+            m.hex_preview = Some(hex_preview(self.matcher, mat.bytes()));
+        }
+        if self.rg.options.raw_bytes {
+            // Decoding may have moved the matches, so find them again
+            let raw = self.rg.trim_line_bytes(mat.bytes());
+            if self.rg.options.overlapping {
+                m.spans = overlapping_spans(self.matcher, raw);
+            } else {
+                m.spans.clear();
+                let _ = self.matcher.find_iter(raw, |found| {
+                    m.spans.push((found.start(), found.end()));
+                    true
+                });
+            }
+            m.raw_line = Some(raw.to_vec());
+        }
+        if self.rg.options.file_span {
+            let start = self.base_offset.unwrap_or(0) + mat.absolute_byte_offset();
+            let mut spans = Vec::new();
+            let _ = self.matcher.find_iter(mat.bytes(), |found| {
+                spans.push((start + found.start() as u64, start + found.end() as u64));
+                true
+            });
+            m.file_spans = Some(spans);
+        }
+        if self.rg.has_context() {
+            m.context_before = Some(before);
+            m.context_after = Some(Vec::new());
+            self.after_remaining = self.rg.options.after_context;
+        }
+        self.matches.push(m);
+        Ok(self.reads_on())
+    }
+
+    /// Called for context lines, and for every other line too when passthru
+    /// is on for `max_lines` or `skip_long_lines`. Passthru reports lines
+    /// without their context kind, so the sink tracks context itself.
+    fn context(&mut self, _searcher: &Searcher, ctx: &SinkContext<'_>) -> io::Result<bool> {
+        let line_number = ctx.line_number().unwrap_or(0);
+        if !self.within_limits(line_number, ctx.bytes()) {
+            return Ok(false);
+        }
+        if self.rg.has_context() {
+            self.context_line(line_number, ctx.bytes());
+        }
+        Ok(self.reads_on())
+    }
+}
+
+/// Sink counting matching lines per bucket of lines, for `density`.
+struct DensitySink<'a> {
+    rg: &'a RipGrep,
+    bucket_lines: u64,
+    buckets: Vec<usize>,
+}
+
+impl DensitySink<'_> {
+    /// The bucket for `line_number`, growing the list to reach it.
+    fn bucket(&mut self, line_number: u64) -> &mut usize {
+        let index = (line_number.saturating_sub(1) / self.bucket_lines) as usize;
+        if self.buckets.len() <= index {
+            self.buckets.resize(index + 1, 0);
+        }
+        &mut self.buckets[index]
+    }
+}
+
+impl Sink for DensitySink<'_> {
+    type Error = io::Error;
+
+    fn matched(&mut self, _searcher: &Searcher, mat: &SinkMatch<'_>) -> io::Result<bool> {
+        let excluded = self.rg.is_excluded(&String::from_utf8_lossy(mat.bytes()));
+        *self.bucket(mat.line_number().unwrap_or(0)) += usize::from(!excluded);
+        Ok(true)
+    }
+
+    fn context(&mut self, _searcher: &Searcher, ctx: &SinkContext<'_>) -> io::Result<bool> {
+        self.bucket(ctx.line_number().unwrap_or(0));
+        Ok(true)
+    }
+}
 
-#[pyclass]
-struct RipGrep {
-    pattern: String,
+/// Sink counting matched lines without decoding them, for `count`.
+struct CountSink<'a> {
+    rg: &'a RipGrep,
+    count: usize,
+    /// As for `MatchSink`.
+    over_max_count: bool,
 }
 
-#[pymethods]
-impl RipGrep {
-    #[new]
-    fn new(pattern: String) -> PyResult<Self> {
-        // Validate the regex pattern immediately
-        RegexMatcher::new(&pattern).map_err(|e| {
-            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Invalid regex: {}", e))
-        })?;
-        Ok(RipGrep { pattern })
+impl CountSink<'_> {
+    /// Like `MatchSink::within_limits`, dropping the count instead.
+    fn within_limits(&mut self, line_number: u64, line: &[u8]) -> bool {
+        let Some(reason) = SkipReason::check(&self.rg.options, line_number, line) else {
+            return true;
+        };
+
+        if !reason.keeps_partial(&self.rg.options) {
+            self.count = 0;
+        }
+        false
     }
+}
 
-    fn search(&self, path: &str, py: Python) -> PyResult<Py<PyList>> {
-        let results = PyList::empty_bound(py);
-        let matcher = RegexMatcher::new(&self.pattern).map_err(|e| {
-            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!("Invalid regex: {}", e))
-        })?;
+impl Sink for CountSink<'_> {
+    type Error = io::Error;
 
-        let search_path = Path::new(path);
+    fn matched(&mut self, _searcher: &Searcher, mat: &SinkMatch<'_>) -> io::Result<bool> {
+        if !self.within_limits(mat.line_number().unwrap_or(0), mat.bytes()) {
+            return Ok(false);
+        }
 
-        if search_path.is_file() {
-            self.search_file_impl(&matcher, search_path, &results)?;
-        } else if search_path.is_dir() {
-            self.search_directory_impl(&matcher, search_path, &results)?;
+        // Only decode the line when there's an exclusion to check it against
+        let max_count = self.rg.options.max_count.unwrap_or(usize::MAX);
+        let raises = self.rg.options.limit_behavior == LimitBehavior::Error;
+        if self.count >= max_count && !raises {
+            return Ok(false);
         }
 
-        Ok(results.into())
+        let excluded = !self.rg.exclusions.is_empty()
+            && self.rg.is_excluded(&String::from_utf8_lossy(mat.bytes()));
+        if excluded {
+            return Ok(true);
+        }
+        if self.count >= max_count {
+            // Only reached under "error": this match is one too many
+            self.over_max_count = true;
+            return Ok(false);
+        }
+        self.count += 1;
+        Ok(self.count < max_count || raises)
+    }
+
+    fn context(&mut self, _searcher: &Searcher, ctx: &SinkContext<'_>) -> io::Result<bool> {
+        Ok(self.within_limits(ctx.line_number().unwrap_or(0), ctx.bytes()))
     }
 }
 
-impl RipGrep {
-    fn search_file_impl(
-        &self,
-        matcher: &RegexMatcher,
-        path: &Path,
-        results: &Bound<'_, PyList>,
-    ) -> PyResult<()> {
-        let mut searcher = Searcher::new();
-        let mut matches = Vec::new();
+/// A `progress` callback for a directory walk.
+struct Progress<'a, 'py> {
+    callback: &'a Bound<'py, PyAny>,
+    /// How many files apart calls are.
+    every: usize,
+    /// The file count last reported.
+    reported: Cell<usize>,
+}
 
-        let sink = UTF8(|line_num, line| {
-            matches.push((
-                path.to_string_lossy().to_string(),
-                line_num,
-                line.to_string(),
-            ));
-            Ok(true)
-        });
+impl Progress<'_, '_> {
+    /// Call back with `files` and `matches` if `every` more files were
+    /// searched since the last call.
+    fn tick(&self, files: usize, matches: usize) -> PyResult<()> {
+        if files < self.reported.get() + self.every {
+            return Ok(());
+        }
+        self.reported.set(files);
+        self.callback.call1((files, matches))?;
+        Ok(())
+    }
+}
 
-        searcher.search_path(matcher, path, sink).map_err(|e| {
-            PyErr::new::<pyo3::exceptions::PyIOError, _>(format!("Search error: {}", e))
-        })?;
+/// State compiled once per search and shared by every file it visits.
+#[derive(Clone)]
+struct SearchContext {
+    matcher: Arc<RegexMatcher>,
+    record_separator: Option<Arc<RegexMatcher>>,
+    searcher: Searcher,
+    /// Only lines in this inclusive range are reported.
+    line_range: Option<RangeInclusive<u64>>,
+    /// Where the searched bytes start in the file, if matches should report
+    /// their `byte_offset`.
+    base_offset: Option<u64>,
+    /// Why the last file searched was given up on, if it was.
+    skipped: Option<SkipReason>,
+    /// How many lines of the last file searched were scanned, under
+    /// `report_lines_scanned`.
+    lines_scanned: Option<u64>,
+    /// Whether the last file searched had more than `max_count` matches,
+    /// which is only looked for under `limit_behavior="error"`.
+    over_max_count: bool,
+    /// Files a walk couldn't search under `on_error="collect"`, as
+    /// `(file, error)` pairs.
+    errors: Vec<(String, String)>,
+    /// How many files have been searched, shared by every clone so the
+    /// workers of a parallel walk add to one count.
+    files_searched: Arc<AtomicUsize>,
+    /// Search inside archives rather than treating them as plain files.
+    archives: bool,
+}
 
-        for (file_path, line_num, line) in matches {
-            Python::with_gil(|py| {
-                let dict = pyo3::types::PyDict::new_bound(py);
-                dict.set_item("file", file_path)?;
-                dict.set_item("line_number", line_num)?;
-                dict.set_item("line", line.trim_end())?;
-                results.append(dict)?;
-                Ok::<_, PyErr>(())
-            })?;
+/// Why a file was given up on partway through.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SkipReason {
+    /// It has more than `max_lines` lines.
+    MaxLines,
+    /// It has a line longer than `skip_long_lines`.
+    LongLine,
+}
+
+impl SkipReason {
+    /// Why a file should be given up on at `line`, if it should.
+    fn check(options: &SearchOptions, line_number: u64, line: &[u8]) -> Option<Self> {
+        if options
+            .max_lines
+            .map_or(false, |max| line_number > max as u64)
+        {
+            return Some(SkipReason::MaxLines);
         }
+        let cap = options.skip_long_lines?;
+        let terminator = line.iter().rev().take_while(|&&b| b == b'\n' || b == b'\r');
+        (line.len() - terminator.count() > cap).then_some(SkipReason::LongLine)
+    }
 
-        Ok(())
+    /// Whether the matches found before giving up are kept.
+    fn keeps_partial(&self, options: &SearchOptions) -> bool {
+        *self == SkipReason::LongLine && options.keep_partial_long_lines
     }
 
-    fn search_directory_impl(
-        &self,
-        matcher: &RegexMatcher,
-        path: &Path,
-        results: &Bound<'_, PyList>,
-    ) -> PyResult<()> {
-        let walker = WalkBuilder::new(path).build();
+    fn as_str(&self) -> &'static str {
+        match self {
+            SkipReason::MaxLines => "max_lines",
+            SkipReason::LongLine => "long_line",
+        }
+    }
+}
 
-        for entry in walker {
-            let entry = entry.map_err(|e| {
-                PyErr::new::<pyo3::exceptions::PyIOError, _>(format!("Walk error: {}", e))
-            })?;
+/// Archive formats `search_all` looks inside, recognized by extension.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ArchiveKind {
+    Tar,
+    TarGz,
+    Gz,
+}
 
-            if entry.file_type().map_or(false, |ft| ft.is_file()) {
-                if let Err(e) = self.search_file_impl(matcher, entry.path(), results) {
-                    eprintln!("Error searching {}: {}", entry.path().display(), e);
-                }
+impl ArchiveKind {
+    fn of(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveKind::TarGz)
+        } else if name.ends_with(".tar") {
+            Some(ArchiveKind::Tar)
+        } else if name.ends_with(".gz") {
+            Some(ArchiveKind::Gz)
+        } else {
+            None
+        }
+    }
+}
+
+impl SearchContext {
+    /// Run one search into a fresh `MatchSink`, returning what it collected.
+    fn run<F>(
+        &mut self,
+        rg: &RipGrep,
+        file: Option<&str>,
+        search: F,
+    ) -> io::Result<Vec<SearchMatch>>
+    where
+        F: FnOnce(&mut Searcher, &RegexMatcher, &mut MatchSink<'_>) -> io::Result<()>,
+    {
+        let SearchContext {
+            ref matcher,
+            ref mut searcher,
+            ref line_range,
+            base_offset,
+            ..
+        } = *self;
+        let matcher: &RegexMatcher = matcher;
+
+        let mut sink = MatchSink {
+            rg,
+            matcher,
+            file,
+            line_range: line_range.clone(),
+            base_offset,
+            matches: Vec::new(),
+            skipped: None,
+            before: Vec::new(),
+            after_remaining: 0,
+            last_line: 0,
+            over_max_count: false,
+        };
+        search(searcher, matcher, &mut sink)?;
+        // An archive is searched a member at a time, and any one counts
+        self.over_max_count |= sink.over_max_count;
+        self.skipped = sink.skipped;
+        self.lines_scanned = rg.options.report_lines_scanned.then_some(sink.last_line);
+        Ok(sink.matches)
+    }
+}
+
+/// Whether `pattern` contains an uppercase letter outside an escape, the
+/// test `smart_case` applies. Close to, if not exactly, grep-regex's own
+/// check, which looks only at literals in the parsed pattern.
+fn has_uppercase_literal(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            chars.next();
+        } else if c.is_uppercase() {
+            return true;
+        }
+    }
+    false
+}
+
+/// `text` in the given normalization form, borrowed if already normalized.
+///
+/// Normalizing costs a full extra pass over the text plus a copy whenever
+/// anything changes, which is why it's opt-in.
+fn normalize_text(form: Option<Normalization>, text: &str) -> Cow<'_, str> {
+    match form {
+        Some(Normalization::Nfc) if !is_nfc(text) => Cow::Owned(text.nfc().collect()),
+        Some(Normalization::Nfd) if !is_nfd(text) => Cow::Owned(text.nfd().collect()),
+        _ => Cow::Borrowed(text),
+    }
+}
+
+/// Hash identifying a match by file and line text for `diff`. FNV-1a,
+/// rather than `DefaultHasher`, so stored hashes stay valid across builds.
+fn match_hash(file: &str, line: &str) -> String {
+    format!("{:016x}", fnv1a([file.as_bytes(), line.as_bytes()]))
+}
+
+/// FNV-1a hash of `parts` joined by NULs, which keep ("ab", "c") and
+/// ("a", "bc") apart.
+fn fnv1a<'a>(parts: impl IntoIterator<Item = &'a [u8]>) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for (i, part) in parts.into_iter().enumerate() {
+        let separator: &[u8] = if i > 0 { b"\0" } else { b"" };
+        for &byte in separator.iter().chain(part) {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+/// Byte offsets of each non-overlapping occurrence of the pattern in `line`.
+fn match_spans(matcher: &RegexMatcher, line: &str) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let _ = matcher.find_iter(line.as_bytes(), |m| {
+        spans.push((m.start(), m.end()));
+        true
+    });
+    spans
+}
+
+/// Byte offsets of every occurrence of the pattern in `line`, overlapping
+/// or not: each search starts one byte past the previous match's start.
+fn overlapping_spans(matcher: &RegexMatcher, line: &[u8]) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let mut at = 0;
+    while at <= line.len() {
+        match matcher.find_at(line, at) {
+            Ok(Some(m)) => {
+                spans.push((m.start(), m.end()));
+                at = m.start() + 1;
+            }
+            _ => break,
+        }
+    }
+    spans
+}
+
+/// Cut `line` at each of `spans` into alternating non-match and match text,
+/// like `re.split` with a capturing group.
+fn split_on_spans(line: &str, spans: &[(usize, usize)]) -> Vec<String> {
+    // Byte patterns can match inside a character, so cut the bytes
+    let bytes = line.as_bytes();
+    let text = |range: &[u8]| String::from_utf8_lossy(range).into_owned();
+    let mut segments = Vec::with_capacity(spans.len() * 2 + 1);
+    let mut last = 0;
+    for &(start, end) in spans {
+        segments.push(text(&bytes[last..start]));
+        segments.push(text(&bytes[start..end]));
+        last = end;
+    }
+    segments.push(text(&bytes[last..]));
+    segments
+}
+
+/// Cut `line` short `limit` bytes into the first match longer than `limit`.
+///
+/// Returns the (possibly shortened) line and whether it was truncated.
+fn truncate_long_match(matcher: &RegexMatcher, line: &str, limit: usize) -> (String, bool) {
+    let mut cut = None;
+    let _ = matcher.find_iter(line.as_bytes(), |m| {
+        if m.end() - m.start() > limit {
+            cut = Some(m.start() + limit);
+            return false;
+        }
+        true
+    });
+
+    match cut {
+        Some(mut end) => {
+            while !line.is_char_boundary(end) {
+                end -= 1;
             }
+            (line[..end].to_string(), true)
         }
+        None => (line.to_string(), false),
+    }
+}
 
-        Ok(())
+/// `bytes` without trailing ASCII whitespace. Unlike `str::trim_end`, which
+/// also trims Unicode whitespace, bytes outside ASCII are left alone.
+fn trim_end_bytes(bytes: &[u8]) -> &[u8] {
+    let end = bytes
+        .iter()
+        .rposition(|b| !b.is_ascii_whitespace())
+        .map_or(0, |i| i + 1);
+    &bytes[..end]
+}
+
+/// Bytes either side of the match that `hex_preview` shows.
+const HEX_PREVIEW_CONTEXT: usize = 4;
+
+/// Hex dump of the first match in `bytes` and up to `HEX_PREVIEW_CONTEXT`
+/// bytes either side, the match in brackets: `61 20 [6e 65 65] 0a`.
+fn hex_preview(matcher: &RegexMatcher, bytes: &[u8]) -> Option<String> {
+    let m = matcher.find(bytes).ok().flatten()?;
+    let start = m.start().saturating_sub(HEX_PREVIEW_CONTEXT);
+    let end = (m.end() + HEX_PREVIEW_CONTEXT).min(bytes.len());
+    let hex = |range: &[u8]| {
+        range
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+
+    let parts = [
+        hex(&bytes[start..m.start()]),
+        format!("[{}]", hex(&bytes[m.start()..m.end()])),
+        hex(&bytes[m.end()..end]),
+    ];
+    Some(
+        parts
+            .into_iter()
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join(" "),
+    )
+}
+
+/// Return up to `2 * radius` characters of `line` centered on its first
+/// match, with `…` marking the sides that were cut off.
+fn snippet_around_match(matcher: &RegexMatcher, line: &str, radius: usize) -> Option<String> {
+    let m = matcher.find(line.as_bytes()).ok().flatten()?;
+    let center_byte = (m.start() + m.end()) / 2;
+
+    // Work in characters so the window never splits a UTF-8 sequence
+    let total = line.chars().count();
+    let center = line
+        .char_indices()
+        .take_while(|(i, _)| *i < center_byte)
+        .count();
+    let start = center.saturating_sub(radius);
+    let end = (center + radius).min(total);
+
+    let byte_at = |index: usize| {
+        line.char_indices()
+            .nth(index)
+            .map_or(line.len(), |(i, _)| i)
+    };
+
+    let mut snippet = String::new();
+    if start > 0 {
+        snippet.push('…');
+    }
+    snippet.push_str(&line[byte_at(start)..byte_at(end)]);
+    if end < total {
+        snippet.push('…');
+    }
+    Some(snippet)
+}
+
+/// Find the root of the git repository containing `path`, if any.
+fn find_git_root(path: &Path) -> Option<&Path> {
+    // `.git` is a directory in normal checkouts and a file in worktrees
+    path.ancestors()
+        .skip(1)
+        .find(|dir| dir.join(".git").exists())
+}
+
+fn build_globset(globs: &[String]) -> PyResult<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for glob in globs {
+        let glob =
+            Glob::new(glob).map_err(|e| PatternError::new_err(format!("Invalid glob: {}", e)))?;
+        builder.add(glob);
+    }
+    builder
+        .build()
+        .map_err(|e| PatternError::new_err(format!("Invalid glob: {}", e)))
+}
+
+fn file_mode(path: &Path) -> Option<FileMode> {
+    let metadata = fs::metadata(path).ok()?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        Some(FileMode::Bits(metadata.permissions().mode() & 0o7777))
+    }
+    #[cfg(not(unix))]
+    {
+        Some(FileMode::ReadOnly(metadata.permissions().readonly()))
+    }
+}
+
+/// Fill in `blame` for matches from the file at `path`, running one
+/// `git blame` for all of their lines.
+fn attach_blame(path: &Path, matches: &mut [SearchMatch]) {
+    let mut lines: Vec<u64> = matches.iter().filter_map(|m| m.line_number).collect();
+    lines.sort_unstable();
+    lines.dedup();
+
+    let blamed = if lines.is_empty() {
+        HashMap::new()
+    } else {
+        blame_lines(path, &lines).unwrap_or_default()
+    };
+    for m in matches {
+        m.blame = Some(m.line_number.and_then(|n| blamed.get(&n).cloned()));
+    }
+}
+
+/// Map each of `lines` to its last commit hash and author, from
+/// `git blame --porcelain`. Returns `None` if git fails, e.g. outside a
+/// repository.
+fn blame_lines(path: &Path, lines: &[u64]) -> Option<HashMap<u64, (String, String)>> {
+    let dir = path.parent().filter(|p| !p.as_os_str().is_empty());
+    let mut command = Command::new("git");
+    command
+        .current_dir(dir.unwrap_or(Path::new(".")))
+        .args(["blame", "--porcelain"]);
+    for line in lines {
+        command.arg("-L").arg(format!("{},{}", line, line));
+    }
+    command.arg("--").arg(path.file_name()?);
+
+    let output = command.stderr(Stdio::null()).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    // Each blamed line starts with a "<hash> <orig> <final> [<count>]"
+    // header; a commit's "author" line is only given the first time it
+    // appears, so remember authors by hash.
+    let mut authors: HashMap<String, String> = HashMap::new();
+    let mut line_commits: Vec<(u64, String)> = Vec::new();
+    let mut current: Option<String> = None;
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        if line.starts_with('\t') {
+            continue;
+        }
+        if let Some(author) = line.strip_prefix("author ") {
+            if let Some(commit) = &current {
+                authors.insert(commit.clone(), author.to_string());
+            }
+            continue;
+        }
+
+        let mut fields = line.split(' ');
+        let (Some(hash), Some(_), Some(final_line)) = (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        // SHA-1 hashes are 40 hex digits, SHA-256 ones 64
+        if matches!(hash.len(), 40 | 64) && hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+            if let Ok(final_line) = final_line.parse() {
+                line_commits.push((final_line, hash.to_string()));
+                current = Some(hash.to_string());
+            }
+        }
     }
+
+    Some(
+        line_commits
+            .into_iter()
+            .map(|(line, commit)| {
+                let author = authors.get(&commit).cloned().unwrap_or_default();
+                (line, (commit, author))
+            })
+            .collect(),
+    )
+}
+
+/// Lines of unchanged text around each change in a `unified_diff` hunk.
+const DIFF_CONTEXT: usize = 3;
+
+/// A unified diff of `old` against `new`, where line `i` of `old` became
+/// `new[i]` (which may hold several lines, or none), or `None` if nothing
+/// changed. Changes closer than twice `DIFF_CONTEXT` share a hunk.
+fn unified_diff(path: &str, old: &[&str], new: &[String]) -> Option<String> {
+    let changed: Vec<usize> = (0..old.len()).filter(|&i| old[i] != new[i]).collect();
+    if changed.is_empty() {
+        return None;
+    }
+
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for &i in &changed {
+        let start = i.saturating_sub(DIFF_CONTEXT);
+        let end = (i + DIFF_CONTEXT + 1).min(old.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    // A line without its terminator can only be the file's last
+    let push_line = |out: &mut String, prefix: char, line: &str| {
+        out.push(prefix);
+        out.push_str(line);
+        if !line.ends_with('\n') {
+            out.push_str("\n\\ No newline at end of file\n");
+        }
+    };
+    let mut out = format!("--- {}\n+++ {}\n", path, path);
+    // How many more lines `new` has than `old` before the current hunk
+    let mut shift: isize = 0;
+    for (start, end) in hunks {
+        let mut body = String::new();
+        let mut new_len = 0;
+        for i in start..end {
+            if old[i] == new[i] {
+                push_line(&mut body, ' ', old[i]);
+                new_len += 1;
+                continue;
+            }
+            push_line(&mut body, '-', old[i]);
+            for line in new[i].split_inclusive('\n') {
+                push_line(&mut body, '+', line);
+                new_len += 1;
+            }
+        }
+        // An empty side starts at the line before it, as diff reports it
+        let new_start = start as isize + shift + (new_len > 0) as isize;
+        out.push_str(&format!(
+            "@@ -{},{} +{},{} @@\n",
+            start + 1,
+            end - start,
+            new_start,
+            new_len
+        ));
+        out.push_str(&body);
+        shift += new_len as isize - (end - start) as isize;
+    }
+    Some(out)
+}
+
+/// Replace `path` with `contents` by writing a sibling temporary file and
+/// renaming it over the original, keeping the original's permissions.
+fn write_atomically(path: &Path, contents: &str) -> io::Result<()> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    let mut tmp_name = std::ffi::OsString::from(".");
+    tmp_name.push(name);
+    tmp_name.push(".sup-tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let permissions = fs::metadata(path)?.permissions();
+    let result = fs::write(&tmp_path, contents)
+        .and_then(|()| fs::set_permissions(&tmp_path, permissions))
+        .and_then(|()| fs::rename(&tmp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+fn symlink_matches(globs: &GlobSet, path: &Path) -> bool {
+    path.file_name().map_or(false, |name| globs.is_match(name))
+        || fs::read_link(path).map_or(false, |target| globs.is_match(target))
+}
+
+/// The path a walk error is about, if it names one.
+fn walk_error_path(e: &ignore::Error) -> Option<&Path> {
+    match e {
+        ignore::Error::WithPath { path, .. } => Some(path),
+        ignore::Error::Loop { child, .. } => Some(child),
+        ignore::Error::WithDepth { err, .. } | ignore::Error::WithLineNumber { err, .. } => {
+            walk_error_path(err)
+        }
+        _ => None,
+    }
+}
+
+fn walk_error(e: &ignore::Error) -> PyErr {
+    SearchIOError::new_err(format!("Walk error: {}", e))
+}
+
+/// Describe `pattern` for query builders: whether it compiles (and the
+/// error if not), the names of its named capture groups, and whether it
+/// matches only a literal string.
+#[pyfunction]
+fn explain_pattern(pattern: &str, py: Python) -> PyResult<Py<PyDict>> {
+    let dict = PyDict::new_bound(py);
+
+    // Validate exactly like the RipGrep constructor does
+    if let Err(e) = RegexMatcher::new(pattern) {
+        dict.set_item("valid", false)?;
+        dict.set_item("error", e.to_string())?;
+        dict.set_item("captures", PyList::empty_bound(py))?;
+        dict.set_item("is_literal", false)?;
+        return Ok(dict.into());
+    }
+
+    let captures: Vec<String> = regex::Regex::new(pattern)
+        .map(|re| re.capture_names().flatten().map(str::to_string).collect())
+        .unwrap_or_default();
+    let is_literal = regex_syntax::parse(pattern)
+        .map(|hir| hir.properties().is_literal())
+        .unwrap_or(false);
+
+    dict.set_item("valid", true)?;
+    dict.set_item("error", py.None())?;
+    dict.set_item("captures", captures)?;
+    dict.set_item("is_literal", is_literal)?;
+    Ok(dict.into())
+}
+
+/// Check `pattern` the way the `RipGrep` constructor would with the same
+/// `options`, returning the error message if it doesn't compile, or `None`.
+#[pyfunction]
+#[pyo3(signature = (pattern, **options))]
+fn validate_pattern(
+    pattern: &str,
+    options: Option<&Bound<'_, PyDict>>,
+) -> PyResult<Option<String>> {
+    let options = SearchOptions::from_kwargs(options)?;
+    let pattern = literal_pattern(&options, normalize_text(options.normalize, pattern));
+    Ok(matcher_builder(&options)
+        .build(&pattern)
+        .err()
+        .map(|e| e.to_string()))
+}
+
+/// Whether `pattern` compiles with `options`, per `validate_pattern`.
+#[pyfunction]
+#[pyo3(signature = (pattern, **options))]
+fn is_valid_pattern(pattern: &str, options: Option<&Bound<'_, PyDict>>) -> PyResult<bool> {
+    Ok(validate_pattern(pattern, options)?.is_none())
 }
 
 // Binary is now shipped as a file in the package, not embedded
 
+/// Run the bundled ripgrep binary with `args`, returning its exit code,
+/// stdout and stderr. `input` is written to its stdin, so with no path in
+/// `args` ripgrep searches those bytes instead of any file. If it's still
+/// running after `timeout_secs`, it's killed and `TimeoutError` raised.
+/// `threads` passes `--threads` and `json` passes `--json`, unless `args`
+/// already sets them.
 #[pyfunction]
-fn run_ripgrep(args: Vec<String>) -> PyResult<(i32, String, String)> {
+#[pyo3(signature = (args, stdout_fd=None, input=None, timeout_secs=None, threads=None, json=false))]
+fn run_ripgrep(
+    mut args: Vec<String>,
+    stdout_fd: Option<i32>,
+    input: Option<Vec<u8>>,
+    timeout_secs: Option<f64>,
+    threads: Option<usize>,
+    json: bool,
+    py: Python<'_>,
+) -> PyResult<(i32, String, String)> {
     let binary_path = get_binary_path()?;
+    if json {
+        add_flag(&mut args, &["--json"], &["--json"]);
+    }
+    if let Some(threads) = threads {
+        add_flag(
+            &mut args,
+            &["--threads", "-j"],
+            &["--threads", &threads.to_string()],
+        );
+    }
+    let timeout = timeout_secs
+        .map(|secs| {
+            Duration::try_from_secs_f64(secs).map_err(|_| {
+                PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                    "Invalid timeout_secs: {}",
+                    secs
+                ))
+            })
+        })
+        .transpose()?;
+
+    let mut command = Command::new(&binary_path);
+    command.args(args);
+
+    // Write straight to the caller's descriptor instead of capturing stdout
+    if let Some(fd) = stdout_fd {
+        command.stdout(stdio_from_fd(fd)?);
+    }
 
     // Run the binary
-    let output = Command::new(&binary_path)
+    let run_error = |e: io::Error| SearchIOError::new_err(format!("Failed to run ripgrep: {}", e));
+    if input.is_none() && timeout.is_none() {
+        let output = command.output().map_err(run_error)?;
+        let exit_code = output.status.code().unwrap_or(-1);
+        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+        return Ok((exit_code, stdout, stderr));
+    }
+
+    if input.is_some() {
+        command.stdin(Stdio::piped());
+    }
+    if stdout_fd.is_none() {
+        command.stdout(Stdio::piped());
+    }
+    command.stderr(Stdio::piped());
+    let mut child = command.spawn().map_err(run_error)?;
+
+    // Feed stdin and drain both pipes on their own threads, so a large
+    // input can't block on ripgrep waiting for its full stdout to be read
+    let writer = child
+        .stdin
+        .take()
+        .zip(input)
+        .map(|(mut stdin, input)| thread::spawn(move || stdin.write_all(&input)));
+    let drain = |pipe: Option<Box<dyn io::Read + Send>>| {
+        thread::spawn(move || {
+            let mut buffer = Vec::new();
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.read_to_end(&mut buffer);
+            }
+            buffer
+        })
+    };
+    let stdout = drain(child.stdout.take().map(|p| Box::new(p) as _));
+    let stderr = drain(child.stderr.take().map(|p| Box::new(p) as _));
+
+    let status = py.allow_threads(|| {
+        let Some(timeout) = timeout else {
+            return child.wait().map(Some);
+        };
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(status) = child.try_wait()? {
+                return Ok(Some(status));
+            }
+            if Instant::now() >= deadline {
+                // Killing closes the pipes, so the threads all finish
+                let _ = child.kill();
+                child.wait()?;
+                return Ok(None);
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    });
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+    let written = writer.map(|writer| writer.join().expect("stdin writer panicked"));
+
+    let Some(status) = status.map_err(run_error)? else {
+        return Err(TimeoutError::new_err(format!(
+            "ripgrep didn't finish within {} seconds",
+            timeout_secs.unwrap_or_default()
+        )));
+    };
+    // ripgrep may stop reading early, such as under --max-count
+    if let Some(Err(e)) = written {
+        if e.kind() != io::ErrorKind::BrokenPipe {
+            return Err(run_error(e));
+        }
+    }
+
+    Ok((
+        status.code().unwrap_or(-1),
+        String::from_utf8_lossy(&stdout).to_string(),
+        String::from_utf8_lossy(&stderr).to_string(),
+    ))
+}
+
+/// Put `flag` at the front of `args` unless the flag is already given under
+/// one of `names`, as `--name`, `--name=VALUE`, `-x` or `-xVALUE`. Anything
+/// after `--` is positional and doesn't count.
+fn add_flag(args: &mut Vec<String>, names: &[&str], flag: &[&str]) {
+    let given = args.iter().take_while(|arg| *arg != "--").any(|arg| {
+        names.iter().any(|name| match arg.strip_prefix(name) {
+            Some(rest) if name.starts_with("--") => rest.is_empty() || rest.starts_with('='),
+            Some(_) => true,
+            None => false,
+        })
+    });
+    if !given {
+        args.splice(0..0, flag.iter().map(|arg| arg.to_string()));
+    }
+}
+
+/// Start the bundled ripgrep binary with `args` in the background,
+/// returning a handle that can cancel it or wait for its output.
+#[pyfunction]
+fn run_ripgrep_cancellable(args: Vec<String>) -> PyResult<RipgrepProcess> {
+    let binary_path = get_binary_path()?;
+
+    let mut child = Command::new(&binary_path)
         .args(args)
-        .output()
-        .map_err(|e| {
-            PyErr::new::<pyo3::exceptions::PyIOError, _>(format!("Failed to run ripgrep: {}", e))
-        })?;
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| SearchIOError::new_err(format!("Failed to run ripgrep: {}", e)))?;
+
+    // Drain both pipes on their own threads so a chatty process can never
+    // block on a full pipe, whether it's waited for or killed.
+    let drain = |pipe: Option<Box<dyn io::Read + Send>>| {
+        thread::spawn(move || {
+            let mut buffer = Vec::new();
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.read_to_end(&mut buffer);
+            }
+            buffer
+        })
+    };
+    let stdout = drain(child.stdout.take().map(|p| Box::new(p) as _));
+    let stderr = drain(child.stderr.take().map(|p| Box::new(p) as _));
+
+    Ok(RipgrepProcess {
+        child: Mutex::new(child),
+        output: Mutex::new(Some((stdout, stderr))),
+    })
+}
+
+/// A ripgrep process started by `run_ripgrep_cancellable`.
+#[pyclass]
+struct RipgrepProcess {
+    child: Mutex<Child>,
+    /// Threads collecting stdout and stderr, until `wait` joins them.
+    output: Mutex<Option<(JoinHandle<Vec<u8>>, JoinHandle<Vec<u8>>)>>,
+}
+
+#[pymethods]
+impl RipgrepProcess {
+    /// Kill the process, if it's still running, and reap it. Safe to call
+    /// from any thread, including while another thread is in `wait`.
+    fn cancel(&self) -> PyResult<()> {
+        let mut child = self.child.lock().unwrap();
+        if child.try_wait().ok().flatten().is_none() {
+            let _ = child.kill();
+        }
+        child
+            .wait()
+            .map_err(|e| SearchIOError::new_err(format!("Failed to wait for ripgrep: {}", e)))?;
+        Ok(())
+    }
+
+    /// Wait for the process to exit and return `(exit_code, stdout, stderr)`
+    /// like `run_ripgrep`. On Unix a cancelled process reports exit code -1.
+    fn wait(&self, py: Python<'_>) -> PyResult<(i32, String, String)> {
+        let Some((stdout, stderr)) = self.output.lock().unwrap().take() else {
+            return Err(PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(
+                "wait() was already called",
+            ));
+        };
+
+        py.allow_threads(|| {
+            // The pipes close once the process exits (or is killed), so the
+            // readers finish first; only then take the lock to reap it
+            let stdout = stdout.join().unwrap_or_default();
+            let stderr = stderr.join().unwrap_or_default();
+            let status = self.child.lock().unwrap().wait().map_err(|e| {
+                SearchIOError::new_err(format!("Failed to wait for ripgrep: {}", e))
+            })?;
+
+            Ok((
+                status.code().unwrap_or(-1),
+                String::from_utf8_lossy(&stdout).to_string(),
+                String::from_utf8_lossy(&stderr).to_string(),
+            ))
+        })
+    }
+}
+
+/// Outcome of a ripgrep run, decoded from its exit code.
+#[pyclass(eq, eq_int)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RipgrepStatus {
+    /// Exit code 0: at least one match
+    #[pyo3(name = "FOUND")]
+    Found,
+    /// Exit code 1: ran successfully but nothing matched
+    #[pyo3(name = "NOT_FOUND")]
+    NotFound,
+    /// Any other exit code, or killed by a signal
+    #[pyo3(name = "ERROR")]
+    Error,
+}
+
+impl RipgrepStatus {
+    fn from_exit_code(code: i32) -> Self {
+        match code {
+            0 => RipgrepStatus::Found,
+            1 => RipgrepStatus::NotFound,
+            _ => RipgrepStatus::Error,
+        }
+    }
+}
+
+/// Like `run_ripgrep`, but returns `(status, exit_code, stdout, stderr)` so
+/// callers don't have to remember that 1 means "no matches".
+#[pyfunction]
+fn run_ripgrep_status(
+    args: Vec<String>,
+    py: Python<'_>,
+) -> PyResult<(RipgrepStatus, i32, String, String)> {
+    let (exit_code, stdout, stderr) = run_ripgrep(args, None, None, None, None, false, py)?;
+    Ok((
+        RipgrepStatus::from_exit_code(exit_code),
+        exit_code,
+        stdout,
+        stderr,
+    ))
+}
 
-    let exit_code = output.status.code().unwrap_or(-1);
-    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+/// Run the bundled ripgrep binary with `args` only to count matches,
+/// returning the total and the exit code. `--count-matches` and
+/// `--no-filename` are added unless `args` already ask for a count or say
+/// how to show file names, and the per-file counts are summed as they're
+/// read instead of capturing all of stdout. stderr is discarded.
+#[pyfunction]
+fn run_ripgrep_count(mut args: Vec<String>, py: Python<'_>) -> PyResult<(i64, i32)> {
+    let binary_path = get_binary_path()?;
+    add_flag(
+        &mut args,
+        &["--count-matches", "--count", "-c"],
+        &["--count-matches"],
+    );
+    add_flag(
+        &mut args,
+        &["--no-filename", "--with-filename", "-I", "-H"],
+        &["--no-filename"],
+    );
+
+    let run_error = |e: io::Error| SearchIOError::new_err(format!("Failed to run ripgrep: {}", e));
+    let mut child = Command::new(&binary_path)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(run_error)?;
+    let stdout = child.stdout.take().expect("stdout is piped");
+
+    let (total, status) = py
+        .allow_threads(|| {
+            let total = sum_counts(io::BufReader::new(stdout));
+            // Waited for even if reading failed, so it's never left a zombie
+            let status = child.wait();
+            Ok::<_, io::Error>((total?, status?))
+        })
+        .map_err(run_error)?;
+    Ok((total, status.code().unwrap_or(-1)))
+}
+
+/// Sum the counts ripgrep prints one per line under `--count` or
+/// `--count-matches`.
+fn sum_counts(reader: impl BufRead) -> io::Result<i64> {
+    let mut total = 0;
+    for line in reader.split(b'\n') {
+        let line = line?;
+        // A file name, if shown after all, comes before the last colon
+        let count = match line.iter().rposition(|&b| b == b':') {
+            Some(colon) => &line[colon + 1..],
+            None => &line[..],
+        };
+        let count = std::str::from_utf8(count).unwrap_or_default();
+        total += count.trim().parse::<i64>().unwrap_or(0);
+    }
+    Ok(total)
+}
+
+#[cfg(unix)]
+fn stdio_from_fd(fd: i32) -> PyResult<Stdio> {
+    use std::os::fd::{FromRawFd, OwnedFd};
+
+    if fd < 0 {
+        return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+            "Invalid file descriptor {}",
+            fd
+        )));
+    }
+    // Duplicate the descriptor so the caller keeps ownership of theirs.
+    // SAFETY: fcntl doesn't touch memory; a descriptor that isn't open
+    // just fails with EBADF.
+    let duplicate = unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) };
+    if duplicate < 0 {
+        let e = std::io::Error::last_os_error();
+        return Err(SearchIOError::new_err(format!(
+            "Invalid file descriptor {}: {}",
+            fd, e
+        )));
+    }
+    // SAFETY: `duplicate` is a newly opened descriptor nothing else owns
+    Ok(Stdio::from(unsafe { OwnedFd::from_raw_fd(duplicate) }))
+}
 
-    Ok((exit_code, stdout, stderr))
+#[cfg(not(unix))]
+fn stdio_from_fd(_fd: i32) -> PyResult<Stdio> {
+    Err(PyErr::new::<pyo3::exceptions::PyNotImplementedError, _>(
+        "stdout_fd is only supported on Unix",
+    ))
 }
 
+/// Smallest plausible size of the bundled ripgrep binary; real ones are
+/// megabytes, so anything below this is an empty or truncated build.
+const MIN_BINARY_SIZE: u64 = 64 * 1024;
+
 fn get_binary_path() -> PyResult<std::path::PathBuf> {
     Python::with_gil(|py| {
         let sup_module = py.import_bound("sup")?;
@@ -133,18 +4616,27 @@ fn get_binary_path() -> PyResult<std::path::PathBuf> {
         let module_path = std::path::Path::new(&file_path);
         let binary_path = module_path
             .parent()
-            .ok_or_else(|| {
-                PyErr::new::<pyo3::exceptions::PyIOError, _>("Failed to get parent directory")
-            })?
+            .ok_or_else(|| SearchIOError::new_err("Failed to get parent directory"))?
             .join("bin")
             .join(binary_name);
 
         if !binary_path.exists() {
-            return Err(PyErr::new::<pyo3::exceptions::PyIOError, _>(format!(
+            return Err(SearchIOError::new_err(format!(
                 "Ripgrep binary not found at: {}",
                 binary_path.display()
             )));
         }
+        // Running a truncated binary fails with a baffling OS error instead
+        let size = fs::metadata(&binary_path).map_or(0, |metadata| metadata.len());
+        if size < MIN_BINARY_SIZE {
+            return Err(PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(format!(
+                "Ripgrep binary at {} is only {} bytes: this build of sup was made \
+                 without a valid ripgrep binary; reinstall it, or rebuild with the \
+                 ripgrep source available",
+                binary_path.display(),
+                size
+            )));
+        }
 
         Ok(binary_path)
     })
@@ -156,10 +4648,97 @@ fn get_ripgrep_path() -> PyResult<String> {
     Ok(binary_path.to_string_lossy().to_string())
 }
 
+/// Target triple the bundled ripgrep binary was built for.
+const RG_TARGET: &str = env!("SUP_RG_TARGET");
+
+/// Git commit of the ripgrep source the bundled binary was built from.
+const RG_COMMIT: &str = env!("SUP_RG_COMMIT");
+
+/// The full git commit hash of the ripgrep source the bundled binary was
+/// built from, or `"unknown"` if the build couldn't tell.
+#[pyfunction]
+fn get_ripgrep_commit() -> &'static str {
+    RG_COMMIT
+}
+
+/// Version of the ripgrep source the bundled binary was built from.
+const RIPGREP_VERSION: &str = env!("RIPGREP_VERSION");
+
+/// The version of the bundled ripgrep binary, such as `"14.1.0"`, as read
+/// from its source at build time, or `"unknown"` if the build couldn't tell.
+#[pyfunction]
+fn ripgrep_version() -> &'static str {
+    RIPGREP_VERSION
+}
+
+/// Check that the bundled ripgrep binary was built for the running platform,
+/// comparing its target triple's architecture and OS with what Python
+/// reports. On a mismatch, such as a wheel installed on the wrong machine,
+/// a `RuntimeWarning` says what was expected and `False` is returned.
+#[pyfunction]
+fn verify_platform(py: Python<'_>) -> PyResult<bool> {
+    let platform = py.import_bound("platform")?;
+    let machine = platform
+        .call_method0("machine")?
+        .extract::<String>()?
+        .to_ascii_lowercase();
+    let system = py
+        .import_bound("sys")?
+        .getattr("platform")?
+        .extract::<String>()?;
+
+    let mut parts = RG_TARGET.split('-');
+    let arch = parts.next().unwrap_or_default();
+    let arch_ok = match arch {
+        "x86_64" => matches!(machine.as_str(), "x86_64" | "amd64"),
+        "aarch64" => matches!(machine.as_str(), "aarch64" | "arm64"),
+        "i686" | "i586" => matches!(machine.as_str(), "i386" | "i686" | "x86"),
+        _ => machine == arch,
+    };
+    let os_ok = if RG_TARGET.contains("-linux") {
+        system.starts_with("linux")
+    } else if RG_TARGET.contains("-apple-darwin") {
+        system == "darwin"
+    } else if RG_TARGET.contains("-windows") {
+        system == "win32"
+    } else {
+        RG_TARGET.contains(&system)
+    };
+
+    if !(arch_ok && os_ok) {
+        let message = format!(
+            "Bundled ripgrep was built for {}, but this is {} on {}; reinstall \
+             sup with a wheel for this platform",
+            RG_TARGET, machine, system
+        );
+        let warning = py.get_type_bound::<pyo3::exceptions::PyRuntimeWarning>();
+        PyErr::warn_bound(py, &warning, &message, 1)?;
+        return Ok(false);
+    }
+    Ok(true)
+}
+
 #[pymodule]
 fn _sup(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<RipGrep>()?;
+    m.add_class::<IgnoreMatcher>()?;
+    m.add_class::<RipgrepStatus>()?;
+    m.add_class::<SearchGenerator>()?;
+    m.add_class::<RipgrepProcess>()?;
     m.add_function(wrap_pyfunction!(run_ripgrep, m)?)?;
+    m.add_function(wrap_pyfunction!(run_ripgrep_status, m)?)?;
+    m.add_function(wrap_pyfunction!(run_ripgrep_count, m)?)?;
+    m.add_function(wrap_pyfunction!(run_ripgrep_cancellable, m)?)?;
     m.add_function(wrap_pyfunction!(get_ripgrep_path, m)?)?;
+    m.add_function(wrap_pyfunction!(get_ripgrep_commit, m)?)?;
+    m.add_function(wrap_pyfunction!(ripgrep_version, m)?)?;
+    m.add_function(wrap_pyfunction!(verify_platform, m)?)?;
+    m.add_function(wrap_pyfunction!(explain_pattern, m)?)?;
+    m.add_function(wrap_pyfunction!(validate_pattern, m)?)?;
+    m.add_function(wrap_pyfunction!(is_valid_pattern, m)?)?;
+    m.add_function(wrap_pyfunction!(options::set_default_options, m)?)?;
+    m.add_function(wrap_pyfunction!(options::get_default_options, m)?)?;
+    m.add_function(wrap_pyfunction!(limiter::set_global_concurrency, m)?)?;
+    m.add_function(wrap_pyfunction!(limiter::get_global_concurrency, m)?)?;
     Ok(())
 }
@@ -0,0 +1,86 @@
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::sync::{Condvar, Mutex, OnceLock};
+
+/// A counting semaphore shared by every search in the process, so a server
+/// running many searches at once can cap how many walk files concurrently.
+struct Limiter {
+    state: Mutex<LimiterState>,
+    released: Condvar,
+}
+
+#[derive(Default)]
+struct LimiterState {
+    /// `None` means unlimited.
+    limit: Option<usize>,
+    active: usize,
+    /// Most searches seen running at once since the limit was last set.
+    peak: usize,
+}
+
+/// Held for the duration of one search; dropping it frees the slot.
+pub(crate) struct Permit {
+    _private: (),
+}
+
+fn limiter() -> &'static Limiter {
+    static LIMITER: OnceLock<Limiter> = OnceLock::new();
+    LIMITER.get_or_init(|| Limiter {
+        state: Mutex::new(LimiterState::default()),
+        released: Condvar::new(),
+    })
+}
+
+/// Block until a search slot is free. Call without holding the GIL.
+pub(crate) fn acquire() -> Permit {
+    let limiter = limiter();
+    let mut state = limiter.state.lock().unwrap();
+    while state.limit.map_or(false, |limit| state.active >= limit) {
+        state = limiter.released.wait(state).unwrap();
+    }
+    state.active += 1;
+    state.peak = state.peak.max(state.active);
+    Permit { _private: () }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let limiter = limiter();
+        limiter.state.lock().unwrap().active -= 1;
+        limiter.released.notify_one();
+    }
+}
+
+/// Cap the number of searches that may run at once across all threads and
+/// `RipGrep` instances. Searches beyond the cap wait for a running one to
+/// finish; a generator gives up its slot while it waits for its consumer
+/// to catch up. `None` removes the cap.
+#[pyfunction]
+#[pyo3(signature = (n))]
+pub(crate) fn set_global_concurrency(n: Option<usize>) -> PyResult<()> {
+    if n == Some(0) {
+        return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+            "Concurrency limit must be at least 1",
+        ));
+    }
+
+    let limiter = limiter();
+    let mut state = limiter.state.lock().unwrap();
+    state.limit = n;
+    state.peak = state.active;
+    // A raised limit may free several slots at once
+    limiter.released.notify_all();
+    Ok(())
+}
+
+/// The current limit, how many searches are running now, and the most that
+/// have run at once since the limit was last set.
+#[pyfunction]
+pub(crate) fn get_global_concurrency(py: Python<'_>) -> PyResult<Bound<'_, PyDict>> {
+    let state = limiter().state.lock().unwrap();
+    let dict = PyDict::new_bound(py);
+    dict.set_item("limit", state.limit)?;
+    dict.set_item("active", state.active)?;
+    dict.set_item("peak", state.peak)?;
+    Ok(dict)
+}
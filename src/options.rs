@@ -0,0 +1,699 @@
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::sync::{Mutex, OnceLock};
+
+/// Options controlling how a `RipGrep` instance matches and walks.
+///
+/// Options are passed to Python as keyword arguments and every field here has
+/// a matching key in `to_dict`, so a dict produced by `to_dict` can be fed back
+/// into `update` unchanged.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SearchOptions {
+    pub(crate) case_insensitive: bool,
+    /// Like ripgrep's `-S`: case insensitive unless the pattern contains an
+    /// uppercase letter.
+    pub(crate) smart_case: bool,
+    pub(crate) hidden: bool,
+    /// Search files that `.gitignore`, `.ignore` or the global gitignore
+    /// would skip, like `rg --no-ignore`.
+    pub(crate) no_ignore: bool,
+    /// Globs selecting which symlinks to follow, by link name or target.
+    pub(crate) follow_links_matching: Vec<String>,
+    /// Follow every symlink, like `rg -L`. A link back to a directory the
+    /// walk is already inside is reported on stderr and not entered.
+    pub(crate) follow_links: bool,
+    /// Follow every symlink to a file, whatever its name.
+    pub(crate) follow_file_links: bool,
+    /// Follow every symlink to a directory, whatever its name. Left off,
+    /// the walk can't loop through a link back to an ancestor.
+    pub(crate) follow_dir_links: bool,
+    /// Matches longer than this many bytes are cut short and flagged.
+    pub(crate) max_match_len: Option<usize>,
+    /// Regex marking the start of each logical record, replacing lines.
+    pub(crate) record_separator: Option<String>,
+    pub(crate) read_strategy: ReadStrategy,
+    /// Characters of context kept on each side of the match in `snippet`.
+    pub(crate) snippet_radius: Option<usize>,
+    /// Report paths relative to the enclosing git repository's root.
+    pub(crate) git_relative: bool,
+    /// Cap on matches collected from the files of any one directory.
+    pub(crate) max_per_dir: Option<usize>,
+    /// Also report directories whose name matches the pattern.
+    pub(crate) match_dirs: bool,
+    /// Capture group names to report per match instead of the line.
+    pub(crate) named_output: Vec<String>,
+    /// Attach each matching line's last commit and author from `git blame`.
+    pub(crate) blame: bool,
+    /// Files with more lines than this are skipped, their matches dropped.
+    pub(crate) max_lines: Option<usize>,
+    /// Attach the matched file's permission bits (a read-only flag on
+    /// Windows).
+    pub(crate) include_mode: bool,
+    /// Stop searching a file, treating it as binary, at the first occurrence
+    /// of this byte. Takes precedence over `binary`.
+    pub(crate) binary_quit_byte: Option<u8>,
+    /// What to do with files that look binary, having a NUL byte.
+    pub(crate) binary: BinaryMode,
+    /// Drop matching lines that also match any of these patterns.
+    pub(crate) exclude_if_matches: Vec<String>,
+    /// Regex for heading lines, such as function definitions; each match
+    /// reports the nearest preceding one as `context_heading`.
+    pub(crate) context_pattern: Option<String>,
+    /// Report how each matching line was terminated.
+    pub(crate) line_ending: bool,
+    /// What happens when a result cap, `max_count`, `max_per_dir` or
+    /// `max_total`, drops matches.
+    pub(crate) limit_behavior: LimitBehavior,
+    /// Attach the paragraph (lines between blank lines) around each match.
+    pub(crate) paragraph: bool,
+    /// Skip files whose start looks binary by content sniffing.
+    pub(crate) text_only: bool,
+    /// Fraction of control bytes above which `text_only` calls a file binary.
+    pub(crate) text_threshold: TextThreshold,
+    /// Unicode normalization applied to the pattern and searched text.
+    pub(crate) normalize: Option<Normalization>,
+    /// Encoding label, such as `latin1` or `utf-16le`, that files are
+    /// transcoded from before matching, like `rg --encoding`. UTF-8 when
+    /// unset or `"auto"`.
+    pub(crate) encoding: Option<String>,
+    /// Stop searching a file at its first line longer than this many bytes,
+    /// such as in minified code. Unlike `max_match_len` this gives up on
+    /// the file rather than shortening what's reported.
+    pub(crate) skip_long_lines: Option<usize>,
+    /// Keep the matches found before `skip_long_lines` stopped a file,
+    /// instead of discarding the whole file.
+    pub(crate) keep_partial_long_lines: bool,
+    /// Walk directories in sorted order and sort the results by path, line
+    /// number and line, so repeated searches give identical output.
+    pub(crate) stable: bool,
+    /// Order of the results by file: each file's matches stay together and
+    /// in line order. Unsorted, for speed, when unset.
+    pub(crate) sort: Option<SortBy>,
+    /// Lines of context reported before each match, like `rg -B`.
+    pub(crate) before_context: usize,
+    /// Lines of context reported after each match, like `rg -A`.
+    pub(crate) after_context: usize,
+    /// Match against each line with runs of spaces and tabs collapsed to a
+    /// single space, still reporting the original line. Spans, snippets and
+    /// truncation refer to the collapsed line, so they're only approximate.
+    /// Not applied to `record_separator` searches.
+    pub(crate) collapse_whitespace: bool,
+    /// How deep a directory walk descends; files directly inside the root
+    /// are at depth 1.
+    pub(crate) max_depth: Option<usize>,
+    /// Keep a directory walk on the filesystem of the path it starts from,
+    /// like `rg --one-file-system`, so mounts under it aren't entered.
+    pub(crate) same_file_system: bool,
+    /// Skip files larger than this many bytes, like `rg --max-filesize`.
+    pub(crate) max_filesize: Option<u64>,
+    /// Raise `LimitExceeded` when the one file searched is over
+    /// `max_filesize`, rather than returning no matches. Walks always skip
+    /// such files.
+    pub(crate) max_filesize_error: bool,
+    /// Only search files at these depths of a directory walk.
+    pub(crate) include_depths: Vec<usize>,
+    /// Globs a walked file must match, like `rg -g`; a leading `!`
+    /// excludes instead.
+    pub(crate) include_globs: Vec<String>,
+    /// Globs excluding walked files and directories, like `rg -g '!GLOB'`.
+    pub(crate) exclude_globs: Vec<String>,
+    /// ripgrep's built-in file types a walked file must be one of, like
+    /// `rg -t`. As in ripgrep, a file matching an include glob is searched
+    /// whatever its type.
+    pub(crate) types: Vec<String>,
+    /// File types to skip, like `rg -T`.
+    pub(crate) types_not: Vec<String>,
+    /// Let matches span lines, like `rg -U --multiline-dotall`: `.` also
+    /// matches a newline, and a match is reported once, at the number of
+    /// the line it starts on, with all the lines it covers as `line`. The
+    /// searcher then holds a whole file in memory rather than a buffer of
+    /// lines, reading it in full unless it's memory-mapped.
+    pub(crate) multiline: bool,
+    /// Whether `.` matches a newline; follows `multiline` when unset.
+    pub(crate) dot_matches_new_line: Option<bool>,
+    /// Unicode-aware classes like `\w` and `\b`. Turned off, like
+    /// `rg --no-unicode`, they're ASCII-only and faster, and Unicode-only
+    /// syntax such as `\p{Greek}` is rejected.
+    pub(crate) unicode: UnicodeMode,
+    /// Treat `\r\n` as the line terminator, like `rg --crlf`: `$` matches
+    /// before it, and `line` drops exactly the terminator, keeping any
+    /// trailing spaces that are otherwise trimmed.
+    pub(crate) crlf: bool,
+    /// Report each match's absolute `(start, end)` byte range in the file.
+    /// Under `normalize` or `collapse_whitespace` it's a range of the
+    /// rewritten text.
+    pub(crate) file_span: bool,
+    /// Report the byte offset in the file where each matching line starts,
+    /// as `offset`, for tools that index content by position. Under
+    /// `normalize` or `collapse_whitespace` it's an offset in the rewritten
+    /// text.
+    pub(crate) absolute_offset: bool,
+    /// Worker threads for directory walks, `None` for one per core. Walks
+    /// under `max_per_dir`, which counts files in walk order, always run on
+    /// one thread.
+    pub(crate) threads: Option<usize>,
+    /// Report each matched line as `segments`, alternating non-match and
+    /// match text, starting and ending with a (possibly empty) non-match.
+    pub(crate) split: bool,
+    /// Match the pattern as literal text rather than a regex, like `rg -F`.
+    /// Applied when the instance is built; `pattern` holds the escaped form.
+    pub(crate) fixed_strings: bool,
+    /// Report how many lines of each file were scanned in `profile`, to
+    /// tell files read to the end from ones given up on early.
+    pub(crate) report_lines_scanned: bool,
+    /// Only match whole words, like `rg -w`.
+    pub(crate) word: bool,
+    /// Report the lines that don't match instead, like `rg -v`.
+    pub(crate) invert_match: bool,
+    /// Give each match an `id` derived from its file, line number, column
+    /// and line text, so the same match has the same id on every run.
+    pub(crate) with_ids: bool,
+    /// What to do when a file in a walk can't be searched.
+    pub(crate) on_error: OnError,
+    /// Stop reading a file once it has this many matches, like `rg -m`;
+    /// under `limit_behavior="error"`, one match more raises instead.
+    pub(crate) max_count: Option<usize>,
+    /// Stop the whole search once this many matches are collected.
+    pub(crate) max_total: Option<usize>,
+    /// Attach a hex dump of each line's first match and the bytes around
+    /// it, for showing binary matches in a terminal.
+    pub(crate) hex_preview: bool,
+    /// Report each line as the `bytes` read from the file rather than a
+    /// string, so lines that aren't UTF-8 keep their original bytes.
+    pub(crate) raw_bytes: bool,
+    /// Attach the numbered capture groups of each line's first match.
+    pub(crate) captures: bool,
+    /// Under `captures`, only the first this many groups are attached.
+    pub(crate) max_groups: Option<usize>,
+    /// Caller's tag, attached to every match as `label`.
+    pub(crate) label: Option<String>,
+    /// Report every occurrence in `matches`, including ones that overlap,
+    /// by searching again one byte past each match's start rather than
+    /// from its end. This costs a search per occurrence, so a line with
+    /// n matches is scanned up to n times over.
+    pub(crate) overlapping: bool,
+    /// Count lines to report each match's `line_number`. Turned off,
+    /// `line_number` is `None` and the searcher skips counting newlines,
+    /// which options that work by line number still need.
+    pub(crate) line_number: LineNumbers,
+    /// Report the 1-based column of each line's first match, counted in
+    /// characters rather than bytes, for jumping to it in an editor.
+    pub(crate) column: bool,
+}
+
+/// The `line_number` option, which is on by default.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct LineNumbers(pub(crate) bool);
+
+impl Default for LineNumbers {
+    fn default() -> Self {
+        LineNumbers(true)
+    }
+}
+
+/// The `unicode` option, which is on by default.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct UnicodeMode(pub(crate) bool);
+
+impl Default for UnicodeMode {
+    fn default() -> Self {
+        UnicodeMode(true)
+    }
+}
+
+/// The `text_threshold` option, which defaults to 30% control bytes.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct TextThreshold(pub(crate) f64);
+
+impl Default for TextThreshold {
+    fn default() -> Self {
+        TextThreshold(0.3)
+    }
+}
+
+/// How file contents are read: memory mapped, buffered, or chosen per search.
+///
+/// A memory map saves copying a large file into a buffer, but costs more to
+/// set up than reading a small one, and on some network filesystems it
+/// fails or is slower than plain reads. Also set by the `memory_map` key:
+/// `True` for `Mmap`, `False` for `Buffered` and `None` for `Auto`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum ReadStrategy {
+    #[default]
+    Auto,
+    Mmap,
+    Buffered,
+}
+
+impl ReadStrategy {
+    fn parse(value: &str) -> PyResult<Self> {
+        match value {
+            "auto" => Ok(ReadStrategy::Auto),
+            "mmap" => Ok(ReadStrategy::Mmap),
+            "buffered" => Ok(ReadStrategy::Buffered),
+            _ => Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "Invalid read_strategy: {} (expected 'auto', 'mmap' or 'buffered')",
+                value
+            ))),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            ReadStrategy::Auto => "auto",
+            ReadStrategy::Mmap => "mmap",
+            ReadStrategy::Buffered => "buffered",
+        }
+    }
+}
+
+/// How files containing a NUL byte, which ripgrep takes to be binary, are
+/// searched.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum BinaryMode {
+    /// Stop searching at the first NUL, like ripgrep's default, so a file
+    /// with one near its start yields no matches.
+    #[default]
+    Skip,
+    /// Search the whole file, treating each NUL as a line terminator.
+    Text,
+    /// Search the whole file as is, NULs and all.
+    Search,
+}
+
+impl BinaryMode {
+    fn parse(value: &str) -> PyResult<Self> {
+        match value {
+            "skip" => Ok(BinaryMode::Skip),
+            "text" => Ok(BinaryMode::Text),
+            "search" => Ok(BinaryMode::Search),
+            _ => Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "Invalid binary: {} (expected 'skip', 'text' or 'search')",
+                value
+            ))),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            BinaryMode::Skip => "skip",
+            BinaryMode::Text => "text",
+            BinaryMode::Search => "search",
+        }
+    }
+}
+
+/// Whether hitting a result cap quietly truncates or raises `LimitExceeded`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum LimitBehavior {
+    #[default]
+    Truncate,
+    Error,
+}
+
+impl LimitBehavior {
+    fn parse(value: &str) -> PyResult<Self> {
+        match value {
+            "truncate" => Ok(LimitBehavior::Truncate),
+            "error" => Ok(LimitBehavior::Error),
+            _ => Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "Invalid limit_behavior: {} (expected 'truncate' or 'error')",
+                value
+            ))),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            LimitBehavior::Truncate => "truncate",
+            LimitBehavior::Error => "error",
+        }
+    }
+}
+
+/// What a walk does with a file that can't be searched, such as one it
+/// has no permission to read.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum OnError {
+    /// Print the error to stderr and carry on.
+    #[default]
+    Print,
+    /// Carry on without a word.
+    Ignore,
+    /// Stop the search and raise the error.
+    Raise,
+    /// Carry on, and have `search` return the errors with the matches.
+    Collect,
+}
+
+impl OnError {
+    fn parse(value: &str) -> PyResult<Self> {
+        match value {
+            "print" => Ok(OnError::Print),
+            "ignore" => Ok(OnError::Ignore),
+            "raise" => Ok(OnError::Raise),
+            "collect" => Ok(OnError::Collect),
+            _ => Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "Invalid on_error: {} (expected 'print', 'ignore', 'raise' or 'collect')",
+                value
+            ))),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            OnError::Print => "print",
+            OnError::Ignore => "ignore",
+            OnError::Raise => "raise",
+            OnError::Collect => "collect",
+        }
+    }
+}
+
+/// Unicode normalization form for the `normalize` option.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Normalization {
+    Nfc,
+    Nfd,
+}
+
+impl Normalization {
+    fn parse(value: &str) -> PyResult<Self> {
+        match value {
+            "nfc" => Ok(Normalization::Nfc),
+            "nfd" => Ok(Normalization::Nfd),
+            _ => Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "Invalid normalize: {} (expected 'nfc' or 'nfd')",
+                value
+            ))),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Normalization::Nfc => "nfc",
+            Normalization::Nfd => "nfd",
+        }
+    }
+}
+
+/// What the `sort` option orders results by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum SortBy {
+    /// File path, lexicographically.
+    Path,
+    /// File modification time, oldest first, then path.
+    Modified,
+}
+
+impl SortBy {
+    fn parse(value: &str) -> PyResult<Self> {
+        match value {
+            "path" => Ok(SortBy::Path),
+            "modified" => Ok(SortBy::Modified),
+            _ => Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "Invalid sort: {} (expected 'path' or 'modified')",
+                value
+            ))),
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            SortBy::Path => "path",
+            SortBy::Modified => "modified",
+        }
+    }
+}
+
+impl SearchOptions {
+    /// Build options from defaults plus keyword overrides.
+    pub(crate) fn from_kwargs(kwargs: Option<&Bound<'_, PyDict>>) -> PyResult<Self> {
+        let mut options = SearchOptions::default();
+        options.update(kwargs)?;
+        Ok(options)
+    }
+
+    /// Apply keyword overrides on top of the current values.
+    pub(crate) fn update(&mut self, kwargs: Option<&Bound<'_, PyDict>>) -> PyResult<()> {
+        let Some(kwargs) = kwargs else {
+            return Ok(());
+        };
+
+        for (key, value) in kwargs.iter() {
+            let key = key.extract::<String>()?;
+            match key.as_str() {
+                "case_insensitive" => self.case_insensitive = value.extract()?,
+                "smart_case" => self.smart_case = value.extract()?,
+                "hidden" => self.hidden = value.extract()?,
+                "no_ignore" => self.no_ignore = value.extract()?,
+                "follow_links_matching" => self.follow_links_matching = value.extract()?,
+                "follow_links" => self.follow_links = value.extract()?,
+                "follow_file_links" => self.follow_file_links = value.extract()?,
+                "follow_dir_links" => self.follow_dir_links = value.extract()?,
+                "max_match_len" => self.max_match_len = value.extract()?,
+                "record_separator" => self.record_separator = value.extract()?,
+                "snippet_radius" => self.snippet_radius = value.extract()?,
+                "git_relative" => self.git_relative = value.extract()?,
+                "max_per_dir" => self.max_per_dir = value.extract()?,
+                "match_dirs" => self.match_dirs = value.extract()?,
+                "named_output" => self.named_output = value.extract()?,
+                "blame" => self.blame = value.extract()?,
+                "max_lines" => self.max_lines = value.extract()?,
+                "include_mode" => self.include_mode = value.extract()?,
+                "exclude_if_matches" => self.exclude_if_matches = value.extract()?,
+                "context_pattern" => self.context_pattern = value.extract()?,
+                "line_ending" => self.line_ending = value.extract()?,
+                "paragraph" => self.paragraph = value.extract()?,
+                "text_only" => self.text_only = value.extract()?,
+                "encoding" => self.encoding = value.extract()?,
+                "skip_long_lines" => self.skip_long_lines = value.extract()?,
+                "keep_partial_long_lines" => self.keep_partial_long_lines = value.extract()?,
+                "stable" => self.stable = value.extract()?,
+                "sort" => {
+                    self.sort = value
+                        .extract::<Option<String>>()?
+                        .map(|key| SortBy::parse(&key))
+                        .transpose()?
+                }
+                "before_context" => self.before_context = value.extract()?,
+                "after_context" => self.after_context = value.extract()?,
+                "collapse_whitespace" => self.collapse_whitespace = value.extract()?,
+                "max_depth" => self.max_depth = value.extract()?,
+                "same_file_system" => self.same_file_system = value.extract()?,
+                "max_filesize" => self.max_filesize = value.extract()?,
+                "max_filesize_error" => self.max_filesize_error = value.extract()?,
+                "include_depths" => self.include_depths = value.extract()?,
+                "include_globs" => self.include_globs = value.extract()?,
+                "exclude_globs" => self.exclude_globs = value.extract()?,
+                "types" => self.types = value.extract()?,
+                "types_not" => self.types_not = value.extract()?,
+                "multiline" => self.multiline = value.extract()?,
+                "dot_matches_new_line" => self.dot_matches_new_line = value.extract()?,
+                "unicode" => self.unicode = UnicodeMode(value.extract()?),
+                "crlf" => self.crlf = value.extract()?,
+                "file_span" => self.file_span = value.extract()?,
+                "absolute_offset" => self.absolute_offset = value.extract()?,
+                "threads" => self.threads = value.extract()?,
+                "split" => self.split = value.extract()?,
+                "fixed_strings" => self.fixed_strings = value.extract()?,
+                "report_lines_scanned" => self.report_lines_scanned = value.extract()?,
+                "word" => self.word = value.extract()?,
+                "invert_match" => self.invert_match = value.extract()?,
+                "with_ids" => self.with_ids = value.extract()?,
+                "on_error" => self.on_error = OnError::parse(&value.extract::<String>()?)?,
+                "max_count" => self.max_count = value.extract()?,
+                "max_total" => self.max_total = value.extract()?,
+                "hex_preview" => self.hex_preview = value.extract()?,
+                "raw_bytes" => self.raw_bytes = value.extract()?,
+                "captures" => self.captures = value.extract()?,
+                "max_groups" => self.max_groups = value.extract()?,
+                "label" => self.label = value.extract()?,
+                "overlapping" => self.overlapping = value.extract()?,
+                "line_number" => self.line_number = LineNumbers(value.extract()?),
+                "column" => self.column = value.extract()?,
+                "text_threshold" => {
+                    let threshold: f64 = value.extract()?;
+                    if !(0.0..=1.0).contains(&threshold) {
+                        return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                            "Invalid text_threshold: {} (expected 0.0 to 1.0)",
+                            threshold
+                        )));
+                    }
+                    self.text_threshold = TextThreshold(threshold);
+                }
+                "binary_quit_byte" => {
+                    self.binary_quit_byte = match value.extract::<Option<i64>>()? {
+                        None => None,
+                        Some(byte) => Some(u8::try_from(byte).map_err(|_| {
+                            PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                                "Invalid binary_quit_byte: {} (expected 0 to 255)",
+                                byte
+                            ))
+                        })?),
+                    }
+                }
+                "binary" => self.binary = BinaryMode::parse(&value.extract::<String>()?)?,
+                "limit_behavior" => {
+                    self.limit_behavior = LimitBehavior::parse(&value.extract::<String>()?)?
+                }
+                "normalize" => {
+                    self.normalize = value
+                        .extract::<Option<String>>()?
+                        .map(|form| Normalization::parse(&form))
+                        .transpose()?
+                }
+                "read_strategy" => {
+                    self.read_strategy = ReadStrategy::parse(&value.extract::<String>()?)?
+                }
+                "memory_map" => {
+                    self.read_strategy = match value.extract::<Option<bool>>()? {
+                        Some(true) => ReadStrategy::Mmap,
+                        Some(false) => ReadStrategy::Buffered,
+                        None => ReadStrategy::Auto,
+                    }
+                }
+                _ => {
+                    return Err(PyErr::new::<pyo3::exceptions::PyTypeError, _>(format!(
+                        "Unknown search option: {}",
+                        key
+                    )))
+                }
+            }
+        }
+
+        if self.case_insensitive && self.smart_case {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                "case_insensitive and smart_case can't both be set",
+            ));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn to_dict<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let dict = PyDict::new_bound(py);
+        dict.set_item("case_insensitive", self.case_insensitive)?;
+        dict.set_item("smart_case", self.smart_case)?;
+        dict.set_item("hidden", self.hidden)?;
+        dict.set_item("no_ignore", self.no_ignore)?;
+        dict.set_item("follow_links_matching", &self.follow_links_matching)?;
+        dict.set_item("follow_links", self.follow_links)?;
+        dict.set_item("follow_file_links", self.follow_file_links)?;
+        dict.set_item("follow_dir_links", self.follow_dir_links)?;
+        dict.set_item("max_match_len", self.max_match_len)?;
+        dict.set_item("record_separator", &self.record_separator)?;
+        dict.set_item("read_strategy", self.read_strategy.as_str())?;
+        dict.set_item("snippet_radius", self.snippet_radius)?;
+        dict.set_item("git_relative", self.git_relative)?;
+        dict.set_item("max_per_dir", self.max_per_dir)?;
+        dict.set_item("match_dirs", self.match_dirs)?;
+        dict.set_item("named_output", &self.named_output)?;
+        dict.set_item("blame", self.blame)?;
+        dict.set_item("max_lines", self.max_lines)?;
+        dict.set_item("include_mode", self.include_mode)?;
+        dict.set_item("binary_quit_byte", self.binary_quit_byte)?;
+        dict.set_item("binary", self.binary.as_str())?;
+        dict.set_item("exclude_if_matches", &self.exclude_if_matches)?;
+        dict.set_item("context_pattern", &self.context_pattern)?;
+        dict.set_item("line_ending", self.line_ending)?;
+        dict.set_item("limit_behavior", self.limit_behavior.as_str())?;
+        dict.set_item("paragraph", self.paragraph)?;
+        dict.set_item("text_only", self.text_only)?;
+        dict.set_item("text_threshold", self.text_threshold.0)?;
+        dict.set_item("normalize", self.normalize.map(|form| form.as_str()))?;
+        dict.set_item("encoding", &self.encoding)?;
+        dict.set_item("skip_long_lines", self.skip_long_lines)?;
+        dict.set_item("keep_partial_long_lines", self.keep_partial_long_lines)?;
+        dict.set_item("stable", self.stable)?;
+        dict.set_item("sort", self.sort.map(|key| key.as_str()))?;
+        dict.set_item("before_context", self.before_context)?;
+        dict.set_item("after_context", self.after_context)?;
+        dict.set_item("collapse_whitespace", self.collapse_whitespace)?;
+        dict.set_item("max_depth", self.max_depth)?;
+        dict.set_item("same_file_system", self.same_file_system)?;
+        dict.set_item("max_filesize", self.max_filesize)?;
+        dict.set_item("max_filesize_error", self.max_filesize_error)?;
+        dict.set_item("include_depths", &self.include_depths)?;
+        dict.set_item("include_globs", &self.include_globs)?;
+        dict.set_item("exclude_globs", &self.exclude_globs)?;
+        dict.set_item("types", &self.types)?;
+        dict.set_item("types_not", &self.types_not)?;
+        dict.set_item("multiline", self.multiline)?;
+        dict.set_item("dot_matches_new_line", self.dot_matches_new_line)?;
+        dict.set_item("unicode", self.unicode.0)?;
+        dict.set_item("crlf", self.crlf)?;
+        dict.set_item("file_span", self.file_span)?;
+        dict.set_item("absolute_offset", self.absolute_offset)?;
+        dict.set_item("threads", self.threads)?;
+        dict.set_item("split", self.split)?;
+        dict.set_item("fixed_strings", self.fixed_strings)?;
+        dict.set_item("report_lines_scanned", self.report_lines_scanned)?;
+        dict.set_item("word", self.word)?;
+        dict.set_item("invert_match", self.invert_match)?;
+        dict.set_item("with_ids", self.with_ids)?;
+        dict.set_item("on_error", self.on_error.as_str())?;
+        dict.set_item("max_count", self.max_count)?;
+        dict.set_item("max_total", self.max_total)?;
+        dict.set_item("hex_preview", self.hex_preview)?;
+        dict.set_item("raw_bytes", self.raw_bytes)?;
+        dict.set_item("captures", self.captures)?;
+        dict.set_item("max_groups", self.max_groups)?;
+        dict.set_item("label", &self.label)?;
+        dict.set_item("overlapping", self.overlapping)?;
+        dict.set_item("line_number", self.line_number.0)?;
+        dict.set_item("column", self.column)?;
+        Ok(dict)
+    }
+}
+
+/// Which sub-pattern a multi-pattern `RipGrep` reports for a matching line.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum PatternPriority {
+    /// The first pattern, in declaration order, that matches.
+    #[default]
+    First,
+    /// The pattern with the longest match; ties go to the earlier pattern.
+    Longest,
+}
+
+impl PatternPriority {
+    pub(crate) fn parse(value: &str) -> PyResult<Self> {
+        match value {
+            "first" => Ok(PatternPriority::First),
+            "longest" => Ok(PatternPriority::Longest),
+            _ => Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
+                "Invalid priority: {} (expected 'first' or 'longest')",
+                value
+            ))),
+        }
+    }
+
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            PatternPriority::First => "first",
+            PatternPriority::Longest => "longest",
+        }
+    }
+}
+
+fn default_options() -> &'static Mutex<SearchOptions> {
+    static DEFAULT_OPTIONS: OnceLock<Mutex<SearchOptions>> = OnceLock::new();
+    DEFAULT_OPTIONS.get_or_init(|| Mutex::new(SearchOptions::default()))
+}
+
+/// Replace the module-wide default options used by `sup.search`.
+///
+/// Calling it with no arguments restores the built-in defaults.
+#[pyfunction]
+#[pyo3(signature = (**options))]
+pub(crate) fn set_default_options(options: Option<&Bound<'_, PyDict>>) -> PyResult<()> {
+    let options = SearchOptions::from_kwargs(options)?;
+    *default_options().lock().unwrap() = options;
+    Ok(())
+}
+
+#[pyfunction]
+pub(crate) fn get_default_options(py: Python<'_>) -> PyResult<Bound<'_, PyDict>> {
+    let options = default_options().lock().unwrap().clone();
+    options.to_dict(py)
+}
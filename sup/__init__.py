@@ -1,31 +1,176 @@
+import base64
+import json
+import shutil
+import statistics
+import struct
 import subprocess
 import sys
-from typing import List, Optional, Union
+import time
+from typing import Callable, Dict, List, Optional, Union
 
-from sup._sup import RipGrep, get_ripgrep_path, run_ripgrep
+from sup._sup import (
+    IgnoreMatcher,
+    RipGrep,
+    RipgrepStatus,
+    explain_pattern,
+    get_default_options,
+    get_global_concurrency,
+    get_ripgrep_commit,
+    get_ripgrep_path,
+    is_valid_pattern,
+    ripgrep_version,
+    run_ripgrep,
+    run_ripgrep_cancellable,
+    run_ripgrep_count,
+    run_ripgrep_status,
+    set_default_options,
+    set_global_concurrency,
+    validate_pattern,
+    verify_platform,
+)
+from sup.errors import (
+    BinaryError,
+    LimitExceeded,
+    PathNotFoundError,
+    PatternError,
+    SearchIOError,
+    SupError,
+    TimeoutError,
+)
 
 __version__ = "0.1.0"
-__all__ = ["RipGrep", "search", "ripgrep", "ripgrep_cli", "get_ripgrep_path"]
+__all__ = [
+    "RipGrep",
+    "IgnoreMatcher",
+    "search",
+    "search_json_string",
+    "decode_matches",
+    "ripgrep",
+    "ripgrep_cli",
+    "run_ripgrep_json",
+    "parse_ripgrep_output",
+    "get_ripgrep_path",
+    "get_ripgrep_commit",
+    "ripgrep_version",
+    "verify_platform",
+    "RipgrepStatus",
+    "run_ripgrep_status",
+    "run_ripgrep_count",
+    "run_ripgrep_cancellable",
+    "set_default_options",
+    "get_default_options",
+    "explain_pattern",
+    "validate_pattern",
+    "is_valid_pattern",
+    "set_global_concurrency",
+    "get_global_concurrency",
+    "benchmark",
+    "SupError",
+    "PatternError",
+    "SearchIOError",
+    "PathNotFoundError",
+    "BinaryError",
+    "TimeoutError",
+    "LimitExceeded",
+]
 
 
-def search(pattern: str, path: str = ".") -> list[dict]:
+# Options that can't be set together; passing any one of them to a call
+# leaves the defaults for all of them out
+_EXCLUSIVE_OPTIONS = (("case_insensitive", "smart_case"),)
+
+
+def _with_defaults(options: dict) -> dict:
+    """Merge options over the set_default_options() ones."""
+    merged = get_default_options()
+    for group in _EXCLUSIVE_OPTIONS:
+        if any(key in options for key in group):
+            for key in group:
+                del merged[key]
+    merged.update(options)
+    return merged
+
+
+def search(pattern: str, path: str = ".", **options) -> list[dict]:
     """
     Search for a pattern in files using ripgrep.
 
     Args:
         pattern: Regular expression pattern to search for
         path: File or directory path to search in (default: current directory)
+        **options: Search options (e.g. case_insensitive=True). These override
+            the defaults configured with set_default_options().
 
     Returns:
         List of dictionaries containing:
             - file: Path to the file
             - line_number: Line number of the match
             - line: The matching line content
+            - matches: Byte offsets of each occurrence in the line, as
+              dicts with start and end
+        Under on_error="collect", a dict with these as "matches" plus the
+        files that couldn't be searched as "errors".
     """
-    rg = RipGrep(pattern)
+    rg = RipGrep(pattern, **_with_defaults(options))
     return rg.search(path)
 
 
+def search_json_string(pattern: str, path: str = ".", **options) -> str:
+    """
+    Like search(), but return the matches as a JSON array string serialized
+    natively, for handlers that would otherwise json.dumps the results.
+
+    Args:
+        pattern: Regular expression pattern to search for
+        path: File or directory path to search in (default: current directory)
+        **options: Search options, overriding the set_default_options() ones
+
+    Returns:
+        A JSON array of objects with the same keys as search() results
+    """
+    rg = RipGrep(pattern, **_with_defaults(options))
+    return rg.search_json(path)
+
+
+def decode_matches(data: bytes) -> List[dict]:
+    """
+    Decode the buffer returned by RipGrep.search_encoded.
+
+    Args:
+        data: The bincode-encoded matches
+
+    Returns:
+        List of dictionaries containing file, line_number, line and matches,
+        like the results of RipGrep.search
+    """
+    view = memoryview(data)
+    offset = 0
+
+    def read(fmt: str):
+        nonlocal offset
+        (value,) = struct.unpack_from(fmt, view, offset)
+        offset += struct.calcsize(fmt)
+        return value
+
+    def read_str() -> str:
+        nonlocal offset
+        length = read("<Q")
+        text = bytes(view[offset : offset + length]).decode("utf-8")
+        offset += length
+        return text
+
+    matches = []
+    for _ in range(read("<Q")):
+        file = read_str() if read("<B") else None
+        line_number = read("<Q") if read("<B") else None
+        line = read_str()
+        spans = [{"start": read("<Q"), "end": read("<Q")} for _ in range(read("<Q"))]
+        matches.append(
+            {"file": file, "line_number": line_number, "line": line, "matches": spans}
+        )
+    return matches
+
+
 def ripgrep(*args: str, check: bool = True) -> subprocess.CompletedProcess:
     """
     Run the bundled ripgrep binary with the given arguments.
@@ -53,6 +198,97 @@ def ripgrep(*args: str, check: bool = True) -> subprocess.CompletedProcess:
     return result
 
 
+def _json_text(data: dict) -> str:
+    """Decode one of ripgrep's JSON text fields, which hold either ``text``
+    or, when the original isn't UTF-8, base64-encoded ``bytes``."""
+    if "text" in data:
+        return data["text"]
+    return base64.b64decode(data["bytes"]).decode("utf-8", errors="replace")
+
+
+def run_ripgrep_json(
+    args: List[str], check: bool = True, threads: Optional[int] = None
+) -> List[dict]:
+    """
+    Run the bundled ripgrep binary with ``--json`` and parse its output.
+
+    Args:
+        args: Command line arguments to pass to ripgrep; ``--json`` is added
+            unless already present, as by run_ripgrep's json
+        check: If True, raises CalledProcessError if ripgrep returns non-zero
+        threads: Passed as ``--threads`` unless args already set it, as for
+            run_ripgrep
+
+    Returns:
+        List of dictionaries, one per matching line, containing:
+            - file: Path to the file
+            - line_number: Line number of the match
+            - line: The matching line content, without its line terminator
+            - absolute_offset: Byte offset of the line in the file
+            - submatches: Each occurrence in the line, as dicts with match,
+              start and end
+    """
+    args = list(args)
+    exit_code, stdout, stderr = run_ripgrep(args, threads=threads, json=True)
+
+    if check and exit_code not in (0, 1):  # ripgrep returns 1 for no matches
+        raise subprocess.CalledProcessError(exit_code, ["rg"] + args, stdout, stderr)
+
+    matches = []
+    for line in stdout.splitlines():
+        event = json.loads(line)
+        # begin, end, context and summary events carry no matches
+        if event.get("type") != "match":
+            continue
+        data = event["data"]
+        matches.append(
+            {
+                "file": _json_text(data["path"]),
+                "line_number": data["line_number"],
+                "line": _json_text(data["lines"]).rstrip("\r\n"),
+                "absolute_offset": data["absolute_offset"],
+                "submatches": [
+                    {
+                        "match": _json_text(sub["match"]),
+                        "start": sub["start"],
+                        "end": sub["end"],
+                    }
+                    for sub in data["submatches"]
+                ],
+            }
+        )
+    return matches
+
+
+def parse_ripgrep_output(
+    output: Union[str, bytes], separator: Union[str, bytes] = "\n"
+) -> List[Union[str, bytes]]:
+    """
+    Split captured ripgrep output into records.
+
+    Args:
+        output: The stdout of a ripgrep run, as str or bytes
+        separator: What ends each record: "\n" by default, or "\0" for
+            output of ``--null`` or ``--null-data``. Given as str, it's
+            encoded as UTF-8 to split bytes output.
+
+    Returns:
+        List of records of the same type as output, without their
+        separators. A final separator doesn't start an empty record.
+    """
+    if isinstance(output, bytes) and isinstance(separator, str):
+        separator = separator.encode()
+    elif isinstance(output, str) and isinstance(separator, bytes):
+        separator = separator.decode()
+    if not separator:
+        raise ValueError("separator must not be empty")
+
+    records = output.split(separator)
+    if records and not records[-1]:
+        records.pop()
+    return records
+
+
 def ripgrep_cli(args: Optional[List[str]] = None) -> int:
     """
     Run ripgrep as a CLI tool, using sys.argv if no args provided.
@@ -77,3 +313,52 @@ def ripgrep_cli(args: Optional[List[str]] = None) -> int:
         sys.stderr.flush()
 
     return exit_code
+
+
+def benchmark(
+    pattern: str, path: str = ".", iterations: int = 5
+) -> Dict[str, Optional[float]]:
+    """
+    Time the native engine against the bundled and system ripgrep binaries.
+
+    Each approach runs once as a discarded warmup and then ``iterations``
+    times; the median duration in seconds is reported.
+
+    Args:
+        pattern: Regular expression pattern to search for
+        path: File or directory path to search in (default: current directory)
+        iterations: Number of timed runs per approach
+
+    Returns:
+        Dictionary with median durations for:
+            - search: RipGrep.search
+            - run_ripgrep: the bundled ripgrep binary
+            - system_rg: an ``rg`` found on PATH, or None if there is none
+    """
+    if iterations < 1:
+        raise ValueError("iterations must be at least 1")
+
+    def median_time(run: Callable[[], object]) -> float:
+        run()
+        durations = []
+        for _ in range(iterations):
+            start = time.perf_counter()
+            run()
+            durations.append(time.perf_counter() - start)
+        return statistics.median(durations)
+
+    rg = RipGrep(pattern)
+    args = ["--line-number", "--no-heading", "--", pattern, path]
+    timings: Dict[str, Optional[float]] = {
+        "search": median_time(lambda: rg.search(path)),
+        "run_ripgrep": median_time(lambda: run_ripgrep(args)),
+        "system_rg": None,
+    }
+
+    system_rg = shutil.which("rg")
+    if system_rg is not None:
+        timings["system_rg"] = median_time(
+            lambda: subprocess.run([system_rg] + args, capture_output=True)
+        )
+
+    return timings
@@ -0,0 +1,41 @@
+"""
+Exceptions raised by sup.
+
+Every error raised by a search derives from SupError. The subclasses also
+derive from the built-in exception previously raised in their place, so
+existing ``except ValueError`` or ``except OSError`` handlers keep working.
+"""
+
+import builtins
+
+
+class SupError(Exception):
+    """Base class for errors raised by sup."""
+
+
+class PatternError(SupError, ValueError):
+    """A regex, glob or other pattern failed to compile."""
+
+
+class SearchIOError(SupError, OSError):
+    """A file or directory couldn't be found, read or written."""
+
+
+class PathNotFoundError(SearchIOError, FileNotFoundError):
+    """A path to search doesn't exist."""
+
+
+class BinaryError(SupError, ValueError):
+    """A matched line wasn't valid UTF-8, as happens in binary files."""
+
+
+class TimeoutError(SupError, builtins.TimeoutError):
+    """A search ran out of time."""
+
+
+class LimitExceeded(SupError):
+    """
+    Raised under ``limit_behavior="error"`` when a result cap would drop
+    matches. The matches kept within the limits are attached as
+    ``partial_results``.
+    """
@@ -1,10 +1,51 @@
+import json
 import os
+import shutil
+import signal
+import struct
 import subprocess
+import sys
 import tempfile
+import threading
+import time
+import warnings
 
 import pytest
 
-from sup import RipGrep, get_ripgrep_path, ripgrep, ripgrep_cli, search
+from sup import (
+    BinaryError,
+    IgnoreMatcher,
+    LimitExceeded,
+    PathNotFoundError,
+    PatternError,
+    RipGrep,
+    RipgrepStatus,
+    SearchIOError,
+    SupError,
+    benchmark,
+    decode_matches,
+    explain_pattern,
+    get_default_options,
+    get_global_concurrency,
+    get_ripgrep_commit,
+    get_ripgrep_path,
+    is_valid_pattern,
+    parse_ripgrep_output,
+    ripgrep,
+    ripgrep_cli,
+    ripgrep_version,
+    run_ripgrep,
+    run_ripgrep_cancellable,
+    run_ripgrep_count,
+    run_ripgrep_json,
+    run_ripgrep_status,
+    search,
+    search_json_string,
+    set_default_options,
+    set_global_concurrency,
+    validate_pattern,
+    verify_platform,
+)
 
 
 def test_ripgrep_initialization():
@@ -161,6 +202,20 @@ def test_get_ripgrep_path():
     assert "ripgrep" in result.stdout.lower()
 
 
+def test_empty_ripgrep_binary(tmp_path, monkeypatch):
+    import sup
+
+    binary = tmp_path / "bin" / ("rg.exe" if sys.platform == "win32" else "rg")
+    binary.parent.mkdir()
+    binary.write_bytes(b"")
+    monkeypatch.setattr(sup, "__file__", str(tmp_path / "__init__.py"))
+
+    with pytest.raises(RuntimeError, match="without a valid ripgrep binary"):
+        get_ripgrep_path()
+    with pytest.raises(RuntimeError, match="only 0 bytes"):
+        run_ripgrep(["--version"])
+
+
 def test_ripgrep_binary_regex():
     with tempfile.NamedTemporaryFile(mode="w", suffix=".txt", delete=False) as f:
         f.write("test123\n")
@@ -177,3 +232,2884 @@ def test_ripgrep_binary_regex():
         assert result.stdout.count("\n") == 2
     finally:
         os.unlink(temp_file)
+
+
+def test_default_options():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        with open(os.path.join(temp_dir, "file.txt"), "w") as f:
+            f.write("Hello world\n")
+            f.write("hello again\n")
+
+        try:
+            set_default_options(case_insensitive=True)
+            assert get_default_options()["case_insensitive"] is True
+
+            assert len(search("hello", temp_dir)) == 2
+            assert len(search("hello", temp_dir, case_insensitive=False)) == 1
+        finally:
+            set_default_options()
+
+        assert get_default_options()["case_insensitive"] is False
+        assert len(search("hello", temp_dir)) == 1
+
+
+def test_default_options_overridden_by_exclusive_option(tmp_path):
+    (tmp_path / "file.txt").write_text("Hello world\nhello again\n")
+
+    try:
+        set_default_options(case_insensitive=True)
+        # smart_case replaces the default case_insensitive, not joins it
+        assert len(search("hello", str(tmp_path), smart_case=True)) == 2
+        assert len(search("Hello", str(tmp_path), smart_case=True)) == 1
+        assert json.loads(
+            search_json_string("Hello", str(tmp_path), smart_case=True)
+        ) == search("Hello", str(tmp_path), smart_case=True)
+    finally:
+        set_default_options()
+
+
+def test_unknown_option():
+    with pytest.raises(TypeError, match="Unknown search option"):
+        RipGrep("test", no_such_option=True)
+
+
+@pytest.mark.skipif(not hasattr(signal, "setitimer"), reason="requires setitimer")
+def test_keyboard_interrupt_during_walk():
+    def interrupt(signum, frame):
+        raise KeyboardInterrupt
+
+    with tempfile.TemporaryDirectory() as temp_dir:
+        for i in range(500):
+            with open(os.path.join(temp_dir, f"file{i}.txt"), "w") as f:
+                f.write("match\n" * 100)
+
+        rg = RipGrep("match")
+        previous = signal.signal(signal.SIGALRM, interrupt)
+        try:
+            signal.setitimer(signal.ITIMER_REAL, 0.01)
+            with pytest.raises(KeyboardInterrupt) as excinfo:
+                while True:
+                    rg.search(temp_dir)
+        finally:
+            signal.setitimer(signal.ITIMER_REAL, 0)
+            signal.signal(signal.SIGALRM, previous)
+
+        # Only set when the signal landed inside the walk itself
+        partial = getattr(excinfo.value, "partial_results", [])
+        assert len(partial) < 500 * 100
+
+        # The instance is still usable after an interrupted search
+        assert len(rg.search(temp_dir)) == 500 * 100
+
+
+def test_search_progress(tmp_path):
+    for i in range(25):
+        (tmp_path / f"file{i:02}.txt").write_text("match\nno\n")
+
+    calls = []
+    rg = RipGrep("match", threads=1)
+    results = rg.search(
+        str(tmp_path), progress=lambda *args: calls.append(args), progress_every=10
+    )
+    assert len(results) == 25
+    assert calls == [(10, 10), (20, 20)]
+
+    def stop(files, matches):
+        raise RuntimeError("stop")
+
+    for rg in (RipGrep("match", threads=1), RipGrep("match")):
+        with pytest.raises(RuntimeError, match="stop"):
+            rg.search(str(tmp_path), progress=stop, progress_every=1)
+
+    with pytest.raises(ValueError, match="progress_every"):
+        rg.search(str(tmp_path), progress=print, progress_every=0)
+
+
+@pytest.mark.skipif(not hasattr(os, "symlink"), reason="requires symlinks")
+def test_follow_links_matching():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        root = os.path.join(temp_dir, "root")
+        os.mkdir(root)
+        for name in ("real_vendor", "real_other"):
+            os.mkdir(os.path.join(temp_dir, name))
+            with open(os.path.join(temp_dir, name, "file.txt"), "w") as f:
+                f.write(f"needle in {name}\n")
+
+        os.symlink(os.path.join(temp_dir, "real_vendor"), os.path.join(root, "vendor"))
+        os.symlink(os.path.join(temp_dir, "real_other"), os.path.join(root, "other"))
+
+        assert search("needle", root) == []
+
+        results = RipGrep("needle", follow_links_matching=["vendor"]).search(root)
+        assert len(results) == 1
+        assert "real_vendor" in results[0]["line"]
+
+
+@pytest.mark.skipif(not hasattr(os, "symlink"), reason="requires symlinks")
+def test_follow_file_and_dir_links(tmp_path):
+    root = tmp_path / "root"
+    root.mkdir()
+    (tmp_path / "real.txt").write_text("needle in file\n")
+    (tmp_path / "real_dir").mkdir()
+    (tmp_path / "real_dir" / "inner.txt").write_text("needle in dir\n")
+    os.symlink(tmp_path / "real.txt", root / "file_link.txt")
+    os.symlink(tmp_path / "real_dir", root / "dir_link")
+
+    def found(**options):
+        results = RipGrep("needle", **options).search(str(root))
+        return sorted(m["line"] for m in results)
+
+    assert found() == []
+    assert found(follow_dir_links=True) == ["needle in dir"]
+
+    # A loop back to the root is never entered when only files are followed
+    os.symlink(root, root / "loop")
+    assert found(follow_file_links=True) == ["needle in file"]
+
+
+def test_follow_links_matching_invalid_glob():
+    with pytest.raises(ValueError, match="Invalid glob"):
+        RipGrep("test", follow_links_matching=["[invalid"])
+
+
+def test_search_digest():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        for name in ("a.txt", "b.txt"):
+            with open(os.path.join(temp_dir, name), "w") as f:
+                f.write(f"needle in {name}\n")
+                f.write("nothing here\n")
+
+        rg = RipGrep("needle")
+        digest, count = rg.search_digest(temp_dir)
+        assert count == 2
+        assert rg.search_digest(temp_dir) == (digest, count)
+
+        with open(os.path.join(temp_dir, "b.txt"), "w") as f:
+            f.write("needle changed\n")
+
+        changed, count = rg.search_digest(temp_dir)
+        assert count == 2
+        assert changed != digest
+
+
+def test_search_digest_is_stable(tmp_path):
+    def fnv1a(*parts):
+        hash = 0xCBF29CE484222325
+        for byte in b"\0".join(parts):
+            hash = ((hash ^ byte) * 0x100000001B3) % 2**64
+        return hash
+
+    (tmp_path / "a.txt").write_text("hay\nneedle one\n")
+    (tmp_path / "b.txt").write_text("needle two\n")
+    expected = [
+        (str(tmp_path / "a.txt"), 2, "needle one"),
+        (str(tmp_path / "b.txt"), 1, "needle two"),
+    ]
+
+    # A fixed encoding, so the digest doesn't change with the toolchain
+    hashes = sorted(
+        fnv1a(file.encode(), struct.pack("<Q", line_number), line.encode())
+        for file, line_number, line in expected
+    )
+    digest = fnv1a(b"".join(struct.pack("<Q", h) for h in hashes))
+    assert RipGrep("needle").search_digest(str(tmp_path)) == (f"{digest:016x}", 2)
+
+
+@pytest.mark.skipif(not hasattr(os, "mkfifo"), reason="requires FIFOs")
+def test_run_ripgrep_timeout(tmp_path):
+    (tmp_path / "a.txt").write_text("needle\n")
+    exit_code, stdout, _ = run_ripgrep(["needle", str(tmp_path)], timeout_secs=30)
+    assert exit_code == 0
+    assert "needle" in stdout
+
+    # Opening a FIFO nobody writes to blocks forever
+    fifo = tmp_path / "fifo"
+    os.mkfifo(fifo)
+    start = time.monotonic()
+    with pytest.raises(TimeoutError, match="0.2 seconds"):
+        run_ripgrep(["needle", str(fifo)], timeout_secs=0.2)
+    assert time.monotonic() - start < 10
+
+
+def test_run_ripgrep_input():
+    exit_code, stdout, _ = run_ripgrep(["needle"], input=b"hay\nneedle one\nhay\n")
+    assert exit_code == 0
+    assert stdout == "needle one\n"
+
+    # Far more than a pipe buffer each way, which must not deadlock
+    data = b"needle\n" * 500_000
+    exit_code, stdout, _ = run_ripgrep(["--count", "needle"], input=data)
+    assert (exit_code, stdout) == (0, "500000\n")
+    exit_code, stdout, _ = run_ripgrep(["needle"], input=data)
+    assert stdout.count("\n") == 500_000
+
+
+def test_run_ripgrep_threads(tmp_path):
+    (tmp_path / "a.txt").write_text("needle\n-j\n")
+    for args in (
+        ["needle", str(tmp_path)],
+        ["-j2", "needle", str(tmp_path)],
+        ["--threads=2", "needle", str(tmp_path)],
+    ):
+        exit_code, stdout, stderr = run_ripgrep(args, threads=1)
+        assert (exit_code, stderr) == (0, "")
+        assert "needle" in stdout
+
+    # After --, a -j is the pattern, not a thread count
+    exit_code, stdout, _ = run_ripgrep(["--", "-j", str(tmp_path)], threads=1)
+    assert exit_code == 0
+    assert "-j" in stdout
+
+    assert len(run_ripgrep_json(["needle", str(tmp_path)], threads=1)) == 1
+
+
+@pytest.mark.skipif(os.name != "posix", reason="requires Unix file descriptors")
+def test_run_ripgrep_stdout_fd():
+    with tempfile.NamedTemporaryFile(mode="w", suffix=".txt", delete=False) as f:
+        f.write("fd test\n")
+        f.write("other line\n")
+        temp_file = f.name
+
+    read_fd, write_fd = os.pipe()
+    try:
+        try:
+            exit_code, stdout, _ = run_ripgrep(["fd", temp_file], stdout_fd=write_fd)
+        finally:
+            os.close(write_fd)
+
+        with os.fdopen(read_fd) as reader:
+            output = reader.read()
+
+        assert exit_code == 0
+        assert stdout == ""
+        assert output == "fd test\n"
+    finally:
+        os.unlink(temp_file)
+
+
+@pytest.mark.skipif(os.name != "posix", reason="requires Unix file descriptors")
+def test_run_ripgrep_invalid_stdout_fd():
+    with pytest.raises(ValueError, match="Invalid file descriptor"):
+        run_ripgrep(["--version"], stdout_fd=-1)
+
+    read_fd, write_fd = os.pipe()
+    os.close(read_fd)
+    os.close(write_fd)
+    with pytest.raises(SearchIOError, match="Invalid file descriptor"):
+        run_ripgrep(["--version"], stdout_fd=write_fd)
+
+
+def test_max_match_len():
+    with tempfile.NamedTemporaryFile(mode="w", suffix=".txt", delete=False) as f:
+        f.write("x" + "a" * 10000 + "y\n")
+        f.write("short aa match\n")
+        temp_file = f.name
+
+    try:
+        results = RipGrep("a+", max_match_len=100).search(temp_file)
+        assert len(results) == 2
+
+        long_line, short_line = results
+        assert long_line["match_truncated"] is True
+        assert long_line["line"] == "x" + "a" * 100
+        assert short_line["match_truncated"] is False
+        assert short_line["line"] == "short aa match"
+    finally:
+        os.unlink(temp_file)
+
+
+def test_search_records_consistent_keys():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        with open(os.path.join(temp_dir, "a.txt"), "w") as f:
+            f.write("x" + "a" * 50 + "\n")
+        with open(os.path.join(temp_dir, "b.txt"), "w") as f:
+            f.write("aa\n")
+            f.write("aaa\n")
+
+        records = RipGrep("a+", max_match_len=10).search_records(temp_dir)
+        assert len(records) == 3
+
+        keys = set(records[0])
+        assert {"file", "line_number", "line"} <= keys
+        assert all(set(r) == keys for r in records)
+
+
+def test_search_aggregate():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        with open(os.path.join(temp_dir, "a.log"), "w") as f:
+            f.write("ERROR disk full\n")
+            f.write("ERROR timeout\n")
+            f.write("INFO ok\n")
+        with open(os.path.join(temp_dir, "b.log"), "w") as f:
+            f.write("ERROR disk full\n")
+            f.write("ERROR  disk   full\n")
+
+        rg = RipGrep("ERROR")
+        assert rg.search_aggregate(temp_dir) == {
+            "ERROR disk full": 2,
+            "ERROR  disk   full": 1,
+            "ERROR timeout": 1,
+        }
+        assert rg.search_aggregate(temp_dir, normalize_whitespace=True) == {
+            "ERROR disk full": 3,
+            "ERROR timeout": 1,
+        }
+
+        with pytest.raises(ValueError, match="Unsupported aggregate"):
+            rg.search_aggregate(temp_dir, aggregate="file")
+
+
+def test_record_separator():
+    with tempfile.NamedTemporaryFile(mode="w", suffix=".log", delete=False) as f:
+        f.write("2024-01-01 10:00 start\n")
+        f.write("2024-01-01 10:01 error happened\n")
+        f.write("  traceback line 1\n")
+        f.write("  traceback line 2\n")
+        f.write("2024-01-01 10:02 done\n")
+        temp_file = f.name
+
+    try:
+        rg = RipGrep("traceback", record_separator=r"^\d{4}-\d{2}-\d{2} ")
+        results = rg.search(temp_file)
+        assert len(results) == 1
+        assert results[0]["line_number"] == 2
+        assert results[0]["line"] == (
+            "2024-01-01 10:01 error happened\n"
+            "  traceback line 1\n"
+            "  traceback line 2"
+        )
+    finally:
+        os.unlink(temp_file)
+
+
+def test_reuse_across_many_files(tmp_path):
+    paths = []
+    for i in range(200):
+        path = tmp_path / f"{i}.log"
+        path.write_text(f"2024-01-01 entry {i}\n  needle {i}\n2024-01-02 other\n")
+        paths.append(str(path))
+
+    # One instance, its matcher and separator compiled once, for every file
+    rg = RipGrep("needle", record_separator=r"^\d{4}-")
+    for i, path in enumerate(paths):
+        results = rg.search(path)
+        assert [r["line"] for r in results] == [f"2024-01-01 entry {i}\n  needle {i}"]
+
+
+def test_reuse_filters(tmp_path):
+    (tmp_path / "a.py").write_text("needle\n")
+    (tmp_path / "b.rs").write_text("needle\n")
+    (tmp_path / "c.txt").write_text("needle\n")
+    (tmp_path / "skip.py").write_text("needle\n")
+    other = tmp_path / "other"
+    other.mkdir()
+    (other / "d.py").write_text("needle\n")
+
+    # Types and globs compiled once, reused by every search of a root
+    rg = RipGrep("needle", types=["py", "rust"], exclude_globs=["skip.py"])
+    for _ in range(50):
+        files = sorted(os.path.basename(r["file"]) for r in rg.search(str(tmp_path)))
+        assert files == ["a.py", "b.rs", "d.py"]
+    # Another root gets its own overrides
+    assert [os.path.basename(r["file"]) for r in rg.search(str(other))] == ["d.py"]
+    # A one-off glob doesn't leak into later searches
+    assert len(rg.search(str(tmp_path), filename_glob="a.*")) == 1
+    assert len(rg.search(str(tmp_path))) == 3
+
+    with pytest.raises(ValueError, match="Invalid glob"):
+        RipGrep("needle", include_globs=["a{"])
+    with pytest.raises(ValueError, match="Invalid file types"):
+        RipGrep("needle", types=["no-such-type"])
+
+
+def test_invalid_record_separator():
+    with pytest.raises(ValueError, match="Invalid record separator"):
+        RipGrep("test", record_separator="[invalid")
+
+
+def test_profile_read_method():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        with open(os.path.join(temp_dir, "file.txt"), "w") as f:
+            f.write("needle\n")
+            f.write("haystack\n")
+
+        # grep-searcher never memory maps on macOS
+        mapped = "buffered" if sys.platform == "darwin" else "mmap"
+        for strategy, expected in [("buffered", "buffered"), ("mmap", mapped)]:
+            profile = RipGrep("needle", read_strategy=strategy).profile(temp_dir)
+            assert len(profile) == 1
+            assert profile[0]["matches"] == 1
+            assert profile[0]["read_method"] == expected
+            assert profile[0]["elapsed"] >= 0
+
+        with pytest.raises(ValueError, match="Invalid read_strategy"):
+            RipGrep("needle", read_strategy="bogus")
+
+
+def test_memory_map(tmp_path):
+    target = tmp_path / "file.txt"
+    target.write_text("needle\nhaystack\nneedle again\n")
+
+    rg = RipGrep("needle", memory_map=False)
+    assert rg.config()["read_strategy"] == "buffered"
+    assert [r["line_number"] for r in rg.search(str(target))] == [1, 3]
+    assert rg.profile(str(target))[0]["read_method"] == "buffered"
+
+    assert RipGrep("needle", memory_map=True).config()["read_strategy"] == "mmap"
+    assert RipGrep("needle", memory_map=None).config()["read_strategy"] == "auto"
+
+
+def test_snippet_radius():
+    with tempfile.NamedTemporaryFile(
+        mode="w", suffix=".txt", encoding="utf-8", delete=False
+    ) as f:
+        f.write("a" * 100 + "needle" + "b" * 100 + "\n")
+        f.write("é needle\n")
+        temp_file = f.name
+
+    try:
+        long_line, short_line = RipGrep("needle", snippet_radius=10).search(temp_file)
+        assert long_line["snippet"] == "…" + "a" * 7 + "needle" + "b" * 7 + "…"
+        assert short_line["snippet"] == "é needle"
+    finally:
+        os.unlink(temp_file)
+
+
+def test_search_file_lines():
+    with tempfile.NamedTemporaryFile(mode="w", suffix=".txt", delete=False) as f:
+        for i in range(1, 21):
+            f.write(f"match {i}\n")
+        temp_file = f.name
+
+    try:
+        rg = RipGrep("match")
+        results = rg.search_file_lines(temp_file, 5, 10)
+        assert [r["line_number"] for r in results] == list(range(5, 11))
+        assert results[0]["line"] == "match 5"
+
+        assert len(rg.search_file_lines(temp_file, 0, 100)) == 20
+        assert rg.search_file_lines(temp_file, 15, 5) == []
+    finally:
+        os.unlink(temp_file)
+
+
+def test_search_line_range():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        for name in ("a.txt", "b.txt"):
+            with open(os.path.join(temp_dir, name), "w") as f:
+                for i in range(1, 21):
+                    f.write(f"match {i}\n")
+        temp_file = os.path.join(temp_dir, "a.txt")
+
+        rg = RipGrep("match", line_number=False)
+        results = rg.search(temp_file, start_line=5, end_line=10)
+        assert [r["line"] for r in results] == [f"match {i}" for i in range(5, 11)]
+        assert len(rg.search(temp_file, start_line=18)) == 3
+        assert len(rg.search(temp_file, end_line=2)) == 2
+
+        # Each file in a directory is restricted to the range
+        results = RipGrep("match").search(temp_dir, start_line=19)
+        assert sorted(r["line_number"] for r in results) == [19, 19, 20, 20]
+
+        assert rg.search(temp_file, start_line=15, end_line=5) == []
+        assert rg.search(temp_file, start_line=100) == []
+
+
+def test_run_ripgrep_status():
+    with tempfile.NamedTemporaryFile(mode="w", suffix=".txt", delete=False) as f:
+        f.write("status test\n")
+        temp_file = f.name
+
+    try:
+        status, code, stdout, _ = run_ripgrep_status(["status", temp_file])
+        assert status == RipgrepStatus.FOUND
+        assert code == 0
+        assert "status test" in stdout
+
+        status, code, stdout, _ = run_ripgrep_status(["nonexistent", temp_file])
+        assert status == RipgrepStatus.NOT_FOUND
+        assert code == 1
+        assert stdout == ""
+
+        missing = temp_file + ".missing"
+        status, code, _, stderr = run_ripgrep_status(["status", missing])
+        assert status == RipgrepStatus.ERROR
+        assert code == 2
+        assert stderr
+    finally:
+        os.unlink(temp_file)
+
+
+def test_run_ripgrep_count(tmp_path):
+    (tmp_path / "a.txt").write_text("needle needle\nhay\nneedle\n")
+    (tmp_path / "b.txt").write_text("needle\n")
+    (tmp_path / "c.txt").write_text("hay\n")
+
+    # Every occurrence, summed across files
+    assert run_ripgrep_count(["needle", str(tmp_path)]) == (4, 0)
+    assert run_ripgrep_count(["needle", str(tmp_path / "a.txt")]) == (3, 0)
+    # A count already asked for is kept, here of lines, file names and all
+    assert run_ripgrep_count(["--count", "-H", "needle", str(tmp_path)]) == (3, 0)
+    assert run_ripgrep_count(["missing", str(tmp_path)]) == (0, 1)
+    assert run_ripgrep_count(["needle", str(tmp_path / "missing")]) == (0, 2)
+
+
+def test_search_bytes_buffer_protocol():
+    data = b"first line\nneedle here\nlast line\n"
+
+    rg = RipGrep("needle")
+    for buffer in (data, bytearray(data), memoryview(data)):
+        results = rg.search_bytes(buffer)
+        assert len(results) == 1
+        assert results[0]["file"] is None
+        assert results[0]["line_number"] == 2
+        assert results[0]["line"] == "needle here"
+
+    with pytest.raises(ValueError, match="contiguous"):
+        rg.search_bytes(memoryview(data)[::2])
+
+
+@pytest.mark.skipif(shutil.which("git") is None, reason="requires git")
+def test_git_relative():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        repo = os.path.join(temp_dir, "repo")
+        nested = os.path.join(repo, "sub", "dir")
+        os.makedirs(nested)
+        subprocess.run(["git", "init", "-q", repo], check=True)
+        with open(os.path.join(nested, "file.txt"), "w") as f:
+            f.write("needle\n")
+
+        outside = os.path.join(temp_dir, "outside.txt")
+        with open(outside, "w") as f:
+            f.write("needle\n")
+
+        rg = RipGrep("needle", git_relative=True)
+        results = rg.search(os.path.join(repo, "sub"))
+        assert [r["file"] for r in results] == [os.path.join("sub", "dir", "file.txt")]
+
+        results = rg.search(outside)
+        assert [r["file"] for r in results] == [os.path.realpath(outside)]
+
+
+def test_max_per_dir():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        for sub in ("", "a", "b", os.path.join("b", "c")):
+            directory = os.path.join(temp_dir, sub)
+            os.makedirs(directory, exist_ok=True)
+            for name in ("one.txt", "two.txt"):
+                with open(os.path.join(directory, name), "w") as f:
+                    f.write("needle\n" * 3)
+
+        assert len(search("needle", temp_dir)) == 4 * 2 * 3
+
+        results = RipGrep("needle", max_per_dir=4).search(temp_dir)
+        per_dir = {}
+        for r in results:
+            directory = os.path.dirname(r["file"])
+            per_dir[directory] = per_dir.get(directory, 0) + 1
+
+        assert len(per_dir) == 4
+        assert all(count == 4 for count in per_dir.values())
+
+
+def test_explain_pattern():
+    info = explain_pattern(r"(?P<year>\d{4})-(?P<month>\d{2})-(\d{2})")
+    assert info["valid"] is True
+    assert info["error"] is None
+    assert info["captures"] == ["year", "month"]
+    assert info["is_literal"] is False
+
+    assert explain_pattern("hello")["is_literal"] is True
+
+
+def test_explain_invalid_pattern():
+    info = explain_pattern("[invalid")
+    assert info["valid"] is False
+    assert info["error"]
+    assert info["captures"] == []
+
+
+def test_validate_pattern():
+    assert validate_pattern(r"\d+") is None
+    assert is_valid_pattern(r"\d+") is True
+
+    error = validate_pattern("[invalid")
+    assert error
+    assert is_valid_pattern("[invalid") is False
+    with pytest.raises(PatternError) as excinfo:
+        RipGrep("[invalid")
+    assert error in str(excinfo.value)
+
+    # Options are taken into account the same way the constructor does
+    assert validate_pattern("[invalid", fixed_strings=True) is None
+    assert is_valid_pattern("[invalid", fixed_strings=True) is True
+
+
+def test_search_generator():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        for i in range(20):
+            with open(os.path.join(temp_dir, f"file{i}.txt"), "w") as f:
+                f.write("needle\n" * 5)
+                f.write("hay\n")
+
+        rg = RipGrep("needle")
+        generator = rg.search_generator(temp_dir)
+        assert iter(generator) is generator
+
+        results = list(generator)
+        assert len(results) == 100
+        assert all(r["line"] == "needle" for r in results)
+
+        # Stopping early is fine, the background search is abandoned
+        generator = rg.search_generator(temp_dir)
+        assert next(generator)["line"] == "needle"
+        del generator
+
+
+def test_search_iter(tmp_path):
+    for i in range(10):
+        (tmp_path / f"file{i}.txt").write_text("needle\nhay\nneedle\n")
+
+    rg = RipGrep("needle", stable=True)
+    assert list(rg.search_iter(str(tmp_path))) == rg.search(str(tmp_path))
+
+    for m in rg.search_iter(str(tmp_path)):
+        assert m["line_number"] == 1
+        break
+
+    capped = RipGrep("needle", max_total=3).search_iter(str(tmp_path))
+    assert len(list(capped)) == 3
+
+
+def test_match_dirs():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        migrations = os.path.join(temp_dir, "app", "migrations")
+        os.makedirs(migrations)
+        with open(os.path.join(migrations, "0001_initial.py"), "w") as f:
+            f.write("pass\n")
+        with open(os.path.join(temp_dir, "notes.txt"), "w") as f:
+            f.write("see migrations\n")
+
+        results = RipGrep("^migrations$", match_dirs=True).search(temp_dir)
+        assert len(results) == 1
+        assert results[0]["is_dir"] is True
+        assert results[0]["line_number"] is None
+        assert results[0]["file"] == migrations
+
+        results = RipGrep("migrations", match_dirs=True).search(temp_dir)
+        assert sorted(r["is_dir"] for r in results) == [False, True]
+
+
+def test_warm():
+    with tempfile.NamedTemporaryFile(mode="w", suffix=".txt", delete=False) as f:
+        f.write("warm me up\n")
+        temp_file = f.name
+
+    try:
+        rg = RipGrep(r"warm\s+\w+")
+        assert rg.pattern == r"warm\s+\w+"
+        assert rg.warm() is None
+        results = rg.search(temp_file)
+        assert len(results) == 1
+        assert results[0]["line"] == "warm me up"
+    finally:
+        os.unlink(temp_file)
+
+
+def test_with_patterns_priority():
+    with tempfile.NamedTemporaryFile(mode="w", suffix=".txt", delete=False) as f:
+        f.write("foobar here\n")
+        f.write("only foo\n")
+        f.write("nothing\n")
+        temp_file = f.name
+
+    try:
+        first = RipGrep.with_patterns(["foo", "foobar"]).search(temp_file)
+        assert [r["which_pattern"] for r in first] == [0, 0]
+
+        longest = RipGrep.with_patterns(["foo", "foobar"], priority="longest")
+        assert [r["which_pattern"] for r in longest.search(temp_file)] == [1, 0]
+
+        with pytest.raises(ValueError, match="Invalid priority"):
+            RipGrep.with_patterns(["foo"], priority="shortest")
+    finally:
+        os.unlink(temp_file)
+
+
+def test_with_patterns_invalid():
+    with pytest.raises(PatternError, match=r'pattern 1 \("\(unclosed"\)'):
+        RipGrep.with_patterns(["fine", "(unclosed", "also fine"])
+    with pytest.raises(PatternError, match="at least one pattern"):
+        RipGrep.with_patterns([])
+
+    rg = RipGrep.with_patterns(["alpha", "beta"])
+    results = rg.search_text("alpha\ngamma\nbeta\n")
+    assert [r["line"] for r in results] == ["alpha", "beta"]
+
+
+def test_config():
+    config = RipGrep("fo+", case_insensitive=True, max_per_dir=3).config()
+    assert config["pattern"] == "fo+"
+    assert config["case_insensitive"] is True
+    assert config["max_per_dir"] == 3
+    # Defaults are filled in for options that weren't passed
+    assert config["hidden"] is False
+    assert config["read_strategy"] == "auto"
+    assert "priority" not in config
+
+    config = RipGrep.with_patterns(["a", "b"], priority="longest").config()
+    assert config["priority"] == "longest"
+
+
+def test_replace_with_callback():
+    with tempfile.NamedTemporaryFile(mode="w", suffix=".txt", delete=False) as f:
+        f.write("name: alice\n")
+        f.write("nothing here\n")
+        f.write("name: bob, name: carol\n")
+        temp_file = f.name
+
+    try:
+        rg = RipGrep(r"name: (\w+)")
+        changes = rg.replace_with_callback(
+            temp_file, lambda groups: "name: " + groups[1].upper()
+        )
+        assert changes == 3
+
+        with open(temp_file) as f:
+            assert f.read() == (
+                "name: ALICE\nnothing here\nname: BOB, name: CAROL\n"
+            )
+
+        # No matches left to change, so the file is not rewritten
+        assert RipGrep("missing").replace_with_callback(temp_file, str) == 0
+    finally:
+        os.unlink(temp_file)
+
+
+def test_named_output():
+    with tempfile.NamedTemporaryFile(mode="w", suffix=".txt", delete=False) as f:
+        f.write("released 2023-04-17\n")
+        f.write("no date\n")
+        f.write("patched 2024-01-02\n")
+        temp_file = f.name
+
+    try:
+        rg = RipGrep(
+            r"(?P<year>\d{4})-(?P<month>\d{2})-(?P<day>\d{2})",
+            named_output=["year", "month", "day"],
+        )
+        results = rg.search(temp_file)
+        assert results == [
+            {
+                "file": temp_file,
+                "line_number": 1,
+                "year": "2023",
+                "month": "04",
+                "day": "17",
+            },
+            {
+                "file": temp_file,
+                "line_number": 3,
+                "year": "2024",
+                "month": "01",
+                "day": "02",
+            },
+        ]
+
+        with pytest.raises(ValueError, match="Unknown capture group"):
+            RipGrep(r"(?P<year>\d{4})", named_output=["year", "month"])
+    finally:
+        os.unlink(temp_file)
+
+
+def test_global_concurrency_limit():
+    temp_dir = tempfile.mkdtemp()
+    try:
+        for i in range(20):
+            with open(os.path.join(temp_dir, f"file{i}.txt"), "w") as f:
+                f.write("needle\n" * 100)
+
+        set_global_concurrency(2)
+        rg = RipGrep("needle")
+        counts = []
+
+        def run():
+            # Generators walk on their own threads, without the GIL
+            counts.append(len(list(rg.search_generator(temp_dir))))
+            counts.append(len(rg.search(temp_dir)))
+
+        threads = [threading.Thread(target=run) for _ in range(8)]
+        for thread in threads:
+            thread.start()
+        for thread in threads:
+            thread.join()
+
+        assert counts == [2000] * 16
+        stats = get_global_concurrency()
+        assert stats["limit"] == 2
+        assert stats["active"] == 0
+        assert 1 <= stats["peak"] <= 2
+
+        with pytest.raises(ValueError):
+            set_global_concurrency(0)
+    finally:
+        set_global_concurrency(None)
+        shutil.rmtree(temp_dir)
+
+
+def test_global_concurrency_partly_consumed_generator(tmp_path):
+    # More matches than the generator buffers, so its walk has to wait
+    (tmp_path / "a.txt").write_text("needle\n" * 1000)
+    rg = RipGrep("needle")
+    counts = []
+
+    def run():
+        generator = rg.search_generator(str(tmp_path))
+        next(generator)
+        # The waiting generator doesn't hold the only slot
+        counts.append(len(rg.search(str(tmp_path))))
+        counts.append(1 + len(list(generator)))
+
+    try:
+        set_global_concurrency(1)
+        thread = threading.Thread(target=run, daemon=True)
+        thread.start()
+        thread.join(timeout=30)
+        assert not thread.is_alive()
+        assert counts == [1000, 1000]
+    finally:
+        set_global_concurrency(None)
+
+
+@pytest.mark.skipif(shutil.which("git") is None, reason="requires git")
+def test_blame():
+    with tempfile.TemporaryDirectory() as repo:
+        git = ["git", "-C", repo, "-c", "user.name=Tester", "-c", "user.email=t@x.org"]
+        subprocess.run(["git", "init", "-q", repo], check=True)
+        with open(os.path.join(repo, "file.txt"), "w") as f:
+            f.write("needle one\nhay\nneedle two\n")
+        subprocess.run(git + ["add", "file.txt"], check=True)
+        subprocess.run(git + ["commit", "-q", "-m", "initial"], check=True)
+        head = subprocess.run(
+            git + ["rev-parse", "HEAD"], check=True, capture_output=True, text=True
+        ).stdout.strip()
+
+        results = RipGrep("needle", blame=True).search(repo)
+        assert [r["commit"] for r in results] == [head, head]
+        assert [r["author"] for r in results] == ["Tester", "Tester"]
+
+        # Outside a repository the fields are present but empty
+        with tempfile.NamedTemporaryFile(mode="w", suffix=".txt") as f:
+            f.write("needle\n")
+            f.flush()
+            results = RipGrep("needle", blame=True).search(f.name)
+            assert results[0]["commit"] is None
+
+
+def test_blame_sha256():
+    with tempfile.TemporaryDirectory() as repo:
+        git = ["git", "-C", repo, "-c", "user.name=Tester", "-c", "user.email=t@x.org"]
+        init = subprocess.run(
+            ["git", "init", "-q", "--object-format=sha256", repo], capture_output=True
+        )
+        if init.returncode != 0:
+            pytest.skip("git without SHA-256 repository support")
+        with open(os.path.join(repo, "file.txt"), "w") as f:
+            f.write("needle\n")
+        subprocess.run(git + ["add", "file.txt"], check=True)
+        subprocess.run(git + ["commit", "-q", "-m", "initial"], check=True)
+        head = subprocess.run(
+            git + ["rev-parse", "HEAD"], check=True, capture_output=True, text=True
+        ).stdout.strip()
+        assert len(head) == 64
+
+        results = RipGrep("needle", blame=True).search(repo)
+        assert [(r["commit"], r["author"]) for r in results] == [(head, "Tester")]
+
+
+def test_search_arrow():
+    from array import array
+
+    with tempfile.TemporaryDirectory() as temp_dir:
+        with open(os.path.join(temp_dir, "a.txt"), "w") as f:
+            f.write("needle one\nhay\nneedle twö\n")
+        with open(os.path.join(temp_dir, "b.txt"), "w") as f:
+            f.write("needle three\n")
+
+        rg = RipGrep("needle")
+        result = rg.search_arrow(temp_dir)
+        line_numbers = array("q", result["line_numbers"])
+        file_ids = array("q", result["file_ids"])
+        offsets = array("q", result["offsets"])
+        assert len(offsets) == len(line_numbers) + 1
+
+        records = [
+            {
+                "file": result["files"][file_ids[i]],
+                "line_number": line_numbers[i],
+                "line": result["text"][offsets[i] : offsets[i + 1]].decode(),
+            }
+            for i in range(len(line_numbers))
+        ]
+        assert records == rg.search(temp_dir)
+
+
+def test_max_lines():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        short = os.path.join(temp_dir, "short.txt")
+        with open(short, "w") as f:
+            f.write("needle\n" * 5)
+        generated = os.path.join(temp_dir, "generated.txt")
+        with open(generated, "w") as f:
+            f.write("needle\n")
+            f.write("filler\n" * 50)
+
+        rg = RipGrep("needle", max_lines=10)
+        results = rg.search(temp_dir)
+        assert {r["file"] for r in results} == {short}
+        assert len(results) == 5
+
+        profile = {p["file"]: p for p in rg.profile(temp_dir)}
+        assert profile[generated]["skipped"] is True
+        assert profile[generated]["matches"] == 0
+        assert profile[short]["skipped"] is False
+
+
+def test_open_matches():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        target = os.path.join(temp_dir, "file.txt")
+        with open(target, "w") as f:
+            f.write("hay\nneedle\nhay\nneedle\n")
+
+        log = os.path.join(temp_dir, "editor.log")
+        editor = os.path.join(temp_dir, "editor.py")
+        with open(editor, "w") as f:
+            f.write(
+                "import sys\n"
+                "with open(sys.argv[1], 'a') as log:\n"
+                "    log.write(' '.join(sys.argv[2:]) + '\\n')\n"
+            )
+
+        rg = RipGrep("needle")
+        assert rg.open_matches(target, [sys.executable, editor, log]) == 2
+        with open(log) as f:
+            assert f.read().splitlines() == [f"+2 {target}", f"+4 {target}"]
+
+        os.unlink(log)
+        editor_cmd = [sys.executable, editor, log]
+        assert rg.open_matches(target, editor_cmd, first_only=True) == 1
+        with open(log) as f:
+            assert f.read().splitlines() == [f"+2 {target}"]
+
+
+@pytest.mark.skipif(sys.platform == "win32", reason="mode bits are Unix only")
+def test_include_mode():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        executable = os.path.join(temp_dir, "run.sh")
+        private = os.path.join(temp_dir, "secret.txt")
+        for path, mode in ((executable, 0o755), (private, 0o600)):
+            with open(path, "w") as f:
+                f.write("needle\n")
+            os.chmod(path, mode)
+
+        results = RipGrep("needle", include_mode=True).search(temp_dir)
+        modes = {r["file"]: r["mode"] for r in results}
+        assert modes == {
+            executable: oct(os.stat(executable).st_mode & 0o7777),
+            private: oct(os.stat(private).st_mode & 0o7777),
+        }
+        assert modes[private] == "0o600"
+
+
+def test_benchmark():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        with open(os.path.join(temp_dir, "file.txt"), "w") as f:
+            f.write("needle\n" * 10)
+
+        timings = benchmark("needle", temp_dir, iterations=2)
+        assert set(timings) == {"search", "run_ripgrep", "system_rg"}
+        assert timings["search"] >= 0
+        assert timings["run_ripgrep"] >= 0
+        if shutil.which("rg") is None:
+            assert timings["system_rg"] is None
+        else:
+            assert timings["system_rg"] >= 0
+
+        with pytest.raises(ValueError):
+            benchmark("needle", temp_dir, iterations=0)
+
+
+def test_binary_modes(tmp_path):
+    target = tmp_path / "blob.bin"
+    target.write_bytes(b"needle one\x00needle two\n")
+
+    assert RipGrep("needle").search(str(target)) == []
+    assert RipGrep("needle").config()["binary"] == "skip"
+    # NULs become line breaks, splitting the line in two
+    assert len(RipGrep("needle", binary="text").search(str(target))) == 2
+    results = RipGrep("needle", binary="search").search(str(target))
+    assert [r["line"] for r in results] == ["needle one\x00needle two"]
+
+    # Directory walks skip the binary file but not its text neighbour
+    (tmp_path / "plain.txt").write_text("needle\n")
+    results = RipGrep("needle").search(str(tmp_path))
+    assert [r["file"] for r in results] == [str(tmp_path / "plain.txt")]
+
+    with pytest.raises(ValueError, match="Invalid binary"):
+        RipGrep("needle", binary="convert")
+
+
+def test_binary_quit_byte():
+    with tempfile.NamedTemporaryFile(mode="wb", suffix=".dat", delete=False) as f:
+        f.write(b"\x01header\nneedle\n")
+        temp_file = f.name
+
+    try:
+        assert len(RipGrep("needle").search(temp_file)) == 1
+        # NUL doesn't occur, so only the chosen control byte trips detection
+        assert len(RipGrep("needle", binary_quit_byte=0).search(temp_file)) == 1
+        assert RipGrep("needle", binary_quit_byte=0x01).search(temp_file) == []
+
+        with pytest.raises(ValueError, match="Invalid binary_quit_byte"):
+            RipGrep("needle", binary_quit_byte=256)
+    finally:
+        os.unlink(temp_file)
+
+
+def test_search_by_file():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        for name in ("a.txt", "b.txt", "c.txt"):
+            with open(os.path.join(temp_dir, name), "w") as f:
+                for i in range(50):
+                    f.write("needle\n" if i % 3 == 0 else "hay\n")
+
+        rg = RipGrep("needle")
+        for group_sorted in (False, True):
+            grouped = rg.search_by_file(temp_dir, group_sorted=group_sorted)
+            assert len(grouped) == 3
+            for file, results in grouped.items():
+                assert all(r["file"] == file for r in results)
+                line_numbers = [r["line_number"] for r in results]
+                assert line_numbers == list(range(1, 51, 3))
+
+        # Matches from each file also stream out in line order
+        results = list(rg.search_generator(temp_dir))
+        for file in grouped:
+            line_numbers = [r["line_number"] for r in results if r["file"] == file]
+            assert line_numbers == sorted(line_numbers)
+
+
+def test_search_grouped(tmp_path):
+    (tmp_path / "a.txt").write_text("needle 1\nhay\nneedle 3\n")
+    (tmp_path / "b.txt").write_text("hay only\n")
+    (tmp_path / "c.txt").write_text("needle\n")
+
+    grouped = RipGrep("needle", stable=True).search_grouped(str(tmp_path))
+    assert [g["file"] for g in grouped] == [
+        str(tmp_path / "a.txt"),
+        str(tmp_path / "c.txt"),
+    ]
+    assert [(m["line_number"], m["line"]) for m in grouped[0]["matches"]] == [
+        (1, "needle 1"),
+        (3, "needle 3"),
+    ]
+    assert "file" not in grouped[0]["matches"][0]
+
+
+def test_exclude_if_matches():
+    with tempfile.NamedTemporaryFile(mode="w", suffix=".rs", delete=False) as f:
+        f.write("let x = todo();\n")
+        f.write("    // todo: remove\n")
+        f.write("# todo in a shell comment\n")
+        f.write("call(todo);\n")
+        temp_file = f.name
+
+    try:
+        rg = RipGrep("todo", exclude_if_matches=[r"^\s*//", r"^\s*#"])
+        results = rg.search(temp_file)
+        assert [r["line_number"] for r in results] == [1, 4]
+
+        with pytest.raises(ValueError, match="Invalid exclusion pattern"):
+            RipGrep("todo", exclude_if_matches=["("])
+    finally:
+        os.unlink(temp_file)
+
+
+def test_context_pattern():
+    with tempfile.NamedTemporaryFile(mode="w", suffix=".py", delete=False) as f:
+        f.write("needle = 1\n")
+        f.write("class Config:\n")
+        f.write("    def load(self):\n")
+        f.write("        return needle\n")
+        f.write("\n")
+        f.write("def save():\n")
+        f.write("    x = 1\n")
+        f.write("    print(needle)\n")
+        temp_file = f.name
+
+    try:
+        rg = RipGrep("needle", context_pattern=r"^\s*(def|class) ")
+        results = rg.search(temp_file)
+        assert [r["context_heading"] for r in results] == [
+            None,
+            "    def load(self):",
+            "def save():",
+        ]
+
+        with open(temp_file, "rb") as f:
+            assert rg.search_bytes(f.read()) == [
+                {**r, "file": None} for r in results
+            ]
+    finally:
+        os.unlink(temp_file)
+
+
+def test_line_ending():
+    with tempfile.NamedTemporaryFile(mode="wb", suffix=".txt", delete=False) as f:
+        f.write(b"needle lf\nneedle crlf\r\nhay\nneedle eof")
+        temp_file = f.name
+
+    try:
+        results = RipGrep("needle", line_ending=True).search(temp_file)
+        assert [r["line_ending"] for r in results] == ["lf", "crlf", "none"]
+        assert [r["line"] for r in results] == [
+            "needle lf",
+            "needle crlf",
+            "needle eof",
+        ]
+    finally:
+        os.unlink(temp_file)
+
+
+def test_crlf():
+    with tempfile.NamedTemporaryFile(mode="wb", suffix=".txt", delete=False) as f:
+        f.write(b"key = value  \r\nother\r\nlast value\r\n")
+        temp_file = f.name
+
+    try:
+        # Without crlf, $ can't match before the \r
+        assert RipGrep("value$").search(temp_file) == []
+
+        results = RipGrep(r"value\s*$", crlf=True).search(temp_file)
+        assert [r["line"] for r in results] == ["key = value  ", "last value"]
+        assert results[0]["matches"] == [{"start": 6, "end": 13}]
+
+        context = RipGrep("last", crlf=True, before_context=1).search(temp_file)
+        assert context[0]["context_before"] == [(2, "other")]
+
+        # Raw bytes keep the trailing spaces too
+        raw = RipGrep("key", crlf=True, raw_bytes=True).search(temp_file)
+        assert raw[0]["line"] == b"key = value  "
+    finally:
+        os.unlink(temp_file)
+
+
+def test_search_all_archives():
+    import gzip
+    import io
+    import tarfile
+
+    with tempfile.TemporaryDirectory() as temp_dir:
+        plain = os.path.join(temp_dir, "plain.txt")
+        with open(plain, "w") as f:
+            f.write("needle in a file\n")
+
+        archive = os.path.join(temp_dir, "data.tar.gz")
+        with tarfile.open(archive, "w:gz") as tar:
+            data = b"hay\nneedle in an archive\n"
+            info = tarfile.TarInfo("docs/inner.txt")
+            info.size = len(data)
+            tar.addfile(info, io.BytesIO(data))
+
+        compressed = os.path.join(temp_dir, "log.gz")
+        with gzip.open(compressed, "wb") as f:
+            f.write(b"needle in a gzip\n")
+
+        results = sorted(
+            RipGrep("needle").search_all(temp_dir), key=lambda r: r["file"]
+        )
+        assert [(r["file"], r["line_number"], r["line"]) for r in results] == [
+            (f"{archive}!docs/inner.txt", 2, "needle in an archive"),
+            (compressed, 1, "needle in a gzip"),
+            (plain, 1, "needle in a file"),
+        ]
+
+        # Plain searches still treat archives as opaque files
+        assert [r["file"] for r in RipGrep("needle").search(temp_dir)] == [plain]
+
+
+def test_limit_behavior_error():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        for name in ("a.txt", "b.txt"):
+            with open(os.path.join(temp_dir, name), "w") as f:
+                f.write("needle\nneedle\n")
+
+        # The default quietly truncates
+        assert len(RipGrep("needle", max_per_dir=3).search(temp_dir)) == 3
+
+        rg = RipGrep("needle", max_per_dir=3, limit_behavior="error")
+        with pytest.raises(LimitExceeded) as excinfo:
+            rg.search(temp_dir)
+        partial = excinfo.value.partial_results
+        assert len(partial) == 3
+        assert all(r["line"] == "needle" for r in partial)
+
+        # Within the limit nothing is raised
+        rg = RipGrep("needle", max_per_dir=4, limit_behavior="error")
+        assert len(rg.search(temp_dir)) == 4
+
+        # A file over max_count raises with the matches so far, whichever
+        # way it's searched
+        a = os.path.join(temp_dir, "a.txt")
+        for threads in (1, 2):
+            rg = RipGrep("needle", max_count=1, limit_behavior="error", threads=threads)
+            for path in (a, temp_dir):
+                with pytest.raises(LimitExceeded) as excinfo:
+                    rg.search(path)
+                assert len(excinfo.value.partial_results) >= 1
+                assert all(r["line"] == "needle" for r in excinfo.value.partial_results)
+            with pytest.raises(LimitExceeded):
+                list(rg.search_iter(temp_dir))
+        with pytest.raises(LimitExceeded) as excinfo:
+            rg.count(a)
+        assert excinfo.value.partial_results == [{"file": a, "count": 1}]
+        # Listing files stops at each one's first match regardless
+        assert len(rg.files_with_matches(temp_dir)) == 2
+        with pytest.raises(LimitExceeded) as excinfo:
+            rg.search_text("needle\nneedle\n")
+        assert len(excinfo.value.partial_results) == 1
+        # Exactly max_count matches is within the limit
+        rg = RipGrep("needle", max_count=2, limit_behavior="error")
+        assert len(rg.search(temp_dir)) == 4
+
+        with pytest.raises(ValueError, match="Invalid limit_behavior"):
+            RipGrep("needle", limit_behavior="ignore")
+
+
+def test_max_total_limit_behavior(tmp_path):
+    for name in ("a.txt", "b.txt"):
+        (tmp_path / name).write_text("needle\nneedle\n")
+
+    # The default quietly truncates
+    assert len(RipGrep("needle", max_total=3).search(str(tmp_path))) == 3
+
+    for path, limit in ((str(tmp_path), 3), (str(tmp_path / "a.txt"), 1)):
+        rg = RipGrep("needle", max_total=limit, limit_behavior="error")
+        with pytest.raises(LimitExceeded) as excinfo:
+            rg.search(path)
+        assert len(excinfo.value.partial_results) == limit
+
+    # Streamed, the matches within the limit are yielded before it raises
+    rg = RipGrep("needle", max_total=3, limit_behavior="error")
+    yielded = []
+    with pytest.raises(LimitExceeded):
+        for m in rg.search_iter(str(tmp_path)):
+            yielded.append(m)
+    assert len(yielded) == 3
+
+    # Exactly at the limit nothing is raised
+    rg = RipGrep("needle", max_total=4, limit_behavior="error")
+    assert len(rg.search(str(tmp_path))) == 4
+    assert len(list(rg.search_iter(str(tmp_path)))) == 4
+
+
+@pytest.mark.skipif(sys.platform == "win32", reason="byte patterns tested on Unix")
+def test_from_bytes_pattern():
+    with tempfile.NamedTemporaryFile(mode="wb", suffix=".bin", delete=False) as f:
+        f.write(b"header\n")
+        f.write(b"magic \x80\xff marker\n")
+        f.write(b"plain \xc2\x80 utf8\n")
+        temp_file = f.name
+
+    try:
+        rg = RipGrep.from_bytes_pattern(b"\x80\xff")
+        results = rg.search(temp_file)
+        assert [r["line_number"] for r in results] == [2]
+        assert results[0]["line"] == "magic �� marker"
+
+        # Byte sequences that happen to be valid UTF-8 match as well
+        rg = RipGrep.from_bytes_pattern(b"^plain \xc2\x80")
+        assert [r["line_number"] for r in rg.search(temp_file)] == [3]
+    finally:
+        os.unlink(temp_file)
+
+
+def test_density():
+    with tempfile.NamedTemporaryFile(mode="w", suffix=".txt", delete=False) as f:
+        for i in range(1, 101):
+            f.write("needle\n" if 41 <= i <= 50 or i == 5 else "hay\n")
+        temp_file = f.name
+
+    try:
+        density = RipGrep("needle").density(temp_file, 10)
+        assert density == [1, 0, 0, 0, 10, 0, 0, 0, 0, 0]
+        assert max(range(len(density)), key=density.__getitem__) == 4
+
+        # A partial last bucket is still reported
+        assert RipGrep("needle").density(temp_file, 30) == [1, 10, 0, 0]
+
+        with pytest.raises(ValueError):
+            RipGrep("needle").density(temp_file, 0)
+    finally:
+        os.unlink(temp_file)
+
+
+def test_paragraph():
+    with tempfile.NamedTemporaryFile(mode="w", suffix=".md", delete=False) as f:
+        f.write("# Title\n")
+        f.write("\n")
+        f.write("The first paragraph\n")
+        f.write("mentions a needle\n")
+        f.write("over three lines.\n")
+        f.write("\n")
+        f.write("Another paragraph.\n")
+        temp_file = f.name
+
+    try:
+        results = RipGrep("needle", paragraph=True).search(temp_file)
+        assert len(results) == 1
+        assert results[0]["line"] == "mentions a needle"
+        assert results[0]["paragraph"] == (
+            "The first paragraph\nmentions a needle\nover three lines."
+        )
+
+        results = RipGrep("paragraph", paragraph=True).search(temp_file)
+        assert [r["paragraph"] for r in results] == [
+            "The first paragraph\nmentions a needle\nover three lines.",
+            "Another paragraph.",
+        ]
+    finally:
+        os.unlink(temp_file)
+
+
+@pytest.mark.skipif(sys.platform == "win32", reason="uses a shell preprocessor")
+def test_run_ripgrep_cancellable():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        target = os.path.join(temp_dir, "file.txt")
+        with open(target, "w") as f:
+            f.write("needle\n")
+
+        # A preprocessor that hangs keeps ripgrep busy until it's killed
+        slow = os.path.join(temp_dir, "slow.sh")
+        with open(slow, "w") as f:
+            f.write("#!/bin/sh\nexec sleep 60 </dev/null >/dev/null 2>&1\n")
+        os.chmod(slow, 0o755)
+
+        start = time.monotonic()
+        process = run_ripgrep_cancellable(["--pre", slow, "needle", target])
+        time.sleep(0.2)
+        process.cancel()
+        exit_code, stdout, stderr = process.wait()
+        assert time.monotonic() - start < 30
+        assert exit_code == -1
+        assert stdout == ""
+
+        with pytest.raises(RuntimeError):
+            process.wait()
+
+        # Without cancelling, wait returns the normal output
+        process = run_ripgrep_cancellable(["needle", target])
+        exit_code, stdout, _ = process.wait()
+        assert exit_code == 0
+        assert "needle" in stdout
+
+
+def test_text_only():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        text = os.path.join(temp_dir, "notes.txt")
+        with open(text, "w") as f:
+            f.write("needle in plain text\n\tindented\n")
+
+        # Mostly control bytes but no NUL anywhere, so NUL detection misses it
+        blob = os.path.join(temp_dir, "blob.dat")
+        with open(blob, "wb") as f:
+            f.write(bytes(range(1, 32)) * 40 + b"needle\n")
+
+        assert len(RipGrep("needle").search(temp_dir)) == 2
+
+        results = RipGrep("needle", text_only=True).search(temp_dir)
+        assert [r["file"] for r in results] == [text]
+
+        # A permissive threshold lets the blob through again
+        rg = RipGrep("needle", text_only=True, text_threshold=1.0)
+        assert len(rg.search(temp_dir)) == 2
+
+        with pytest.raises(ValueError, match="Invalid text_threshold"):
+            RipGrep("needle", text_threshold=1.5)
+
+
+def test_diff():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        path = os.path.join(temp_dir, "todo.txt")
+        with open(path, "w") as f:
+            f.write("TODO: write docs\nTODO: fix bug\n")
+
+        rg = RipGrep("TODO")
+        before = rg.search(temp_dir)
+
+        with open(path, "w") as f:
+            f.write("intro\nTODO: write docs\nTODO: add tests\n")
+
+        delta = rg.diff(temp_dir, before)
+        assert [r["line"] for r in delta["added"]] == ["TODO: add tests"]
+        assert [r["line"] for r in delta["removed"]] == ["TODO: fix bug"]
+        # Moving down a line doesn't count as a change
+        assert [(r["line"], r["line_number"]) for r in delta["unchanged"]] == [
+            ("TODO: write docs", 2)
+        ]
+
+        # The returned hashes stand in for the full results next time
+        again = rg.diff(temp_dir, delta["hashes"])
+        assert again["added"] == [] and again["removed"] == []
+        assert len(again["unchanged"]) == 2
+
+
+def test_normalize():
+    composed = "café"
+    decomposed = "café"
+    with tempfile.NamedTemporaryFile(
+        mode="w", encoding="utf-8", suffix=".txt", delete=False
+    ) as f:
+        f.write(f"a {decomposed} here\n")
+        temp_file = f.name
+
+    try:
+        assert RipGrep(composed).search(temp_file) == []
+
+        results = RipGrep(composed, normalize="nfc").search(temp_file)
+        assert [r["line"] for r in results] == [f"a {composed} here"]
+
+        # The pattern is normalized too, so either spelling finds the line
+        results = RipGrep(decomposed, normalize="nfc").search(temp_file)
+        assert len(results) == 1
+
+        with pytest.raises(ValueError, match="Invalid normalize"):
+            RipGrep(composed, normalize="nfkc")
+    finally:
+        os.unlink(temp_file)
+
+
+def test_search_files_with_options():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        utf8_file = os.path.join(temp_dir, "utf8.txt")
+        latin1_file = os.path.join(temp_dir, "latin1.txt")
+        with open(utf8_file, "w", encoding="utf-8") as f:
+            f.write("un café noir\n")
+        with open(latin1_file, "w", encoding="latin-1") as f:
+            f.write("un café au lait\n")
+
+        rg = RipGrep("café")
+        results = rg.search_files_with_options(
+            [{"path": utf8_file}, {"path": latin1_file, "encoding": "latin1"}]
+        )
+        assert [(r["file"], r["line"]) for r in results] == [
+            (utf8_file, "un café noir"),
+            (latin1_file, "un café au lait"),
+        ]
+
+        # Overrides apply to their own spec only
+        assert rg.search(latin1_file) == []
+        assert rg.config()["encoding"] is None
+
+        with pytest.raises(ValueError, match="Invalid encoding"):
+            rg.search_files_with_options([{"path": utf8_file, "encoding": "bogus"}])
+        with pytest.raises(ValueError, match="needs a path"):
+            rg.search_files_with_options([{"encoding": "latin1"}])
+
+        # The pattern was built with these, so they can't change per path
+        for key, value in (("fixed_strings", True), ("normalize", "nfc")):
+            with pytest.raises(TypeError, match=key):
+                rg.search_files_with_options([{"path": utf8_file, key: value}])
+
+
+def test_encoding(tmp_path):
+    sjis_file = tmp_path / "sjis.txt"
+    sjis_file.write_bytes("設定ファイル\nother\n".encode("shift_jis"))
+
+    results = RipGrep("設定", encoding="shift_jis").search(str(sjis_file))
+    assert [(r["line_number"], r["line"]) for r in results] == [(1, "設定ファイル")]
+
+    utf8_file = tmp_path / "utf8.txt"
+    utf8_file.write_text("設定\n", encoding="utf-8")
+    assert len(RipGrep("設定", encoding="auto").search(str(utf8_file))) == 1
+
+    with pytest.raises(ValueError, match="Invalid encoding"):
+        RipGrep("x", encoding="not-an-encoding")
+
+
+def test_profile_per_file_state(tmp_path):
+    (tmp_path / "a.min.js").write_text("var a=1;" * 10000 + "needle\n")
+    # Mostly control bytes, so text_only passes it over before reading it
+    (tmp_path / "b.dat").write_bytes(bytes(range(1, 32)) * 40 + b"needle\n")
+
+    rg = RipGrep(
+        "needle",
+        skip_long_lines=1000,
+        text_only=True,
+        report_lines_scanned=True,
+        stable=True,
+    )
+    profile = {os.path.basename(p["file"]): p for p in rg.profile(str(tmp_path))}
+    assert profile["a.min.js"]["skip_reason"] == "long_line"
+    # Nothing is left over from the file before
+    assert profile["b.dat"]["skipped"] is False
+    assert profile["b.dat"]["skip_reason"] is None
+    assert profile["b.dat"]["lines_scanned"] is None
+
+
+def test_profile_on_error(tmp_path):
+    (tmp_path / "good.txt").write_text("needle\n")
+    # A matched line that isn't UTF-8 fails the file's search
+    (tmp_path / "bad.txt").write_bytes(b"needle \xff\xfe\n")
+
+    rg = RipGrep("needle", on_error="ignore")
+    assert [os.path.basename(p["file"]) for p in rg.profile(str(tmp_path))] == [
+        "good.txt"
+    ]
+
+    collected = RipGrep("needle", on_error="collect").profile(str(tmp_path))
+    assert [os.path.basename(p["file"]) for p in collected["files"]] == ["good.txt"]
+    assert [e["file"] for e in collected["errors"]] == [str(tmp_path / "bad.txt")]
+
+    with pytest.raises(BinaryError):
+        RipGrep("needle", on_error="raise").profile(str(tmp_path))
+
+
+def test_skip_long_lines():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        minified = os.path.join(temp_dir, "app.min.js")
+        with open(minified, "w") as f:
+            f.write("needle first\n")
+            f.write("var a=1;" * 10000 + "needle\n")
+            f.write("needle last\n")
+        normal = os.path.join(temp_dir, "normal.txt")
+        with open(normal, "w") as f:
+            f.write("needle here\n")
+
+        rg = RipGrep("needle", skip_long_lines=1000)
+        results = rg.search(temp_dir)
+        assert [r["file"] for r in results] == [normal]
+
+        profile = {p["file"]: p for p in rg.profile(temp_dir)}
+        assert profile[minified]["skipped"] is True
+        assert profile[minified]["skip_reason"] == "long_line"
+        assert profile[normal]["skip_reason"] is None
+
+        # Matches before the long line survive when partial results are kept
+        rg = RipGrep("needle", skip_long_lines=1000, keep_partial_long_lines=True)
+        results = rg.search(minified)
+        assert [r["line"] for r in results] == ["needle first"]
+
+        assert len(RipGrep("needle").search(minified)) == 3
+
+
+def test_exception_hierarchy():
+    with pytest.raises(PatternError, match="Invalid regex") as excinfo:
+        RipGrep("[invalid")
+    assert isinstance(excinfo.value, SupError)
+    assert isinstance(excinfo.value, ValueError)
+
+    with tempfile.TemporaryDirectory() as temp_dir:
+        missing = os.path.join(temp_dir, "missing.txt")
+        with pytest.raises(SearchIOError, match="No such file") as excinfo:
+            RipGrep("needle").search(missing)
+        assert isinstance(excinfo.value, SupError)
+        assert isinstance(excinfo.value, OSError)
+
+        with pytest.raises(SearchIOError):
+            list(RipGrep("needle").search_generator(missing))
+
+        binary = os.path.join(temp_dir, "binary.dat")
+        with open(binary, "wb") as f:
+            f.write(b"needle \xff\xfe\n")
+        with pytest.raises(BinaryError):
+            RipGrep("needle").search(binary)
+
+    assert issubclass(LimitExceeded, SupError)
+
+
+def test_search_tail():
+    with tempfile.NamedTemporaryFile(mode="w", suffix=".log", delete=False) as f:
+        f.write("error: old\ninfo: ok\n")
+        temp_file = f.name
+
+    try:
+        rg = RipGrep("error")
+        results, offset = rg.search_tail(temp_file, 0)
+        assert [r["line"] for r in results] == ["error: old"]
+        assert results[0]["byte_offset"] == 0
+        assert offset == os.path.getsize(temp_file)
+
+        with open(temp_file, "a") as f:
+            f.write("error: new\n")
+        results, new_offset = rg.search_tail(temp_file, offset)
+        assert [r["line"] for r in results] == ["error: new"]
+        assert results[0]["byte_offset"] == offset
+        assert new_offset == os.path.getsize(temp_file)
+
+        # Nothing appended since, nothing found
+        assert rg.search_tail(temp_file, new_offset) == ([], new_offset)
+
+        # An offset inside a line skips to the start of the next one
+        results, _ = rg.search_tail(temp_file, 3)
+        assert [r["line"] for r in results] == ["error: new"]
+    finally:
+        os.unlink(temp_file)
+
+
+def test_search_tail_partial_line(tmp_path):
+    log = tmp_path / "app.log"
+    log.write_text("info: start\n")
+    rg = RipGrep("error")
+    _, offset = rg.search_tail(str(log), 0)
+
+    # A line still being written isn't searched, nor skipped past
+    with open(log, "a") as f:
+        f.write("error: par")
+    assert rg.search_tail(str(log), offset) == ([], offset)
+
+    with open(log, "a") as f:
+        f.write("tial\n")
+    results, offset = rg.search_tail(str(log), offset)
+    assert [r["line"] for r in results] == ["error: partial"]
+    assert offset == log.stat().st_size
+
+    # Truncated, or rotated, below the offset: searched again from the start
+    log.write_text("error: rotated\n")
+    results, offset = rg.search_tail(str(log), offset)
+    assert [r["line"] for r in results] == ["error: rotated"]
+    assert offset == log.stat().st_size
+
+
+def test_smart_case():
+    with tempfile.NamedTemporaryFile(mode="w", suffix=".txt", delete=False) as f:
+        f.write("Hello world\nhello again\n")
+        temp_file = f.name
+
+    try:
+        assert len(RipGrep("hello").search(temp_file)) == 1
+        assert len(RipGrep("hello", smart_case=True).search(temp_file)) == 2
+        # An uppercase letter makes the search case sensitive
+        results = RipGrep("Hello", smart_case=True).search(temp_file)
+        assert [r["line"] for r in results] == ["Hello world"]
+        assert RipGrep("hello", smart_case=True).config()["smart_case"] is True
+
+        with pytest.raises(ValueError, match="can't both be set"):
+            RipGrep("hello", case_insensitive=True, smart_case=True)
+    finally:
+        os.unlink(temp_file)
+
+
+def test_stable():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        for name in ["b", "a", "c/d", "c/a", "a-b"]:
+            path = os.path.join(temp_dir, name + ".txt")
+            os.makedirs(os.path.dirname(path), exist_ok=True)
+            with open(path, "w") as f:
+                f.write("needle 1\nhay\nneedle 2\n")
+
+        rg = RipGrep("needle", stable=True)
+        first = repr(rg.search(temp_dir))
+        assert repr(rg.search(temp_dir)) == first
+
+        results = rg.search(temp_dir)
+        keys = [(r["file"], r["line_number"]) for r in results]
+        assert len(keys) == 10
+        assert keys == sorted(keys, key=lambda k: (k[0].split(os.sep), k[1]))
+
+        # Then by where on the line each match starts
+        rg = RipGrep(r"needle \d\nhay|2", stable=True, multiline=True, threads=4)
+        first = repr(rg.search(temp_dir))
+        assert repr(rg.search(temp_dir)) == first
+        keys = [
+            (r["file"].split(os.sep), r["line_number"], r["matches"][0]["start"])
+            for r in rg.search(temp_dir)
+        ]
+        assert keys == sorted(keys)
+
+
+def test_sort(tmp_path):
+    for name, mtime in [("b", 300), ("a", 200), ("c/d", 100)]:
+        path = tmp_path / (name + ".txt")
+        path.parent.mkdir(exist_ok=True)
+        path.write_text("needle 1\nhay\nneedle 2\n")
+        os.utime(path, (mtime, mtime))
+
+    def order(**options):
+        results = RipGrep("needle", **options).search(str(tmp_path))
+        return [
+            (os.path.relpath(r["file"], tmp_path), r["line_number"]) for r in results
+        ]
+
+    c_d = os.path.join("c", "d.txt")
+    assert order(sort="path") == [
+        ("a.txt", 1),
+        ("a.txt", 3),
+        ("b.txt", 1),
+        ("b.txt", 3),
+        (c_d, 1),
+        (c_d, 3),
+    ]
+    assert order(sort="modified") == [
+        (c_d, 1),
+        (c_d, 3),
+        ("a.txt", 1),
+        ("a.txt", 3),
+        ("b.txt", 1),
+        ("b.txt", 3),
+    ]
+    assert RipGrep("needle").config()["sort"] is None
+    with pytest.raises(ValueError, match="Invalid sort"):
+        RipGrep("needle", sort="size")
+
+
+def test_context_lines():
+    with tempfile.NamedTemporaryFile(mode="w", suffix=".txt", delete=False) as f:
+        f.write("a\nneedle 1\nb\nneedle 2\nc\nd\ne\n")
+        temp_file = f.name
+
+    try:
+        rg = RipGrep("needle", before_context=1, after_context=1)
+        results = rg.search(temp_file)
+        assert [r["line_number"] for r in results] == [2, 4]
+        assert results[0]["context_before"] == [(1, "a")]
+        assert results[0]["context_after"] == [(3, "b")]
+        # Line 3 was already reported after the first match
+        assert results[1]["context_before"] == []
+        assert results[1]["context_after"] == [(5, "c")]
+
+        # Context is cut short at the edges of the file
+        rg = RipGrep(r"\ba\b|\be\b", before_context=3, after_context=3)
+        results = rg.search(temp_file)
+        assert [r["line_number"] for r in results] == [1, 7]
+        assert results[0]["context_before"] == []
+        assert results[1]["context_after"] == []
+        assert results[0]["context_after"] == [
+            (2, "needle 1"),
+            (3, "b"),
+            (4, "needle 2"),
+        ]
+        assert results[1]["context_before"] == [(5, "c"), (6, "d")]
+
+        assert "context_before" not in RipGrep("needle").search(temp_file)[0]
+    finally:
+        os.unlink(temp_file)
+
+
+def test_verify_platform():
+    with warnings.catch_warnings():
+        warnings.simplefilter("error")
+        assert verify_platform() is True
+
+
+def test_match_spans():
+    with tempfile.NamedTemporaryFile(
+        mode="w", encoding="utf-8", suffix=".txt", delete=False
+    ) as f:
+        f.write("ab ab\n")
+        f.write("é ab\n")
+        temp_file = f.name
+
+    try:
+        results = RipGrep("ab").search(temp_file)
+        assert results[0]["matches"] == [
+            {"start": 0, "end": 2},
+            {"start": 3, "end": 5},
+        ]
+        # Offsets count bytes, and "é" is two of them
+        assert results[1]["matches"] == [{"start": 3, "end": 5}]
+    finally:
+        os.unlink(temp_file)
+
+
+def test_count():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        with open(os.path.join(temp_dir, "a.txt"), "w") as f:
+            f.write("needle\nhay\nneedle needle\n")
+        with open(os.path.join(temp_dir, "b.txt"), "w") as f:
+            f.write("needle\n")
+        with open(os.path.join(temp_dir, "empty.txt"), "w") as f:
+            f.write("hay\n")
+
+        rg = RipGrep("needle")
+        counts = {c["file"]: c["count"] for c in rg.count(temp_dir)}
+        # Matching lines are counted, not occurrences; empty files are omitted
+        assert counts == {
+            os.path.join(temp_dir, "a.txt"): 2,
+            os.path.join(temp_dir, "b.txt"): 1,
+        }
+
+        for entry in rg.count(temp_dir):
+            assert entry["count"] == len(rg.search(entry["file"]))
+
+        excluded = RipGrep("needle", exclude_if_matches=["needle needle"])
+        counts = {c["file"]: c["count"] for c in excluded.count(temp_dir)}
+        assert counts[os.path.join(temp_dir, "a.txt")] == 1
+
+
+def test_collapse_whitespace():
+    with tempfile.NamedTemporaryFile(mode="w", suffix=".txt", delete=False) as f:
+        f.write("call foo    bar here\n")
+        f.write("foo\t \tbar\n")
+        f.write("foobar\n")
+        temp_file = f.name
+
+    try:
+        assert RipGrep("foo bar").search(temp_file) == []
+
+        rg = RipGrep("foo bar", collapse_whitespace=True, after_context=1)
+        results = rg.search(temp_file)
+        # Lines are reported as written, not as matched
+        assert [r["line"] for r in results] == ["call foo    bar here", "foo\t \tbar"]
+        assert results[1]["context_after"] == [(3, "foobar")]
+    finally:
+        os.unlink(temp_file)
+
+
+def test_include_depths():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        paths = {
+            1: os.path.join(temp_dir, "top.txt"),
+            2: os.path.join(temp_dir, "a", "mid.txt"),
+            3: os.path.join(temp_dir, "a", "b", "deep.txt"),
+        }
+        for path in paths.values():
+            os.makedirs(os.path.dirname(path), exist_ok=True)
+            with open(path, "w") as f:
+                f.write("needle\n")
+
+        def depths(**options):
+            results = RipGrep("needle", **options).search(temp_dir)
+            found = {r["file"] for r in results}
+            return sorted(d for d, path in paths.items() if path in found)
+
+        assert depths() == [1, 2, 3]
+        assert depths(include_depths=[1, 3]) == [1, 3]
+        assert depths(include_depths=[2]) == [2]
+        assert depths(max_depth=2) == [1, 2]
+        # Both limits apply
+        assert depths(include_depths=[1, 3], max_depth=2) == [1]
+
+
+def test_include_exclude_globs():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        for name in ["app.py", "app.js", "node_modules/lib.js", "build/out.py"]:
+            path = os.path.join(temp_dir, name)
+            os.makedirs(os.path.dirname(path), exist_ok=True)
+            with open(path, "w") as f:
+                f.write("needle\n")
+
+        def found(**options):
+            results = RipGrep("needle", **options).search(temp_dir)
+            return sorted(os.path.relpath(r["file"], temp_dir) for r in results)
+
+        python_files = ["app.py", os.path.join("build", "out.py")]
+        assert found(include_globs=["*.py"]) == python_files
+        assert found(exclude_globs=["node_modules", "build"]) == ["app.js", "app.py"]
+        # A leading "!" in an include glob excludes, as with rg -g
+        assert found(include_globs=["*.py", "!build"]) == ["app.py"]
+
+        with pytest.raises(ValueError, match="Invalid glob"):
+            RipGrep("needle", include_globs=["a{b"])
+
+
+def test_no_ignore():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        os.mkdir(os.path.join(temp_dir, ".git"))
+        with open(os.path.join(temp_dir, ".gitignore"), "w") as f:
+            f.write("ignored.txt\n")
+        for name in ["kept.txt", "ignored.txt", ".hidden.txt"]:
+            with open(os.path.join(temp_dir, name), "w") as f:
+                f.write("needle\n")
+
+        def found(**options):
+            results = RipGrep("needle", **options).search(temp_dir)
+            return sorted(os.path.basename(r["file"]) for r in results)
+
+        assert found() == ["kept.txt"]
+        assert found(no_ignore=True) == ["ignored.txt", "kept.txt"]
+        assert found(hidden=True) == [".hidden.txt", "kept.txt"]
+        assert found(no_ignore=True, hidden=True) == [
+            ".hidden.txt",
+            "ignored.txt",
+            "kept.txt",
+        ]
+
+
+def test_file_span():
+    content = b"start\nfoo\nbar end\nfoo\nbaz\n"
+    with tempfile.NamedTemporaryFile(suffix=".txt", delete=False) as f:
+        f.write(content)
+        temp_file = f.name
+
+    try:
+        rg = RipGrep(r"foo\nbar", multiline=True, file_span=True)
+        results = rg.search(temp_file)
+        assert len(results) == 1
+        assert results[0]["line_number"] == 2
+        [(start, end)] = results[0]["file_spans"]
+        assert content[start:end] == b"foo\nbar"
+
+        results = RipGrep("ba.", file_span=True).search(temp_file)
+        assert [content[s:e] for r in results for s, e in r["file_spans"]] == [
+            b"bar",
+            b"baz",
+        ]
+    finally:
+        os.unlink(temp_file)
+
+
+def test_absolute_offset():
+    content = b"a\nneedle one\nsome much longer line here\n\nx\nlast needle"
+    with tempfile.NamedTemporaryFile(suffix=".txt", delete=False) as f:
+        f.write(content)
+        temp_file = f.name
+
+    try:
+        results = RipGrep("needle", absolute_offset=True).search(temp_file)
+        offsets = [r["offset"] for r in results]
+        assert offsets == [2, content.index(b"last needle")]
+        for r, offset in zip(results, offsets):
+            assert content[offset:].startswith(r["line"].encode())
+
+        assert "offset" not in RipGrep("needle").search(temp_file)[0]
+    finally:
+        os.unlink(temp_file)
+
+
+def test_dir_match_summary():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        files = {
+            "top.txt": "hay\n",
+            "a/x.txt": "needle\n",
+            "a/y.txt": "needle needle\nneedle\n",
+            "a/z.txt": "hay\n",
+            "a/b/w.txt": "needle\n",
+        }
+        for name, text in files.items():
+            path = os.path.join(temp_dir, name)
+            os.makedirs(os.path.dirname(path), exist_ok=True)
+            with open(path, "w") as f:
+                f.write(text)
+
+        summary = RipGrep("needle").dir_match_summary(temp_dir)
+        # Files with matches are counted, not the matches in them
+        assert summary == {
+            os.path.join(temp_dir, "a"): 2,
+            os.path.join(temp_dir, "a", "b"): 1,
+        }
+
+
+def test_max_depth():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        top = os.path.join(temp_dir, "top.txt")
+        nested = os.path.join(temp_dir, "a", "b", "nested.txt")
+        os.makedirs(os.path.dirname(nested))
+        for path in [top, nested]:
+            with open(path, "w") as f:
+                f.write("needle\n")
+
+        results = RipGrep("needle", max_depth=1).search(temp_dir)
+        assert [r["file"] for r in results] == [top]
+
+        results = RipGrep("needle", max_depth=None).search(temp_dir)
+        assert {r["file"] for r in results} == {top, nested}
+
+
+@pytest.mark.skipif(sys.platform == "win32", reason="Unix mount layout")
+def test_same_file_system(tmp_path):
+    nested = tmp_path / "a" / "nested.txt"
+    nested.parent.mkdir()
+    nested.write_text("needle\n")
+
+    rg = RipGrep("needle", same_file_system=True)
+    assert rg.config()["same_file_system"] is True
+    assert RipGrep("needle").config()["same_file_system"] is False
+    # Everything under a fresh temp dir is on one filesystem
+    assert [r["file"] for r in rg.search(str(tmp_path))] == [str(nested)]
+
+
+def test_parallel_walk():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        for i in range(40):
+            with open(os.path.join(temp_dir, f"file{i:02}.txt"), "w") as f:
+                for line in range(30):
+                    f.write("needle\n" if line % 2 == 0 else "hay\n")
+
+        serial = RipGrep("needle", threads=1).search(temp_dir)
+        parallel = RipGrep("needle", threads=4)
+        results = parallel.search(temp_dir)
+        assert len(results) == len(serial) == 40 * 15
+        assert sorted(map(repr, results)) == sorted(map(repr, serial))
+
+        # Each file's matches stay together and in line order
+        by_file = {}
+        for r in results:
+            by_file.setdefault(r["file"], []).append(r["line_number"])
+        assert all(lines == list(range(1, 31, 2)) for lines in by_file.values())
+        files = [r["file"] for r in results]
+        assert files == sorted(files, key=lambda f: list(by_file).index(f))
+
+        assert parallel.search(temp_dir) == results
+
+
+def test_ignore_matcher():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        os.mkdir(os.path.join(temp_dir, "sub"))
+        files = {
+            ".gitignore": "ignored.txt\n",
+            "sub/.gitignore": "*.log\n",
+            "kept.txt": "needle\n",
+            "ignored.txt": "needle\n",
+            "sub/x.log": "needle\n",
+        }
+        for name, content in files.items():
+            with open(os.path.join(temp_dir, name), "w") as f:
+                f.write(content)
+
+        kept = os.path.join(temp_dir, "kept.txt")
+        matcher = IgnoreMatcher(temp_dir)
+        assert matcher.is_ignored(os.path.join(temp_dir, "sub", "y.log"))
+        assert not matcher.is_ignored(os.path.join(temp_dir, "y.log"))
+
+        rg = RipGrep("needle")
+        for _ in range(2):
+            results = rg.search(temp_dir, ignore=matcher)
+            assert [r["file"] for r in results] == [kept]
+
+        # The rules were read once, so later changes on disk don't matter
+        os.remove(os.path.join(temp_dir, ".gitignore"))
+        os.remove(os.path.join(temp_dir, "sub", ".gitignore"))
+        results = rg.search(temp_dir, ignore=matcher)
+        assert [r["file"] for r in results] == [kept]
+
+        # Outside a git repository the walk itself ignores nothing
+        assert len(rg.search(temp_dir)) == 3
+
+
+def test_ignore_matcher_ignored_directory(tmp_path):
+    (tmp_path / ".gitignore").write_text("build/\n")
+    (tmp_path / "build").mkdir()
+
+    matcher = IgnoreMatcher(str(tmp_path))
+    assert matcher.is_ignored(str(tmp_path / "build"), is_dir=True)
+    # Anything inside an ignored directory is ignored with it
+    assert matcher.is_ignored(str(tmp_path / "build" / "x.o"))
+    assert matcher.is_ignored(str(tmp_path / "build" / "sub" / "y.o"))
+    assert not matcher.is_ignored(str(tmp_path / "src" / "x.o"))
+
+
+def test_ignore_matcher_invalid_gitignore(tmp_path):
+    (tmp_path / ".gitignore").write_text("a[\n")
+    with pytest.raises(PatternError, match=r"\.gitignore"):
+        IgnoreMatcher(str(tmp_path))
+
+
+def test_split():
+    with tempfile.NamedTemporaryFile(
+        mode="w", encoding="utf-8", suffix=".txt", delete=False
+    ) as f:
+        f.write("a,b,c\n")
+        f.write(",x,\n")
+        temp_file = f.name
+
+    try:
+        results = RipGrep(",", split=True).search(temp_file)
+        assert results[0]["segments"] == ["a", ",", "b", ",", "c"]
+        assert results[1]["segments"] == ["", ",", "x", ",", ""]
+        assert "segments" not in RipGrep(",").search(temp_file)[0]
+    finally:
+        os.unlink(temp_file)
+
+
+def test_search_text():
+    content = "Hello world\nnothing here\nHELLO again\n"
+    results = RipGrep("hello", case_insensitive=True).search_text(content)
+    assert [(r["file"], r["line_number"]) for r in results] == [(None, 1), (None, 3)]
+    assert results[1]["line"] == "HELLO again"
+    assert RipGrep("hello").search_text(content) == []
+
+
+def test_search_reader():
+    import io
+
+    content = "Hello world\nnothing here\nHELLO again\n"
+    rg = RipGrep("hello", case_insensitive=True)
+    results = rg.search_reader(io.BytesIO(content.encode()))
+    assert [(r["file"], r["line_number"]) for r in results] == [
+        ("<stdin>", 1),
+        ("<stdin>", 3),
+    ]
+    assert rg.search_reader(io.StringIO(content)) == results
+
+    script = (
+        "from sup import RipGrep; "
+        "print([r['line_number'] for r in RipGrep('b').search_reader()])"
+    )
+    proc = subprocess.run(
+        [sys.executable, "-c", script],
+        input="a\nb\nc\nb\n",
+        capture_output=True,
+        text=True,
+    )
+    assert proc.stdout.strip() == "[2, 4]"
+
+
+def test_column():
+    content = "naïve café: needle\nneedle first\n"
+    results = RipGrep("needle", column=True).search_text(content)
+    # "naïve café: " is 12 characters but 14 bytes
+    assert [(r["line_number"], r["column"]) for r in results] == [(1, 13), (2, 1)]
+    assert results[0]["matches"] == [{"start": 14, "end": 20}]
+
+    inverted = RipGrep("needle", column=True, invert_match=True)
+    assert [r["column"] for r in inverted.search_text("hay\n")] == [None]
+    assert "column" not in RipGrep("needle").search_text(content)[0]
+
+
+def test_fixed_strings():
+    content = "call foo(x).bar\ncall foox-bar\n"
+    results = RipGrep("foo(x).bar", fixed_strings=True).search_text(content)
+    assert [r["line_number"] for r in results] == [1]
+    assert results[0]["matches"] == [{"start": 5, "end": 15}]
+
+    # As a regex the same pattern matches the other line instead
+    results = RipGrep("foo(x).bar").search_text(content)
+    assert [r["line_number"] for r in results] == [2]
+
+    # Unbalanced metacharacters are fine once escaped
+    assert len(RipGrep("(", fixed_strings=True).search_text(content)) == 1
+    with pytest.raises(PatternError):
+        RipGrep("(")
+
+
+def test_search_concat():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        first = os.path.join(temp_dir, "log.1")
+        second = os.path.join(temp_dir, "log.2")
+        with open(first, "w") as f:
+            f.write("start\nneedle one")
+        with open(second, "w") as f:
+            f.write("needle two\nend\nneedle three\n")
+
+        results = RipGrep("needle").search_concat([first, second])
+        assert [(r["file"], r["line_number"], r["line"]) for r in results] == [
+            (first, 2, "needle one"),
+            (second, 1, "needle two"),
+            (second, 3, "needle three"),
+        ]
+
+        # The last line of one file never runs on into the next
+        assert RipGrep("one needle").search_concat([first, second]) == []
+
+        with pytest.raises(SearchIOError):
+            RipGrep("needle").search_concat([os.path.join(temp_dir, "missing")])
+
+
+def test_report_lines_scanned():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        with open(os.path.join(temp_dir, "a.txt"), "w") as f:
+            f.write("needle\n" + "hay\n" * 9)
+        with open(os.path.join(temp_dir, "b.txt"), "w") as f:
+            f.write("hay\nhay\nlast line without a newline")
+
+        rg = RipGrep("needle", report_lines_scanned=True)
+        scanned = {
+            os.path.basename(p["file"]): p["lines_scanned"]
+            for p in rg.profile(temp_dir)
+        }
+        assert scanned == {"a.txt": 10, "b.txt": 3}
+
+        # A file given up on is only scanned up to the limit
+        rg = RipGrep("needle", report_lines_scanned=True, max_lines=5)
+        scanned = {
+            os.path.basename(p["file"]): p["lines_scanned"]
+            for p in rg.profile(temp_dir)
+        }
+        assert scanned == {"a.txt": 5, "b.txt": 3}
+
+        assert "lines_scanned" not in RipGrep("needle").profile(temp_dir)[0]
+
+
+def test_word():
+    content = "a cat sat\ncategory\nbobcat\nCAT.\n"
+    results = RipGrep("cat", word=True).search_text(content)
+    assert [r["line"] for r in results] == ["a cat sat"]
+    assert len(RipGrep("cat").search_text(content)) == 3
+
+    rg = RipGrep("cat", word=True, case_insensitive=True)
+    assert [r["line_number"] for r in rg.search_text(content)] == [1, 4]
+    rg = RipGrep("cat.", word=True, fixed_strings=True, case_insensitive=True)
+    assert [r["line_number"] for r in rg.search_text(content)] == [4]
+
+
+def test_invert_match():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        log = os.path.join(temp_dir, "app.log")
+        with open(log, "w") as f:
+            f.write("DEBUG one\nERROR two\nDEBUG three\nWARN four\n")
+        clean = os.path.join(temp_dir, "clean.log")
+        with open(clean, "w") as f:
+            f.write("DEBUG only\n")
+
+        rg = RipGrep("DEBUG", invert_match=True)
+        results = rg.search(log)
+        assert [(r["line_number"], r["line"]) for r in results] == [
+            (2, "ERROR two"),
+            (4, "WARN four"),
+        ]
+        assert results[0]["matches"] == []
+
+        # A walk reports the same lines, and nothing for a file of matches
+        results = rg.search(temp_dir)
+        assert [(r["file"], r["line_number"]) for r in results] == [(log, 2), (log, 4)]
+
+
+def test_search_filename_glob():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        os.mkdir(os.path.join(temp_dir, "pkg"))
+        files = {
+            "main.py": "import asyncio\n",
+            "pkg/loop.py": "asyncio.run(main())\n",
+            "notes.md": "asyncio notes\n",
+            "other.py": "import threading\n",
+        }
+        for name, content in files.items():
+            with open(os.path.join(temp_dir, name), "w") as f:
+                f.write(content)
+
+        rg = RipGrep("asyncio")
+        results = rg.search(temp_dir, filename_glob="*.py")
+        assert sorted(os.path.relpath(r["file"], temp_dir) for r in results) == [
+            "main.py",
+            os.path.join("pkg", "loop.py"),
+        ]
+        assert len(rg.search(temp_dir)) == 3
+
+        with pytest.raises(PatternError):
+            rg.search(temp_dir, filename_glob="[")
+
+
+def test_get_ripgrep_commit():
+    commit = get_ripgrep_commit()
+    assert len(commit) >= 7
+    assert all(c in "0123456789abcdef" for c in commit)
+
+
+def test_ripgrep_version():
+    version = ripgrep_version()
+    # Matches what the bundled binary itself reports
+    assert ripgrep("--version").stdout.split()[1].startswith(version)
+
+
+def test_run_ripgrep_json():
+    with tempfile.NamedTemporaryFile(mode="w", suffix=".txt", delete=False) as f:
+        f.write("Hello world\n")
+        f.write("test and test\n")
+        temp_file = f.name
+
+    try:
+        expected = [
+            {
+                "file": temp_file,
+                "line_number": 2,
+                "line": "test and test",
+                "absolute_offset": 12,
+                "submatches": [
+                    {"match": "test", "start": 0, "end": 4},
+                    {"match": "test", "start": 9, "end": 13},
+                ],
+            }
+        ]
+        assert run_ripgrep_json(["test", temp_file]) == expected
+        # An explicit --json isn't passed twice
+        assert run_ripgrep_json(["--json", "test", temp_file]) == expected
+        assert run_ripgrep_json(["nonexistent", temp_file]) == []
+
+        # After --, "--json" is the pattern, so the flag is still added
+        assert run_ripgrep_json(["--", "--json", temp_file]) == []
+        exit_code, stdout, _ = run_ripgrep(["test", temp_file], json=True)
+        assert exit_code == 0
+        assert [json.loads(line)["type"] for line in stdout.splitlines()][0] == "begin"
+    finally:
+        os.unlink(temp_file)
+
+
+def test_search_encoded():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        with open(os.path.join(temp_dir, "a.txt"), "w") as f:
+            f.write("needle one\nhay\nneedle é needle\n")
+        with open(os.path.join(temp_dir, "b.txt"), "w") as f:
+            f.write("no match\n")
+
+        rg = RipGrep("needle")
+        encoded = rg.search_encoded(temp_dir)
+        assert isinstance(encoded, bytes)
+        expected = [
+            {key: r[key] for key in ("file", "line_number", "line", "matches")}
+            for r in rg.search(temp_dir)
+        ]
+        assert len(expected) == 2
+        assert decode_matches(encoded) == expected
+
+        assert decode_matches(RipGrep("absent").search_encoded(temp_dir)) == []
+
+
+def test_search_from_list():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        os.mkdir(os.path.join(temp_dir, "src"))
+        single = os.path.join(temp_dir, "single.txt")
+        nested = os.path.join(temp_dir, "src", "nested.txt")
+        skipped = os.path.join(temp_dir, "skipped.txt")
+        for path in (single, nested, skipped):
+            with open(path, "w") as f:
+                f.write("needle\n")
+
+        list_path = os.path.join(temp_dir, "paths.lst")
+        with open(list_path, "w") as f:
+            f.write(f"# paths to scan\n{single}\n\n{os.path.dirname(nested)}\n")
+            f.write(f"#{skipped}\n")
+
+        results = RipGrep("needle").search_from_list(list_path)
+        assert [r["file"] for r in results] == [single, nested]
+
+        with pytest.raises(SearchIOError):
+            RipGrep("needle").search_from_list(os.path.join(temp_dir, "missing"))
+
+
+def test_search_many():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        os.mkdir(os.path.join(temp_dir, "dir"))
+        first = os.path.join(temp_dir, "first.txt")
+        nested = os.path.join(temp_dir, "dir", "nested.txt")
+        last = os.path.join(temp_dir, "last.txt")
+        for path in (first, nested, last):
+            with open(path, "w") as f:
+                f.write("needle\nhay\n")
+
+        rg = RipGrep("needle")
+        results = rg.search_many([last, os.path.join(temp_dir, "dir"), first])
+        assert [r["file"] for r in results] == [last, nested, first]
+        assert rg.search_many([]) == []
+
+        with pytest.raises(SearchIOError):
+            rg.search_many([first, os.path.join(temp_dir, "missing")])
+
+
+def test_with_ids():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        for name in ("a.txt", "b.txt"):
+            with open(os.path.join(temp_dir, name), "w") as f:
+                f.write("needle\nhay\nneedle\n  needle\n")
+
+        rg = RipGrep("needle", with_ids=True)
+        ids = [r["id"] for r in rg.search(temp_dir)]
+        assert len(ids) == 6
+        assert len(set(ids)) == len(ids)
+        assert [r["id"] for r in rg.search(temp_dir)] == ids
+        fresh = RipGrep("needle", with_ids=True)
+        assert [r["id"] for r in fresh.search(temp_dir)] == ids
+
+        assert "id" not in RipGrep("needle").search(temp_dir)[0]
+
+
+def test_unsearchable_paths():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        rg = RipGrep("needle")
+        missing = os.path.join(temp_dir, "typo.txt")
+        with pytest.raises(FileNotFoundError, match="typo.txt") as excinfo:
+            rg.search(missing)
+        assert isinstance(excinfo.value, PathNotFoundError)
+        assert isinstance(excinfo.value, SearchIOError)
+
+        if hasattr(os, "symlink"):
+            link = os.path.join(temp_dir, "dangling")
+            os.symlink(missing, link)
+            with pytest.raises(SearchIOError, match="Broken symlink") as excinfo:
+                rg.search(link)
+            assert not isinstance(excinfo.value, FileNotFoundError)
+
+        if hasattr(os, "mkfifo"):
+            fifo = os.path.join(temp_dir, "fifo")
+            os.mkfifo(fifo)
+            with pytest.raises(SearchIOError, match="Not a file or directory"):
+                rg.search(fifo)
+
+        # An empty result now always means the search ran and found nothing
+        os.mkdir(os.path.join(temp_dir, "empty"))
+        assert rg.search(os.path.join(temp_dir, "empty")) == []
+
+
+def test_on_error():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        good = os.path.join(temp_dir, "good.txt")
+        with open(good, "w") as f:
+            f.write("needle\n")
+        # A matched line that isn't UTF-8 fails the file's search
+        bad = os.path.join(temp_dir, "bad.txt")
+        with open(bad, "wb") as f:
+            f.write(b"needle \xff\xfe\n")
+
+        for threads in (1, None):
+            rg = RipGrep("needle", on_error="ignore", threads=threads)
+            assert [r["file"] for r in rg.search(temp_dir)] == [good]
+
+            rg = RipGrep("needle", on_error="raise", threads=threads)
+            with pytest.raises(BinaryError):
+                rg.search(temp_dir)
+
+            rg = RipGrep("needle", on_error="collect", threads=threads)
+            collected = rg.search(temp_dir)
+            assert [r["file"] for r in collected["matches"]] == [good]
+            assert [e["file"] for e in collected["errors"]] == [bad]
+            assert "BinaryError" in collected["errors"][0]["error"]
+
+        unreadable = os.path.join(temp_dir, "unreadable.txt")
+        with open(unreadable, "w") as f:
+            f.write("needle\n")
+        os.chmod(unreadable, 0)
+        try:
+            # Root can read the file anyway
+            if not os.access(unreadable, os.R_OK):
+                collected = RipGrep("needle", on_error="collect").search(temp_dir)
+                assert unreadable in [e["file"] for e in collected["errors"]]
+        finally:
+            os.chmod(unreadable, 0o644)
+
+        with pytest.raises(ValueError, match="Invalid on_error"):
+            RipGrep("needle", on_error="explode")
+
+
+def test_on_error_unlistable_directory(tmp_path):
+    (tmp_path / "good.txt").write_text("needle\n")
+    locked = tmp_path / "locked"
+    locked.mkdir()
+    (locked / "hidden.txt").write_text("needle\n")
+    os.chmod(locked, 0)
+    try:
+        # Root can list the directory anyway
+        if os.access(locked, os.R_OK):
+            pytest.skip("directory permissions aren't enforced")
+        for threads in (1, 4):
+            rg = RipGrep("needle", on_error="collect", threads=threads)
+            collected = rg.search(str(tmp_path))
+            assert [r["file"] for r in collected["matches"]] == [
+                str(tmp_path / "good.txt")
+            ]
+            assert [e["file"] for e in collected["errors"]] == [str(locked)]
+            assert "Walk error" in collected["errors"][0]["error"]
+
+            rg = RipGrep("needle", on_error="ignore", threads=threads)
+            assert len(rg.search(str(tmp_path))) == 1
+
+            rg = RipGrep("needle", on_error="raise", threads=threads)
+            with pytest.raises(SearchIOError, match="Walk error") as excinfo:
+                rg.search(str(tmp_path))
+            # Whatever was found before it stopped is kept
+            assert all(
+                r["file"] == str(tmp_path / "good.txt")
+                for r in excinfo.value.partial_results
+            )
+    finally:
+        os.chmod(locked, 0o755)
+
+
+def test_parse_ripgrep_output():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        for name in ("a.txt", "b.txt"):
+            with open(os.path.join(temp_dir, name), "w") as f:
+                f.write("needle\n")
+
+        exit_code, stdout, _ = run_ripgrep(
+            ["--files-with-matches", "--null", "--sort", "path", "needle", temp_dir]
+        )
+        assert exit_code == 0
+        assert parse_ripgrep_output(stdout, "\0") == [
+            os.path.join(temp_dir, "a.txt"),
+            os.path.join(temp_dir, "b.txt"),
+        ]
+
+    assert parse_ripgrep_output("one\ntwo\n") == ["one", "two"]
+    assert parse_ripgrep_output(b"a\0b", "\0") == [b"a", b"b"]
+    assert parse_ripgrep_output("") == []
+
+
+def test_max_count():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        for name in ("a.txt", "b.txt"):
+            with open(os.path.join(temp_dir, name), "w") as f:
+                f.write("needle\nhay\n" * 50)
+        a = os.path.join(temp_dir, "a.txt")
+        b = os.path.join(temp_dir, "b.txt")
+
+        results = RipGrep("needle", max_count=2).search(temp_dir)
+        assert [(r["file"], r["line_number"]) for r in results] == [
+            (a, 1),
+            (a, 3),
+            (b, 1),
+            (b, 3),
+        ]
+        assert RipGrep("needle", max_count=2).count(a) == [{"file": a, "count": 2}]
+
+        # The rest of the file is never read
+        rg = RipGrep("needle", max_count=2, report_lines_scanned=True)
+        assert rg.profile(a)[0]["lines_scanned"] == 3
+
+        # After context is still filled in for the last match
+        rg = RipGrep("needle", max_count=1, after_context=1)
+        assert rg.search(a)[0]["context_after"] == [(2, "hay")]
+
+        # Walked in order, which only stable fixes
+        results = RipGrep("needle", max_total=3, stable=True).search(temp_dir)
+        assert [(r["file"], r["line_number"]) for r in results] == [
+            (a, 1),
+            (a, 3),
+            (a, 5),
+        ]
+        assert len(RipGrep("needle", max_total=3).search(a)) == 3
+
+
+@pytest.mark.skipif(sys.platform == "win32", reason="byte patterns tested on Unix")
+def test_hex_preview():
+    with tempfile.NamedTemporaryFile(mode="wb", suffix=".bin", delete=False) as f:
+        f.write(b"magic \x80\xff marker\n")
+        f.write(b"\x80\xff\n")
+        temp_file = f.name
+
+    try:
+        rg = RipGrep.from_bytes_pattern(b"\x80\xff", hex_preview=True)
+        results = rg.search(temp_file)
+        assert results[0]["hex_preview"] == "67 69 63 20 [80 ff] 20 6d 61 72"
+        # Cut short at the ends of the line, terminator included
+        assert results[1]["hex_preview"] == "[80 ff] 0a"
+
+        preview = results[0]["hex_preview"]
+        matched = preview[preview.index("[") + 1 : preview.index("]")]
+        assert bytes.fromhex(matched) == b"\x80\xff"
+    finally:
+        os.unlink(temp_file)
+
+
+def test_raw_bytes():
+    with tempfile.TemporaryDirectory() as temp_dir:
+        latin1 = os.path.join(temp_dir, "latin1.txt")
+        with open(latin1, "wb") as f:
+            f.write(b"plain needle\n")
+            f.write(b"skip\n")
+            f.write(b"caf\xe9 needle\r\n")
+
+        with pytest.raises(BinaryError):
+            RipGrep("needle").search(latin1)
+
+        rg = RipGrep("needle", raw_bytes=True)
+        for results in (rg.search(latin1), rg.search(temp_dir)):
+            assert [(r["file"], r["line_number"], r["line"]) for r in results] == [
+                (latin1, 1, b"plain needle"),
+                (latin1, 3, b"caf\xe9 needle"),
+            ]
+        # Offsets are into the original bytes
+        assert rg.search(latin1)[1]["matches"] == [{"start": 5, "end": 11}]
+
+
+def test_captures_max_groups():
+    content = "x abcdefghij y\n"
+    pattern = "".join(f"({c})" for c in "abcdefghij")
+
+    results = RipGrep(pattern, captures=True).search_text(content)
+    assert results[0]["captures"] == list("abcdefghij")
+
+    results = RipGrep(pattern, captures=True, max_groups=3).search_text(content)
+    assert results[0]["captures"] == ["a", "b", "c"]
+
+    # Groups that don't take part in the match are None
+    results = RipGrep("(x)|(z)", captures=True).search_text(content)
+    assert results[0]["captures"] == ["x", None]
+    assert "captures" not in RipGrep(pattern).search_text(content)[0]
+
+
+def test_label(tmp_path):
+    (tmp_path / "a.txt").write_text("needle one\nneedle two\n")
+
+    ours = RipGrep("needle", label="ours").search(str(tmp_path))
+    theirs = RipGrep("needle", label="theirs").search(str(tmp_path))
+    assert len(ours) == len(theirs) == 2
+    assert all(m["label"] == "ours" for m in ours)
+    assert all(m["label"] == "theirs" for m in theirs)
+    assert "label" not in RipGrep("needle").search(str(tmp_path))[0]
+
+
+def test_overlapping():
+    def spans(**options):
+        [result] = RipGrep("aa", **options).search_text("aaaa\n")
+        return [(m["start"], m["end"]) for m in result["matches"]]
+
+    assert spans() == [(0, 2), (2, 4)]
+    assert spans(overlapping=True) == [(0, 2), (1, 3), (2, 4)]
+
+
+def test_estimate(tmp_path):
+    (tmp_path / "a.py").write_text("one\ntwo\n")
+    (tmp_path / "b.py").write_text("three\n")
+    (tmp_path / "c.txt").write_text("skipped by the glob\n")
+    (tmp_path / "sub").mkdir()
+    (tmp_path / "sub" / "d.py").write_text("four\n")
+
+    rg = RipGrep(".", include_globs=["*.py"])
+    estimate = rg.estimate(str(tmp_path))
+    searched = {m["file"] for m in rg.search(str(tmp_path))}
+    assert estimate["file_count"] == len(searched) == 3
+    assert estimate["total_bytes"] == sum(os.path.getsize(f) for f in searched)
+
+
+@pytest.mark.skipif(shutil.which("patch") is None, reason="needs patch")
+def test_diff_preview(tmp_path):
+    lines = [f"line {i}\n" for i in range(20)]
+    lines[2] = "call old_name(1)\n"
+    lines[15] = "old_name = 2\n"
+    original = "".join(lines) + "last old_name"
+    target = tmp_path / "code.py"
+    target.write_text(original)
+    (tmp_path / "other.py").write_text("nothing here\n")
+
+    rg = RipGrep(r"old_(\w+)")
+    diffs = rg.diff_preview(str(tmp_path), "new_$1")
+    assert list(diffs) == [str(target)]
+    # Previewing leaves the file alone
+    assert target.read_text() == original
+
+    copy = tmp_path / "copy.py"
+    copy.write_text(original)
+    subprocess.run(
+        ["patch", str(copy)], input=diffs[str(target)], text=True, check=True
+    )
+    assert copy.read_text() == original.replace("old_name", "new_name")
+
+
+def test_replace_preview(tmp_path):
+    original = "key = 1\nfoo_bar and foo_baz\nnothing\n"
+    target = tmp_path / "config.txt"
+    target.write_text(original)
+
+    rg = RipGrep(r"foo_(?P<suffix>\w+)")
+    results = rg.replace(str(target), "qux_${suffix}")
+    assert [(r["line_number"], r["line"], r["replaced"]) for r in results] == [
+        (2, "foo_bar and foo_baz", "qux_bar and qux_baz")
+    ]
+    assert RipGrep(r"(\w+) = (\d)").replace(str(target), "$2 = $1")[0][
+        "replaced"
+    ] == "1 = key"
+    # Preview only
+    assert target.read_text() == original
+
+
+def test_replace_in_files(tmp_path):
+    (tmp_path / "a.txt").write_text("foo_bar and foo_baz\nnothing\n")
+    (tmp_path / "b.txt").write_text("foo_qux\n")
+    untouched = tmp_path / "c.txt"
+    untouched.write_text("nothing here\n")
+    os.chmod(tmp_path / "a.txt", 0o640)
+    mtime = untouched.stat().st_mtime_ns
+
+    rg = RipGrep(r"foo_(?P<suffix>\w+)")
+    assert rg.replace_in_files(str(tmp_path), "new_${suffix}") == (2, 3)
+    assert (tmp_path / "a.txt").read_text() == "new_bar and new_baz\nnothing\n"
+    assert (tmp_path / "b.txt").read_text() == "new_qux\n"
+    assert (tmp_path / "a.txt").stat().st_mode & 0o777 == 0o640
+    # Files without matches are never rewritten
+    assert untouched.stat().st_mtime_ns == mtime
+    assert not [p for p in os.listdir(tmp_path) if p.endswith(".sup-tmp")]
+
+    assert rg.replace_in_files(str(tmp_path), "x") == (0, 0)
+
+
+def test_replace_in_files_follows_search_options(tmp_path):
+    target = tmp_path / "pets.txt"
+    target.write_text("cat and category\ncatalog\nno match here\n")
+
+    rg = RipGrep("cat", word=True)
+    assert rg.replace_in_files(str(target), "dog") == (1, 1)
+    assert target.read_text() == "dog and category\ncatalog\nno match here\n"
+
+    # The reported lines don't match, so there's nothing in them to replace
+    assert RipGrep("dog", invert_match=True).replace_in_files(str(target), "x") == (
+        0,
+        0,
+    )
+    assert target.read_text() == "dog and category\ncatalog\nno match here\n"
+
+    assert RipGrep("cat", max_count=1).replace_in_files(str(target), "c") == (1, 1)
+    assert target.read_text() == "dog and cegory\ncatalog\nno match here\n"
+
+
+def test_search_stream(tmp_path):
+    for name in "abc":
+        (tmp_path / f"{name}.txt").write_text("needle\nhay\nneedle\n")
+
+    seen = []
+    rg = RipGrep("needle")
+    assert rg.search_stream(str(tmp_path), lambda m: seen.append(m) or True) == 6
+    assert sorted(m["file"] for m in seen) == sorted(
+        m["file"] for m in rg.search(str(tmp_path))
+    )
+
+    # A falsy return stops after that match
+    assert rg.search_stream(str(tmp_path), lambda m: False) == 1
+
+    def fail(match):
+        raise KeyError("from callback")
+
+    with pytest.raises(KeyError, match="from callback"):
+        rg.search_stream(str(tmp_path), fail)
+
+
+def test_multiline():
+    content = "start\ndef handler(\n    request,\n    response,\n):\nend\n"
+
+    [result] = RipGrep(r"def handler\(.*?\):", multiline=True).search_text(content)
+    assert result["line_number"] == 2
+    assert result["line"] == "def handler(\n    request,\n    response,\n):"
+    assert RipGrep(r"def handler\(.*?\):").search_text(content) == []
+
+
+def test_unicode_and_dot_controls():
+    content = "café au lait\n"
+    [result] = RipGrep(r"\w+").search_text(content)
+    assert result["matches"][0] == {"start": 0, "end": 5}
+    [result] = RipGrep(r"\w+", unicode=False).search_text(content)
+    assert result["matches"][0] == {"start": 0, "end": 3}
+
+    assert len(RipGrep(r"\p{Greek}").search_text("λ\n")) == 1
+    with pytest.raises(ValueError, match="Unicode"):
+        RipGrep(r"\p{Greek}", unicode=False)
+
+    content = "start\nend\n"
+    assert len(RipGrep("start.end", multiline=True).search_text(content)) == 1
+    rg = RipGrep("start.end", multiline=True, dot_matches_new_line=False)
+    assert rg.search_text(content) == []
+
+    config = RipGrep("x").config()
+    assert config["unicode"] is True
+    assert config["dot_matches_new_line"] is None
+
+
+def test_search_json_string(tmp_path):
+    (tmp_path / "a.txt").write_text("first needle\nhay\nneedle needle\n")
+    (tmp_path / "b.txt").write_text("café needle\n")
+
+    options = {"stable": True, "line_ending": True, "after_context": 1}
+    text = search_json_string("needle", str(tmp_path), **options)
+    expected = search("needle", str(tmp_path), **options)
+    # JSON has no tuples, so context pairs come back as lists
+    for m in expected:
+        m["context_after"] = [list(pair) for pair in m["context_after"]]
+    assert json.loads(text) == expected
+
+
+def test_types(tmp_path):
+    for name in ("main.rs", "app.py", "skip_me.py", "notes.md"):
+        (tmp_path / name).write_text("needle\n")
+
+    def found(**options):
+        results = RipGrep("needle", **options).search(str(tmp_path))
+        return sorted(os.path.basename(m["file"]) for m in results)
+
+    assert found(types=["rust"]) == ["main.rs"]
+    assert found(types=["rust", "py"]) == ["app.py", "main.rs", "skip_me.py"]
+    assert found(types_not=["py"]) == ["main.rs", "notes.md"]
+    # Globs narrow the typed set further
+    assert found(types=["py"], exclude_globs=["skip_*"]) == ["app.py"]
+
+    with pytest.raises(ValueError, match="no_such_type"):
+        RipGrep("needle", types=["no_such_type"])
+
+
+@pytest.mark.parametrize("threads", [1, None])
+def test_search_with_stats(tmp_path, threads):
+    (tmp_path / "a.txt").write_text("needle\nneedle\n")
+    (tmp_path / "b.txt").write_text("needle\n")
+    (tmp_path / "c.txt").write_text("hay\n")
+    (tmp_path / "d.txt").write_text("more hay\n")
+
+    rg = RipGrep("needle", threads=threads, stable=True)
+    result = rg.search_with_stats(str(tmp_path))
+    assert result["matches"] == rg.search(str(tmp_path))
+    assert result["stats"] == {
+        "total_matches": 3,
+        "files_with_matches": 2,
+        "files_searched": 4,
+    }
+
+
+def test_files_with_matches(tmp_path):
+    (tmp_path / "a.txt").write_text("needle\n" * 1000)
+    (tmp_path / "b.txt").write_text("hay\n")
+    (tmp_path / "sub").mkdir()
+    (tmp_path / "sub" / "c.txt").write_text("hay\nneedle\n")
+
+    rg = RipGrep("needle", stable=True)
+    files = rg.files_with_matches(str(tmp_path))
+    assert sorted(files) == sorted({m["file"] for m in rg.search(str(tmp_path))})
+    assert len(files) == 2
+    assert rg.files_with_matches(str(tmp_path / "b.txt")) == []
+
+
+def test_max_filesize(tmp_path):
+    (tmp_path / "small.txt").write_text("needle\n")
+    big = tmp_path / "big.txt"
+    big.write_text("needle\n" + "x" * 2000 + "\n")
+
+    rg = RipGrep("needle", max_filesize=1000)
+    results = rg.search(str(tmp_path))
+    assert [os.path.basename(m["file"]) for m in results] == ["small.txt"]
+    assert rg.search(str(big)) == []
+    assert len(RipGrep("needle").search(str(big))) == 1
+
+    with pytest.raises(LimitExceeded, match="max_filesize") as excinfo:
+        RipGrep("needle", max_filesize=1000, max_filesize_error=True).search(str(big))
+    assert excinfo.value.partial_results == []
+
+
+def test_line_number_off(tmp_path):
+    (tmp_path / "a.txt").write_text("hay\nneedle\nhay\nneedle\n")
+
+    assert RipGrep("needle").config()["line_number"] is True
+    results = RipGrep("needle", line_number=False).search(str(tmp_path))
+    assert [(m["line_number"], m["line"]) for m in results] == [
+        (None, "needle"),
+        (None, "needle"),
+    ]
+    # Limits kept by line still see the numbers
+    results = RipGrep("needle", line_number=False, max_lines=2).search(str(tmp_path))
+    assert results == []
+
+    with pytest.raises(ValueError, match="after_context"):
+        RipGrep("needle", line_number=False, after_context=1)
+
+
+@pytest.mark.skipif(not hasattr(os, "symlink"), reason="requires symlinks")
+@pytest.mark.parametrize("threads", [1, None])
+def test_follow_links(tmp_path, threads):
+    root = tmp_path / "root"
+    (root / "sub").mkdir(parents=True)
+    (root / "sub" / "a.txt").write_text("needle\n")
+    (tmp_path / "elsewhere").mkdir()
+    (tmp_path / "elsewhere" / "b.txt").write_text("needle\n")
+    os.symlink(tmp_path / "elsewhere", root / "linked")
+    # Points back up its own tree
+    os.symlink(root, root / "sub" / "loop")
+
+    def found(**options):
+        results = RipGrep("needle", threads=threads, **options).search(str(root))
+        return sorted(os.path.relpath(m["file"], root) for m in results)
+
+    assert found() == [os.path.join("sub", "a.txt")]
+    # Each file once, despite the loop
+    assert found(follow_links=True) == [
+        os.path.join("linked", "b.txt"),
+        os.path.join("sub", "a.txt"),
+    ]
+
+    # The loop is reported as on_error says
+    rg = RipGrep("needle", threads=threads, follow_links=True, on_error="collect")
+    errors = rg.search(str(root))["errors"]
+    assert [os.path.basename(e["file"]) for e in errors] == ["loop"]